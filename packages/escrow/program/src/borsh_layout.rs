@@ -0,0 +1,544 @@
+use std::io::{Error, ErrorKind, Result, Write};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::pubkey::Pubkey;
+
+use crate::{
+    instruction::EscrowInstruction,
+    state::{check_discriminator, Config, Escrow, TreasuryAccount},
+};
+
+// The Pack layouts can't be derived on the types themselves: options are stored at a fixed
+// width, the escrow stores its version instead of `is_initialized`, state is prefixed with a
+// discriminator and instruction tags don't follow the order of the variants.
+// Thus the derives live on the layouts below, which mirror the Pack layouts field by field.
+
+/// Option that is always stored at the full width of its value, zeroed if there is none.
+/// Borsh's own `Option` omits the value instead.
+#[derive(BorshSerialize, BorshDeserialize)]
+struct FixedOption<T> {
+    is_some: bool,
+    value: T,
+}
+
+impl<T: Default> From<Option<T>> for FixedOption<T> {
+    fn from(option: Option<T>) -> Self {
+        match option {
+            Some(value) => Self {
+                is_some: true,
+                value,
+            },
+            None => Self {
+                is_some: false,
+                value: T::default(),
+            },
+        }
+    }
+}
+
+impl<T> From<FixedOption<T>> for Option<T> {
+    fn from(option: FixedOption<T>) -> Self {
+        if option.is_some {
+            Some(option.value)
+        } else {
+            None
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+// -----------------
+// Escrow
+// -----------------
+
+/// Only the current layout, escrows of older versions have to be unpacked via
+/// [solana_program::program_pack::Pack]
+#[derive(BorshSerialize, BorshDeserialize)]
+struct EscrowLayout {
+    discriminator: [u8; 8],
+    version: u8,
+    initializer_pubkey: Pubkey,
+    tmp_token_account_pubkey: Pubkey,
+    initializer_token_to_receive_account_pubkey: Pubkey,
+    expected_amount: u64,
+    bump_seed: u8,
+    expires_at: FixedOption<i64>,
+    allowed_taker: FixedOption<Pubkey>,
+    mint_x_pubkey: Pubkey,
+    mint_y_pubkey: Pubkey,
+    mint_x_decimals: u8,
+    mint_y_decimals: u8,
+}
+
+impl BorshSerialize for Escrow {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let (discriminator, version) = if self.is_initialized {
+            (Escrow::DISCRIMINATOR, Escrow::VERSION)
+        } else {
+            ([0; 8], 0)
+        };
+        EscrowLayout {
+            discriminator,
+            version,
+            initializer_pubkey: self.initializer_pubkey,
+            tmp_token_account_pubkey: self.tmp_token_account_pubkey,
+            initializer_token_to_receive_account_pubkey: self
+                .initializer_token_to_receive_account_pubkey,
+            expected_amount: self.expected_amount,
+            bump_seed: self.bump_seed,
+            expires_at: self.expires_at.into(),
+            allowed_taker: self.allowed_taker.into(),
+            mint_x_pubkey: self.mint_x_pubkey,
+            mint_y_pubkey: self.mint_y_pubkey,
+            mint_x_decimals: self.mint_x_decimals,
+            mint_y_decimals: self.mint_y_decimals,
+        }
+        .serialize(writer)
+    }
+}
+
+impl BorshDeserialize for Escrow {
+    fn deserialize(buf: &mut &[u8]) -> Result<Self> {
+        let layout = EscrowLayout::deserialize(buf)?;
+        let is_initialized = match layout.version {
+            0 => false,
+            Escrow::VERSION => true,
+            _ => return Err(invalid_data("Unsupported escrow version")),
+        };
+        check_discriminator(
+            &layout.discriminator,
+            &Escrow::DISCRIMINATOR,
+            is_initialized,
+        )
+        .map_err(|_| invalid_data("Invalid escrow discriminator"))?;
+
+        Ok(Escrow {
+            is_initialized,
+            initializer_pubkey: layout.initializer_pubkey,
+            tmp_token_account_pubkey: layout.tmp_token_account_pubkey,
+            initializer_token_to_receive_account_pubkey: layout
+                .initializer_token_to_receive_account_pubkey,
+            expected_amount: layout.expected_amount,
+            bump_seed: layout.bump_seed,
+            expires_at: layout.expires_at.into(),
+            allowed_taker: layout.allowed_taker.into(),
+            mint_x_pubkey: layout.mint_x_pubkey,
+            mint_y_pubkey: layout.mint_y_pubkey,
+            mint_x_decimals: layout.mint_x_decimals,
+            mint_y_decimals: layout.mint_y_decimals,
+        })
+    }
+}
+
+// -----------------
+// Config
+// -----------------
+
+#[derive(BorshSerialize, BorshDeserialize)]
+struct ConfigLayout {
+    discriminator: [u8; 8],
+    is_initialized: bool,
+    admin_pubkey: Pubkey,
+    fee_basis_points: u16,
+    treasury_pubkey: Pubkey,
+    bump_seed: u8,
+    treasury_accounts: [TreasuryAccountLayout; Config::MAX_TREASURY_ACCOUNTS],
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Default)]
+struct TreasuryAccountLayout {
+    mint_pubkey: Pubkey,
+    token_account_pubkey: Pubkey,
+}
+
+impl BorshSerialize for Config {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
+        ConfigLayout {
+            discriminator: if self.is_initialized {
+                Config::DISCRIMINATOR
+            } else {
+                [0; 8]
+            },
+            is_initialized: self.is_initialized,
+            admin_pubkey: self.admin_pubkey,
+            fee_basis_points: self.fee_basis_points,
+            treasury_pubkey: self.treasury_pubkey,
+            bump_seed: self.bump_seed,
+            treasury_accounts: self.treasury_accounts.map(|treasury_account| {
+                TreasuryAccountLayout {
+                    mint_pubkey: treasury_account.mint_pubkey,
+                    token_account_pubkey: treasury_account.token_account_pubkey,
+                }
+            }),
+        }
+        .serialize(writer)
+    }
+}
+
+impl BorshDeserialize for Config {
+    fn deserialize(buf: &mut &[u8]) -> Result<Self> {
+        let layout = ConfigLayout::deserialize(buf)?;
+        check_discriminator(
+            &layout.discriminator,
+            &Config::DISCRIMINATOR,
+            layout.is_initialized,
+        )
+        .map_err(|_| invalid_data("Invalid config discriminator"))?;
+
+        Ok(Config {
+            is_initialized: layout.is_initialized,
+            admin_pubkey: layout.admin_pubkey,
+            fee_basis_points: layout.fee_basis_points,
+            treasury_pubkey: layout.treasury_pubkey,
+            bump_seed: layout.bump_seed,
+            treasury_accounts: layout
+                .treasury_accounts
+                .map(|treasury_account| TreasuryAccount {
+                    mint_pubkey: treasury_account.mint_pubkey,
+                    token_account_pubkey: treasury_account.token_account_pubkey,
+                }),
+        })
+    }
+}
+
+// -----------------
+// Instructions
+// -----------------
+
+#[derive(BorshSerialize, BorshDeserialize)]
+struct InitEscrowLayout {
+    amount: u64,
+    expires_at: FixedOption<i64>,
+    allowed_taker: FixedOption<Pubkey>,
+}
+
+impl InitEscrowLayout {
+    fn new(amount: u64, expires_at: Option<i64>, allowed_taker: Option<Pubkey>) -> Self {
+        Self {
+            amount,
+            expires_at: expires_at.into(),
+            allowed_taker: allowed_taker.into(),
+        }
+    }
+}
+
+/// Variants in the order of their tags, see [EscrowInstruction::unpack]
+#[derive(BorshSerialize, BorshDeserialize)]
+enum EscrowInstructionLayout {
+    InitEscrow(InitEscrowLayout),
+    Exchange {
+        amount: u64,
+    },
+    CancelEscrow,
+    PartialExchange {
+        amount: u64,
+        expected_amount: u64,
+    },
+    ReclaimExpired,
+    InitConfig {
+        fee_basis_points: u16,
+    },
+    UpdateConfig {
+        fee_basis_points: u16,
+    },
+    InitEscrowPda(InitEscrowLayout),
+    InitEscrowWithDeposit {
+        deposit: u64,
+        init: InitEscrowLayout,
+    },
+    InitEscrowOfferingLamports {
+        deposit: u64,
+        init: InitEscrowLayout,
+    },
+    InitEscrowExpectingLamports(InitEscrowLayout),
+    MigrateEscrow,
+    SetTreasuryAccount,
+}
+
+impl BorshSerialize for EscrowInstruction {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
+        use EscrowInstructionLayout as Layout;
+
+        let layout = match *self {
+            EscrowInstruction::InitEscrow {
+                amount,
+                expires_at,
+                allowed_taker,
+            } => Layout::InitEscrow(InitEscrowLayout::new(amount, expires_at, allowed_taker)),
+            EscrowInstruction::Exchange { amount } => Layout::Exchange { amount },
+            EscrowInstruction::CancelEscrow => Layout::CancelEscrow,
+            EscrowInstruction::PartialExchange {
+                amount,
+                expected_amount,
+            } => Layout::PartialExchange {
+                amount,
+                expected_amount,
+            },
+            EscrowInstruction::ReclaimExpired => Layout::ReclaimExpired,
+            EscrowInstruction::InitConfig { fee_basis_points } => {
+                Layout::InitConfig { fee_basis_points }
+            }
+            EscrowInstruction::UpdateConfig { fee_basis_points } => {
+                Layout::UpdateConfig { fee_basis_points }
+            }
+            EscrowInstruction::InitEscrowPda {
+                amount,
+                expires_at,
+                allowed_taker,
+            } => Layout::InitEscrowPda(InitEscrowLayout::new(amount, expires_at, allowed_taker)),
+            EscrowInstruction::InitEscrowWithDeposit {
+                deposit,
+                amount,
+                expires_at,
+                allowed_taker,
+            } => Layout::InitEscrowWithDeposit {
+                deposit,
+                init: InitEscrowLayout::new(amount, expires_at, allowed_taker),
+            },
+            EscrowInstruction::InitEscrowOfferingLamports {
+                deposit,
+                amount,
+                expires_at,
+                allowed_taker,
+            } => Layout::InitEscrowOfferingLamports {
+                deposit,
+                init: InitEscrowLayout::new(amount, expires_at, allowed_taker),
+            },
+            EscrowInstruction::InitEscrowExpectingLamports {
+                amount,
+                expires_at,
+                allowed_taker,
+            } => Layout::InitEscrowExpectingLamports(InitEscrowLayout::new(
+                amount,
+                expires_at,
+                allowed_taker,
+            )),
+            EscrowInstruction::MigrateEscrow => Layout::MigrateEscrow,
+            EscrowInstruction::SetTreasuryAccount => Layout::SetTreasuryAccount,
+        };
+        layout.serialize(writer)
+    }
+}
+
+impl BorshDeserialize for EscrowInstruction {
+    fn deserialize(buf: &mut &[u8]) -> Result<Self> {
+        use EscrowInstructionLayout as Layout;
+
+        let instruction = match Layout::deserialize(buf)? {
+            Layout::InitEscrow(init) => EscrowInstruction::InitEscrow {
+                amount: init.amount,
+                expires_at: init.expires_at.into(),
+                allowed_taker: init.allowed_taker.into(),
+            },
+            Layout::Exchange { amount } => EscrowInstruction::Exchange { amount },
+            Layout::CancelEscrow => EscrowInstruction::CancelEscrow,
+            Layout::PartialExchange {
+                amount,
+                expected_amount,
+            } => EscrowInstruction::PartialExchange {
+                amount,
+                expected_amount,
+            },
+            Layout::ReclaimExpired => EscrowInstruction::ReclaimExpired,
+            Layout::InitConfig { fee_basis_points } => {
+                EscrowInstruction::InitConfig { fee_basis_points }
+            }
+            Layout::UpdateConfig { fee_basis_points } => {
+                EscrowInstruction::UpdateConfig { fee_basis_points }
+            }
+            Layout::InitEscrowPda(init) => EscrowInstruction::InitEscrowPda {
+                amount: init.amount,
+                expires_at: init.expires_at.into(),
+                allowed_taker: init.allowed_taker.into(),
+            },
+            Layout::InitEscrowWithDeposit { deposit, init } => {
+                EscrowInstruction::InitEscrowWithDeposit {
+                    deposit,
+                    amount: init.amount,
+                    expires_at: init.expires_at.into(),
+                    allowed_taker: init.allowed_taker.into(),
+                }
+            }
+            Layout::InitEscrowOfferingLamports { deposit, init } => {
+                EscrowInstruction::InitEscrowOfferingLamports {
+                    deposit,
+                    amount: init.amount,
+                    expires_at: init.expires_at.into(),
+                    allowed_taker: init.allowed_taker.into(),
+                }
+            }
+            Layout::InitEscrowExpectingLamports(init) => {
+                EscrowInstruction::InitEscrowExpectingLamports {
+                    amount: init.amount,
+                    expires_at: init.expires_at.into(),
+                    allowed_taker: init.allowed_taker.into(),
+                }
+            }
+            Layout::MigrateEscrow => EscrowInstruction::MigrateEscrow,
+            Layout::SetTreasuryAccount => EscrowInstruction::SetTreasuryAccount,
+        };
+        Ok(instruction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::EscrowV0;
+    use solana_program::program_pack::Pack;
+
+    fn init_escrow_args() -> [(u64, Option<i64>, Option<Pubkey>); 3] {
+        [
+            (10, None, None),
+            (10, Some(1_634_400_000), None),
+            (10, Some(-1), Some(Pubkey::new_unique())),
+        ]
+    }
+
+    fn instructions() -> Vec<EscrowInstruction> {
+        let mut instructions = vec![
+            EscrowInstruction::Exchange { amount: 42 },
+            EscrowInstruction::CancelEscrow,
+            EscrowInstruction::PartialExchange {
+                amount: 42,
+                expected_amount: 7,
+            },
+            EscrowInstruction::ReclaimExpired,
+            EscrowInstruction::InitConfig {
+                fee_basis_points: 30,
+            },
+            EscrowInstruction::UpdateConfig {
+                fee_basis_points: 10_000,
+            },
+            EscrowInstruction::MigrateEscrow,
+            EscrowInstruction::SetTreasuryAccount,
+        ];
+        for (amount, expires_at, allowed_taker) in init_escrow_args() {
+            instructions.extend([
+                EscrowInstruction::InitEscrow {
+                    amount,
+                    expires_at,
+                    allowed_taker,
+                },
+                EscrowInstruction::InitEscrowPda {
+                    amount,
+                    expires_at,
+                    allowed_taker,
+                },
+                EscrowInstruction::InitEscrowWithDeposit {
+                    deposit: 5000,
+                    amount,
+                    expires_at,
+                    allowed_taker,
+                },
+                EscrowInstruction::InitEscrowOfferingLamports {
+                    deposit: 5000,
+                    amount,
+                    expires_at,
+                    allowed_taker,
+                },
+                EscrowInstruction::InitEscrowExpectingLamports {
+                    amount,
+                    expires_at,
+                    allowed_taker,
+                },
+            ]);
+        }
+        instructions
+    }
+
+    fn escrow() -> Escrow {
+        Escrow {
+            is_initialized: true,
+            initializer_pubkey: Pubkey::new_unique(),
+            tmp_token_account_pubkey: Pubkey::new_unique(),
+            initializer_token_to_receive_account_pubkey: Pubkey::new_unique(),
+            expected_amount: 1000,
+            bump_seed: 254,
+            expires_at: Some(1_634_400_000),
+            allowed_taker: Some(Pubkey::new_unique()),
+            mint_x_pubkey: Pubkey::new_unique(),
+            mint_y_pubkey: Escrow::LAMPORTS_MINT,
+            mint_x_decimals: 6,
+            mint_y_decimals: Escrow::LAMPORTS_DECIMALS,
+        }
+    }
+
+    #[test]
+    fn instructions_match_pack() {
+        for instruction in instructions() {
+            let packed = instruction.pack();
+            assert_eq!(
+                instruction.try_to_vec().unwrap(),
+                packed,
+                "{:?}",
+                instruction
+            );
+            assert_eq!(
+                EscrowInstruction::try_from_slice(&packed).unwrap(),
+                instruction
+            );
+        }
+    }
+
+    #[test]
+    fn escrow_matches_pack() {
+        let uninitialized = Escrow {
+            is_initialized: false,
+            expires_at: None,
+            allowed_taker: None,
+            ..escrow()
+        };
+        for escrow in [escrow(), uninitialized] {
+            let mut packed = [0u8; Escrow::LEN];
+            escrow.pack_into_slice(&mut packed);
+            assert_eq!(escrow.try_to_vec().unwrap(), packed);
+
+            let deserialized = Escrow::try_from_slice(&packed).unwrap();
+            assert_eq!(format!("{:?}", deserialized), format!("{:?}", escrow));
+        }
+    }
+
+    #[test]
+    fn escrow_invalid_data() {
+        let mut packed = [0u8; Escrow::LEN];
+        escrow().pack_into_slice(&mut packed);
+
+        let mut data = packed;
+        data[..8].copy_from_slice(&Config::DISCRIMINATOR);
+        assert!(Escrow::try_from_slice(&data).is_err(), "discriminator");
+
+        let mut data = packed;
+        data[8] = Escrow::VERSION + 1;
+        assert!(Escrow::try_from_slice(&data).is_err(), "version");
+
+        assert!(
+            Escrow::try_from_slice(&packed[..EscrowV0::LEN]).is_err(),
+            "v0 size"
+        );
+    }
+
+    #[test]
+    fn config_matches_pack() {
+        let mut config = Config {
+            is_initialized: true,
+            admin_pubkey: Pubkey::new_unique(),
+            fee_basis_points: 30,
+            treasury_pubkey: Pubkey::new_unique(),
+            bump_seed: 255,
+            ..Config::default()
+        };
+        config
+            .set_treasury_account(&Pubkey::new_unique(), &Pubkey::new_unique())
+            .unwrap();
+        let mut packed = [0u8; Config::LEN];
+        config.pack_into_slice(&mut packed);
+        assert_eq!(config.try_to_vec().unwrap(), packed);
+
+        let deserialized = Config::try_from_slice(&packed).unwrap();
+        assert_eq!(format!("{:?}", deserialized), format!("{:?}", config));
+    }
+}
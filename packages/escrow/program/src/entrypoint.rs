@@ -1,6 +1,7 @@
-use crate::processor::Processor;
+use crate::{error::EscrowError, processor::Processor};
 use solana_program::{
-    account_info::AccountInfo, entrypoint, entrypoint::ProgramResult, msg, pubkey::Pubkey,
+    account_info::AccountInfo, entrypoint, entrypoint::ProgramResult, msg,
+    program_error::PrintProgramError, pubkey::Pubkey,
 };
 
 entrypoint!(process_instruction);
@@ -19,5 +20,10 @@ fn process_instruction(
         accounts.len(),
         instruction_data
     );
-    Processor::process(program_id, accounts, instruction_data)
+    if let Err(error) = Processor::process(program_id, accounts, instruction_data) {
+        // log the name of the error as well, custom errors only surface as codes to clients
+        error.print::<EscrowError>();
+        return Err(error);
+    }
+    Ok(())
 }
@@ -1,23 +1,119 @@
-use solana_program::program_error::ProgramError;
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+use solana_program::{
+    decode_error::DecodeError,
+    msg,
+    program_error::{PrintProgramError, ProgramError},
+};
 use thiserror::Error;
 
-#[derive(Error, Debug, Copy, Clone)]
+/// Errors of the escrow program, surfaced as [ProgramError::Custom] with the code of the variant.
+/// Clients match on these codes, thus variants keep their code forever: never renumber or reuse
+/// a code, only append new ones.
+#[derive(Error, Debug, Copy, Clone, Eq, PartialEq, FromPrimitive)]
 pub enum EscrowError {
     /// Invalid instruction
     #[error("Invalid Instruction")]
-    InvalidInstruction,
+    InvalidInstruction = 0,
 
     /// Not Rent Exempt
     #[error("Not Rent Exempt")]
-    NotRentExempt,
+    NotRentExempt = 1,
 
     /// Expected Amount Mismatch
     #[error("Expected Amount Mismatch")]
-    ExpectedAmountMismatch,
+    ExpectedAmountMismatch = 2,
 
     /// Amount Overflow
     #[error("Amount Overflow")]
-    AmountOverflow,
+    AmountOverflow = 3,
+
+    /// Invalid Fill Amount
+    #[error("Invalid Fill Amount")]
+    InvalidFillAmount = 4,
+
+    /// Escrow Expired
+    #[error("Escrow Expired")]
+    EscrowExpired = 5,
+
+    /// Escrow Not Expired
+    #[error("Escrow Not Expired")]
+    EscrowNotExpired = 6,
+
+    /// Unauthorized Taker
+    #[error("Unauthorized Taker")]
+    UnauthorizedTaker = 7,
+
+    /// Unauthorized Admin
+    #[error("Unauthorized Admin")]
+    UnauthorizedAdmin = 8,
+
+    /// Invalid Fee
+    #[error("Invalid Fee")]
+    InvalidFee = 9,
+
+    /// Invalid Config Account
+    #[error("Invalid Config Account")]
+    InvalidConfigAccount = 10,
+
+    /// Invalid Treasury Account
+    #[error("Invalid Treasury Account")]
+    InvalidTreasuryAccount = 11,
+
+    /// Mint Mismatch
+    #[error("Mint Mismatch")]
+    MintMismatch = 12,
+
+    /// Invalid Token Program
+    #[error("Invalid Token Program")]
+    InvalidTokenProgram = 13,
+
+    /// Invalid PDA Account
+    #[error("Invalid PDA Account")]
+    InvalidPdaAccount = 14,
+
+    /// Invalid Escrow Owner
+    #[error("Invalid Escrow Owner")]
+    InvalidEscrowOwner = 15,
+
+    /// Invalid Escrow Size
+    #[error("Invalid Escrow Size")]
+    InvalidEscrowSize = 16,
+
+    /// Invalid Escrow Address
+    #[error("Invalid Escrow Address")]
+    InvalidEscrowAddress = 17,
+
+    /// Invalid Vault Address
+    #[error("Invalid Vault Address")]
+    InvalidVaultAddress = 18,
+
+    /// Invalid Account Discriminator
+    #[error("Invalid Account Discriminator")]
+    InvalidAccountDiscriminator = 19,
+
+    /// Escrow Account Mismatch
+    #[error("Escrow Account Mismatch")]
+    EscrowAccountMismatch = 20,
+
+    /// Invalid Token Account Owner
+    #[error("Invalid Token Account Owner")]
+    InvalidTokenAccountOwner = 21,
+
+    /// Invalid Program Data Account
+    #[error("Invalid Program Data Account")]
+    InvalidProgramDataAccount = 22,
+
+    /// Treasury Accounts Full
+    #[error("Treasury Accounts Full")]
+    TreasuryAccountsFull = 23,
+}
+
+impl EscrowError {
+    /// The error with the given code, see [ProgramError::Custom]
+    pub fn from_code(code: u32) -> Option<Self> {
+        Self::from_u32(code)
+    }
 }
 
 impl From<EscrowError> for ProgramError {
@@ -25,3 +121,66 @@ impl From<EscrowError> for ProgramError {
         ProgramError::Custom(e as u32)
     }
 }
+
+impl<T> DecodeError<T> for EscrowError {
+    fn type_of() -> &'static str {
+        "EscrowError"
+    }
+}
+
+impl PrintProgramError for EscrowError {
+    fn print<E>(&self)
+    where
+        E: 'static + std::error::Error + DecodeError<E> + PrintProgramError + FromPrimitive,
+    {
+        msg!("Error: {}", self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_are_stable() {
+        let codes = [
+            (EscrowError::InvalidInstruction, 0),
+            (EscrowError::NotRentExempt, 1),
+            (EscrowError::ExpectedAmountMismatch, 2),
+            (EscrowError::AmountOverflow, 3),
+            (EscrowError::InvalidFillAmount, 4),
+            (EscrowError::EscrowExpired, 5),
+            (EscrowError::EscrowNotExpired, 6),
+            (EscrowError::UnauthorizedTaker, 7),
+            (EscrowError::UnauthorizedAdmin, 8),
+            (EscrowError::InvalidFee, 9),
+            (EscrowError::InvalidConfigAccount, 10),
+            (EscrowError::InvalidTreasuryAccount, 11),
+            (EscrowError::MintMismatch, 12),
+            (EscrowError::InvalidTokenProgram, 13),
+            (EscrowError::InvalidPdaAccount, 14),
+            (EscrowError::InvalidEscrowOwner, 15),
+            (EscrowError::InvalidEscrowSize, 16),
+            (EscrowError::InvalidEscrowAddress, 17),
+            (EscrowError::InvalidVaultAddress, 18),
+            (EscrowError::InvalidAccountDiscriminator, 19),
+            (EscrowError::EscrowAccountMismatch, 20),
+            (EscrowError::InvalidTokenAccountOwner, 21),
+            (EscrowError::InvalidProgramDataAccount, 22),
+            (EscrowError::TreasuryAccountsFull, 23),
+        ];
+        for (error, code) in codes {
+            assert_eq!(ProgramError::from(error), ProgramError::Custom(code));
+            assert_eq!(EscrowError::from_code(code), Some(error));
+        }
+        assert_eq!(EscrowError::from_code(codes.len() as u32), None);
+    }
+
+    #[test]
+    fn decode_custom_error() {
+        assert_eq!(
+            <EscrowError as DecodeError<EscrowError>>::decode_custom_error_to_enum(12),
+            Some(EscrowError::MintMismatch)
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use arrayref::{array_ref, array_refs, mut_array_refs};
+use solana_program::{msg, program_error::ProgramError, pubkey::Pubkey};
+
+/// Marks the log lines carrying an event, followed by the base64 encoded event
+pub const EVENT_LOG_PREFIX: &str = "EscrowEvent: ";
+
+/// Prefix the runtime puts in front of every line logged by a program
+const PROGRAM_LOG_PREFIX: &str = "Program log: ";
+
+/// Events the processor logs once an escrow changed, for indexers to follow the escrows of the
+/// program without polling accounts.
+///
+/// This runtime has no `sol_log_data` yet, thus events are logged with [msg!] as
+/// [EVENT_LOG_PREFIX] followed by the base64 encoded [EscrowEvent::pack].
+/// Amounts are in the smallest unit of the mint, either may be [crate::state::Escrow::LAMPORTS_MINT].
+/// Logs of failed transactions may still contain events, only those of successful ones count.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EscrowEvent {
+    /// Alice opened an escrow
+    Created {
+        escrow: Pubkey,
+        initializer: Pubkey,
+        mint_x: Pubkey,
+        mint_y: Pubkey,
+        /// X Alice deposited
+        amount_x: u64,
+        /// Y Alice expects in return
+        expected_amount: u64,
+    },
+
+    /// Bob took all or part of an escrow
+    Exchanged {
+        escrow: Pubkey,
+        initializer: Pubkey,
+        taker: Pubkey,
+        mint_x: Pubkey,
+        mint_y: Pubkey,
+        /// X sent to Bob, before any transfer fee of the mint
+        amount_x: u64,
+        /// Y paid by Bob, including the fee
+        amount_y: u64,
+        /// Part of `amount_y` which went to the treasury
+        fee: u64,
+        /// Y the escrow still expects, the escrow is closed once nothing remains
+        remaining_amount: u64,
+    },
+
+    /// The deposit was returned to Alice and the escrow closed
+    Cancelled {
+        escrow: Pubkey,
+        initializer: Pubkey,
+        mint_x: Pubkey,
+        /// X returned to Alice
+        amount_x: u64,
+        /// Determines if the escrow expired and was reclaimed rather than cancelled by Alice
+        reclaimed: bool,
+    },
+}
+
+impl EscrowEvent {
+    const CREATED_LEN: usize = 1 + 32 * 4 + 8 * 2;
+    const EXCHANGED_LEN: usize = 1 + 32 * 5 + 8 * 4;
+    const CANCELLED_LEN: usize = 1 + 32 * 3 + 8 + 1;
+
+    /// Logs the event
+    pub fn emit(&self) {
+        msg!("{}{}", EVENT_LOG_PREFIX, base64::encode(self.pack()));
+    }
+
+    /// Decodes the event logged in `line`, if it is an event line.
+    /// Accepts the line as the program logged it as well as prefixed with "Program log: " the way
+    /// it shows up in the log messages of a transaction.
+    pub fn from_log(line: &str) -> Option<Result<Self, ProgramError>> {
+        let line = line.strip_prefix(PROGRAM_LOG_PREFIX).unwrap_or(line);
+        let encoded = line.strip_prefix(EVENT_LOG_PREFIX)?;
+        Some(
+            base64::decode(encoded)
+                .map_err(|_| ProgramError::InvalidArgument)
+                .and_then(|data| Self::unpack(&data)),
+        )
+    }
+
+    /// Decodes all events in the log messages of a transaction, in the order they were logged
+    pub fn from_logs<'a>(
+        lines: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Vec<Self>, ProgramError> {
+        lines.into_iter().filter_map(Self::from_log).collect()
+    }
+
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        let event = match (input.first(), input.len()) {
+            (Some(0), Self::CREATED_LEN) => {
+                let src = array_ref![input, 0, EscrowEvent::CREATED_LEN];
+                let (_, escrow, initializer, mint_x, mint_y, amount_x, expected_amount) =
+                    array_refs![src, 1, 32, 32, 32, 32, 8, 8];
+                EscrowEvent::Created {
+                    escrow: Pubkey::new_from_array(*escrow),
+                    initializer: Pubkey::new_from_array(*initializer),
+                    mint_x: Pubkey::new_from_array(*mint_x),
+                    mint_y: Pubkey::new_from_array(*mint_y),
+                    amount_x: u64::from_le_bytes(*amount_x),
+                    expected_amount: u64::from_le_bytes(*expected_amount),
+                }
+            }
+            (Some(1), Self::EXCHANGED_LEN) => {
+                let src = array_ref![input, 0, EscrowEvent::EXCHANGED_LEN];
+                let (
+                    _,
+                    escrow,
+                    initializer,
+                    taker,
+                    mint_x,
+                    mint_y,
+                    amount_x,
+                    amount_y,
+                    fee,
+                    remaining_amount,
+                ) = array_refs![src, 1, 32, 32, 32, 32, 32, 8, 8, 8, 8];
+                EscrowEvent::Exchanged {
+                    escrow: Pubkey::new_from_array(*escrow),
+                    initializer: Pubkey::new_from_array(*initializer),
+                    taker: Pubkey::new_from_array(*taker),
+                    mint_x: Pubkey::new_from_array(*mint_x),
+                    mint_y: Pubkey::new_from_array(*mint_y),
+                    amount_x: u64::from_le_bytes(*amount_x),
+                    amount_y: u64::from_le_bytes(*amount_y),
+                    fee: u64::from_le_bytes(*fee),
+                    remaining_amount: u64::from_le_bytes(*remaining_amount),
+                }
+            }
+            (Some(2), Self::CANCELLED_LEN) => {
+                let src = array_ref![input, 0, EscrowEvent::CANCELLED_LEN];
+                let (_, escrow, initializer, mint_x, amount_x, reclaimed) =
+                    array_refs![src, 1, 32, 32, 32, 8, 1];
+                EscrowEvent::Cancelled {
+                    escrow: Pubkey::new_from_array(*escrow),
+                    initializer: Pubkey::new_from_array(*initializer),
+                    mint_x: Pubkey::new_from_array(*mint_x),
+                    amount_x: u64::from_le_bytes(*amount_x),
+                    reclaimed: match reclaimed {
+                        [0] => false,
+                        [1] => true,
+                        _ => return Err(ProgramError::InvalidArgument),
+                    },
+                }
+            }
+            _ => return Err(ProgramError::InvalidArgument),
+        };
+        Ok(event)
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        match self {
+            EscrowEvent::Created {
+                escrow,
+                initializer,
+                mint_x,
+                mint_y,
+                amount_x,
+                expected_amount,
+            } => {
+                let mut dst = [0_u8; Self::CREATED_LEN];
+                let (
+                    tag_dst,
+                    escrow_dst,
+                    initializer_dst,
+                    mint_x_dst,
+                    mint_y_dst,
+                    amount_x_dst,
+                    expected_amount_dst,
+                ) = mut_array_refs![&mut dst, 1, 32, 32, 32, 32, 8, 8];
+
+                *tag_dst = [0_u8];
+                escrow_dst.copy_from_slice(escrow.as_ref());
+                initializer_dst.copy_from_slice(initializer.as_ref());
+                mint_x_dst.copy_from_slice(mint_x.as_ref());
+                mint_y_dst.copy_from_slice(mint_y.as_ref());
+                *amount_x_dst = amount_x.to_le_bytes();
+                *expected_amount_dst = expected_amount.to_le_bytes();
+                dst.to_vec()
+            }
+            EscrowEvent::Exchanged {
+                escrow,
+                initializer,
+                taker,
+                mint_x,
+                mint_y,
+                amount_x,
+                amount_y,
+                fee,
+                remaining_amount,
+            } => {
+                let mut dst = [0_u8; Self::EXCHANGED_LEN];
+                let (
+                    tag_dst,
+                    escrow_dst,
+                    initializer_dst,
+                    taker_dst,
+                    mint_x_dst,
+                    mint_y_dst,
+                    amount_x_dst,
+                    amount_y_dst,
+                    fee_dst,
+                    remaining_amount_dst,
+                ) = mut_array_refs![&mut dst, 1, 32, 32, 32, 32, 32, 8, 8, 8, 8];
+
+                *tag_dst = [1_u8];
+                escrow_dst.copy_from_slice(escrow.as_ref());
+                initializer_dst.copy_from_slice(initializer.as_ref());
+                taker_dst.copy_from_slice(taker.as_ref());
+                mint_x_dst.copy_from_slice(mint_x.as_ref());
+                mint_y_dst.copy_from_slice(mint_y.as_ref());
+                *amount_x_dst = amount_x.to_le_bytes();
+                *amount_y_dst = amount_y.to_le_bytes();
+                *fee_dst = fee.to_le_bytes();
+                *remaining_amount_dst = remaining_amount.to_le_bytes();
+                dst.to_vec()
+            }
+            EscrowEvent::Cancelled {
+                escrow,
+                initializer,
+                mint_x,
+                amount_x,
+                reclaimed,
+            } => {
+                let mut dst = [0_u8; Self::CANCELLED_LEN];
+                let (tag_dst, escrow_dst, initializer_dst, mint_x_dst, amount_x_dst, reclaimed_dst) =
+                    mut_array_refs![&mut dst, 1, 32, 32, 32, 8, 1];
+
+                *tag_dst = [2_u8];
+                escrow_dst.copy_from_slice(escrow.as_ref());
+                initializer_dst.copy_from_slice(initializer.as_ref());
+                mint_x_dst.copy_from_slice(mint_x.as_ref());
+                *amount_x_dst = amount_x.to_le_bytes();
+                *reclaimed_dst = [*reclaimed as u8];
+                dst.to_vec()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events() -> Vec<EscrowEvent> {
+        vec![
+            EscrowEvent::Created {
+                escrow: Pubkey::new_unique(),
+                initializer: Pubkey::new_unique(),
+                mint_x: Pubkey::new_unique(),
+                mint_y: Pubkey::new_unique(),
+                amount_x: 5000,
+                expected_amount: 1000,
+            },
+            EscrowEvent::Exchanged {
+                escrow: Pubkey::new_unique(),
+                initializer: Pubkey::new_unique(),
+                taker: Pubkey::new_unique(),
+                mint_x: Pubkey::new_unique(),
+                mint_y: Pubkey::new_unique(),
+                amount_x: 2500,
+                amount_y: 500,
+                fee: 5,
+                remaining_amount: 500,
+            },
+            EscrowEvent::Cancelled {
+                escrow: Pubkey::new_unique(),
+                initializer: Pubkey::new_unique(),
+                mint_x: Pubkey::new_unique(),
+                amount_x: u64::MAX,
+                reclaimed: true,
+            },
+        ]
+    }
+
+    #[test]
+    fn pack_unpack() {
+        for event in events() {
+            assert_eq!(EscrowEvent::unpack(&event.pack()), Ok(event));
+        }
+    }
+
+    #[test]
+    fn unpack_invalid() {
+        for event in events() {
+            let packed = event.pack();
+            assert_eq!(
+                EscrowEvent::unpack(&packed[..packed.len() - 1]),
+                Err(ProgramError::InvalidArgument)
+            );
+        }
+        let mut cancelled = events()[2].pack();
+        *cancelled.last_mut().unwrap() = 2;
+        assert_eq!(
+            EscrowEvent::unpack(&cancelled),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert_eq!(EscrowEvent::unpack(&[]), Err(ProgramError::InvalidArgument));
+    }
+
+    #[test]
+    fn decode_logs() {
+        let events = events();
+        let line = |event: &EscrowEvent| {
+            format!(
+                "{}{}{}",
+                PROGRAM_LOG_PREFIX,
+                EVENT_LOG_PREFIX,
+                base64::encode(event.pack())
+            )
+        };
+        let logs = vec![
+            "Program 11111111111111111111111111111111 invoke [1]".to_string(),
+            "Program log: Instruction: Exchange".to_string(),
+            line(&events[1]),
+            // as logged by the program, without the runtime's prefix
+            line(&events[2])[PROGRAM_LOG_PREFIX.len()..].to_string(),
+            "Program 11111111111111111111111111111111 success".to_string(),
+        ];
+
+        assert_eq!(
+            EscrowEvent::from_logs(logs.iter().map(String::as_str)),
+            Ok(events[1..].to_vec())
+        );
+        assert_eq!(
+            EscrowEvent::from_log("Program log: EscrowEvent: not base64!"),
+            Some(Err(ProgramError::InvalidArgument))
+        );
+    }
+}
@@ -1,7 +1,15 @@
 use std::convert::TryInto;
 
-use crate::error::EscrowError::InvalidInstruction;
-use solana_program::program_error::ProgramError;
+use crate::{
+    error::EscrowError::InvalidInstruction,
+    state::{Config, Escrow},
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program,
+};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum EscrowInstruction {
@@ -17,11 +25,19 @@ pub enum EscrowInstruction {
     ///    through (Alice Token Y) `[]` indicates _readonly_
     /// 3. `[writable]` The escrow account, it will hold all necessary info about the trade.
     ///     Program will write escrow info to it
-    /// 4. `[]` The token program
+    /// 4. `[]` The token program, either the classic token program or Token-2022, whose mints may
+    ///    only use the transfer fee extension
+    /// 5. `[]` The mint of token X, its decimals are stored with the escrow
+    /// 6. `[]` The mint of token Y, its decimals are stored with the escrow
     InitEscrow {
         /// The amount of token Y that Alice wants to receive for her (Alice Token X)
         /// Provided via `instruction_data` instead of via an account
         amount: u64,
+        /// Unix timestamp at which the escrow expires, after that it can no longer be taken and
+        /// anyone may return Alice's tokens to her via [EscrowInstruction::ReclaimExpired]
+        expires_at: Option<i64>,
+        /// The only taker (Bob) allowed to accept the trade, anyone may if not provided
+        allowed_taker: Option<Pubkey>,
     },
 
     /// Accepts a trade
@@ -30,19 +46,258 @@ pub enum EscrowInstruction {
     /// Accounts expected:
     ///
     /// 0. `[signer]` The account of the person taking the trade (Bob)
-    /// 1. `[writable]` The taker's token account for the token they send (Bob Token Y)
+    /// 1. `[writable]` The taker's token account for the token they send (Bob Token Y), the taker
+    ///    himself when Alice expects lamports
     /// 2. `[writable]` The taker's token account for the token they will receive should the trade
-    ///    go through (Bob Token X)
-    /// 3. `[writable]` The PDA's temp token account to get tokens from and eventually close
+    ///    go through (Bob Token X), any account when Alice offers lamports
+    /// 3. `[writable]` The PDA's temp token account to get tokens from and eventually close, the
+    ///    escrow account when Alice offers lamports
     /// 4. `[writable]` The initializer's main account to send their rent fees to
     /// 5. `[writable]` The initializer's token account that will receive tokens
     /// 6. `[writable]` The escrow account holding the escrow info
     /// 7. `[]` The token program
-    /// 8. `[]` The PDA account
+    /// 8. `[]` The PDA account, the one shared by all escrows for v0 escrows
+    /// 9. `[]` The config account holding the protocol fee, its PDA address even if no config was
+    ///    initialized yet
+    /// 10. `[writable]` The treasury's token account that receives the fee (Treasury Token Y) as
+    ///     set via [EscrowInstruction::SetTreasuryAccount], the treasury itself when Alice expects
+    ///     lamports. Any account while there is no config or no treasury token account of token Y
+    /// 11. `[writable]` The mint of token X, any account when Alice offers lamports. Only has to
+    ///     be writable if it charges transfer fees (Token-2022)
+    /// 12. `[]` The mint of token Y, any account when Alice expects lamports
+    /// 13. `[]` The system program, only when Alice expects lamports
+    ///
+    /// When token X charges a transfer fee, the amount Bob expects is what he receives after
+    /// the fee was withheld.
+    ///
+    /// Accounts 9 and 10 were added along with the protocol fee, clients built for the earlier
+    /// layout have to pass them. No fee is charged as long as the config wasn't initialized, nor
+    /// in tokens the treasury has no token account for.
     Exchange {
         /// The amount of token X Bob expects to be paid in the other token
         amount: u64,
     },
+
+    /// Accepts part of a trade, Bob sends some of the token Y Alice expects and receives a
+    /// proportional amount of her token X.
+    /// The escrow stays open until all of the expected token Y was sent.
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// Same as [EscrowInstruction::Exchange]
+    PartialExchange {
+        /// The amount of token Y Bob sends, at most the amount the escrow still expects
+        amount: u64,
+        /// The amount of token X Bob expects to be paid in return, rounded down
+        expected_amount: u64,
+    },
+
+    /// Cancels a trade that no taker has accepted yet, returning the tokens held in the temp
+    /// token account to the initializer and closing the escrow
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the person who initialized the escrow (Alice), also
+    ///    receives the rent fees of the closed accounts
+    /// 1. `[writable]` The PDA's temp token account to return tokens from and eventually close
+    /// 2. `[writable]` The initializer's token account that gets the tokens back (Alice Token X)
+    /// 3. `[writable]` The escrow account holding the escrow info
+    /// 4. `[]` The token program
+    /// 5. `[]` The PDA account, the one shared by all escrows for v0 escrows
+    /// 6. `[writable]` The mint of token X, only has to be writable if it charges transfer fees
+    ///
+    /// When Alice offered lamports they are returned to her main account along with the rent,
+    /// account 1 is the escrow account and accounts 2, 4, 5 and 6 are ignored.
+    CancelEscrow,
+
+    /// Returns the tokens of an expired trade to the initializer and closes the escrow.
+    /// Anyone may submit this, it doesn't require the initializer's signature.
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[writable]` The initializer's main account (Alice) to send the rent fees of the closed
+    ///    accounts to
+    /// 1. `[writable]` The PDA's temp token account to return tokens from and eventually close
+    /// 2. `[writable]` The initializer's token account that gets the tokens back (Alice Token X),
+    ///    has to be owned by the initializer
+    /// 3. `[writable]` The escrow account holding the escrow info
+    /// 4. `[]` The token program
+    /// 5. `[]` The PDA account, the one shared by all escrows for v0 escrows
+    /// 6. `[writable]` The mint of token X, only has to be writable if it charges transfer fees
+    ///
+    /// When Alice offered lamports they are returned to her main account along with the rent,
+    /// account 1 is the escrow account and accounts 2, 4, 5 and 6 are ignored.
+    ReclaimExpired,
+
+    /// Creates the program wide config account at the PDA derived via `Config::PDA_SEED`
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The admin who will be allowed to update the config, pays for the
+    ///    config account
+    /// 1. `[writable]` The config account (PDA)
+    /// 2. `[]` The treasury which owns the token accounts that receive fees
+    /// 3. `[]` The system program
+    /// 4. `[]` The ProgramData account of the escrow program, the admin has to be the program's
+    ///    upgrade authority so that nobody else can claim the config after the deployment
+    InitConfig {
+        /// The fee skimmed from each exchange, 100 = 1%
+        fee_basis_points: u16,
+    },
+
+    /// Updates the program wide config
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The current admin
+    /// 1. `[writable]` The config account (PDA)
+    /// 2. `[]` The admin who will be allowed to update the config from now on
+    /// 3. `[]` The treasury which owns the token accounts that receive fees
+    ///
+    /// When the treasury changes, the treasury token accounts of the previous one are removed
+    /// and have to be set again via [EscrowInstruction::SetTreasuryAccount].
+    UpdateConfig {
+        /// The fee skimmed from each exchange, 100 = 1%
+        fee_basis_points: u16,
+    },
+
+    /// Same as [EscrowInstruction::InitEscrow], except that the program creates the escrow
+    /// account itself at the PDA derived via `Escrow::ACCOUNT_SEED`, the initializer and the temp
+    /// token account, so the client doesn't have to create and fund it beforehand
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the person initializing the escrow (Alice), pays for
+    ///    the escrow account
+    /// 1. `[writable]` Temporary token account (Alice Token X) that should be created prior to
+    ///    this instruction and owned by the initializer (Alice)
+    /// 2. `[]` The initializer's token account for the token they will receive should the trade go
+    ///    through (Alice Token Y)
+    /// 3. `[writable]` The escrow account (PDA), must not exist yet
+    /// 4. `[]` The token program
+    /// 5. `[]` The mint of token X
+    /// 6. `[]` The mint of token Y
+    /// 7. `[]` The system program
+    InitEscrowPda {
+        /// The amount of token Y that Alice wants to receive for her (Alice Token X)
+        amount: u64,
+        /// Unix timestamp at which the escrow expires
+        expires_at: Option<i64>,
+        /// The only taker (Bob) allowed to accept the trade, anyone may if not provided
+        allowed_taker: Option<Pubkey>,
+    },
+
+    /// Starts the trade in a single instruction by creating the temp token account (vault) owned
+    /// by the PDA and transferring the deposit from the initializer's token account into it
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the person initializing the escrow (Alice), pays for
+    ///    the vault
+    /// 1. `[writable]` The vault (PDA derived via `Escrow::VAULT_SEED` and the escrow account),
+    ///    must not exist yet
+    /// 2. `[]` The initializer's token account for the token they will receive should the trade go
+    ///    through (Alice Token Y)
+    /// 3. `[writable]` The escrow account, it will hold all necessary info about the trade
+    /// 4. `[]` The token program
+    /// 5. `[writable]` The initializer's token account to take the deposit from (Alice Token X)
+    /// 6. `[]` The mint of token X
+    /// 7. `[]` The PDA account which will own the vault
+    /// 8. `[]` The system program
+    /// 9. `[]` The rent sysvar
+    /// 10. `[]` The mint of token Y
+    InitEscrowWithDeposit {
+        /// The amount of token X that Alice offers and deposits into the vault
+        deposit: u64,
+        /// The amount of token Y that Alice wants to receive for her (Alice Token X)
+        amount: u64,
+        /// Unix timestamp at which the escrow expires
+        expires_at: Option<i64>,
+        /// The only taker (Bob) allowed to accept the trade, anyone may if not provided
+        allowed_taker: Option<Pubkey>,
+    },
+
+    /// Starts a trade in which Alice offers native lamports instead of token X.
+    /// The lamports are held by the escrow account itself on top of its rent exempt minimum.
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The account of the person initializing the escrow (Alice), pays
+    ///    the deposit
+    /// 1. `[writable]` The escrow account, it will hold all necessary info about the trade and
+    ///    the deposit
+    /// 2. `[]` The initializer's token account for the token they will receive should the trade go
+    ///    through (Alice Token Y)
+    /// 3. `[]` The system program
+    /// 4. `[]` The mint of token Y
+    InitEscrowOfferingLamports {
+        /// The amount of lamports that Alice offers
+        deposit: u64,
+        /// The amount of token Y that Alice wants to receive for her lamports
+        amount: u64,
+        /// Unix timestamp at which the escrow expires
+        expires_at: Option<i64>,
+        /// The only taker (Bob) allowed to accept the trade, anyone may if not provided
+        allowed_taker: Option<Pubkey>,
+    },
+
+    /// Starts a trade in which Alice expects native lamports instead of token Y
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// Same as [EscrowInstruction::InitEscrow], except that account 2 is the account which
+    /// receives the lamports should the trade go through and there's no mint of token Y
+    InitEscrowExpectingLamports {
+        /// The amount of lamports that Alice wants to receive for her (Alice Token X)
+        amount: u64,
+        /// Unix timestamp at which the escrow expires
+        expires_at: Option<i64>,
+        /// The only taker (Bob) allowed to accept the trade, anyone may if not provided
+        allowed_taker: Option<Pubkey>,
+    },
+
+    /// Moves an escrow of the first release, see [crate::state::EscrowV0], into a new escrow
+    /// account in the current layout. The trade itself is unchanged.
+    /// The runtime doesn't let programs grow accounts, thus the escrow is copied rather than
+    /// rewritten in place. The authority over the tmp token account moves from the PDA shared
+    /// by all v0 escrows to the PDA of the new escrow and the old escrow account is closed.
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The initializer of the escrow (Alice), gets the rent of the old
+    ///    escrow account
+    /// 1. `[writable]` The escrow account in the v0 layout
+    /// 2. `[writable]` The new escrow account, created by Alice beforehand the same way as for
+    ///    [EscrowInstruction::InitEscrow]
+    /// 3. `[writable]` The PDA's temp token account (Alice Token X)
+    /// 4. `[]` The initializer's token account that will receive tokens (Alice Token Y)
+    /// 5. `[]` The token program
+    /// 6. `[]` The PDA which v0 escrows share, derived from
+    ///    [crate::state::Escrow::PDA_SEED] alone
+    /// 7. `[]` The mint of token X
+    /// 8. `[]` The mint of token Y
+    MigrateEscrow,
+
+    /// Sets the treasury token account which receives the fees paid in its mint, replacing the
+    /// previous one of that mint
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The admin
+    /// 1. `[writable]` The config account (PDA)
+    /// 2. `[]` The token account receiving the fees, owned by the treasury
+    SetTreasuryAccount,
 }
 
 impl EscrowInstruction {
@@ -51,17 +306,83 @@ impl EscrowInstruction {
         let (instruction_tag, rest): (&u8, &[u8]) =
             input.split_first().ok_or(InvalidInstruction)?;
         let escrow = match instruction_tag {
-            0 => Self::InitEscrow {
+            0 => {
+                let (amount, expires_at, allowed_taker) = Self::unpack_init_escrow(rest)?;
+                Self::InitEscrow {
+                    amount,
+                    expires_at,
+                    allowed_taker,
+                }
+            }
+            1 => Self::Exchange {
                 amount: Self::unpack_amount(rest)?,
             },
-            1 => Self::Exchange {
+            2 => Self::CancelEscrow,
+            3 => Self::PartialExchange {
                 amount: Self::unpack_amount(rest)?,
+                expected_amount: Self::unpack_amount(rest.get(8..).ok_or(InvalidInstruction)?)?,
+            },
+            4 => Self::ReclaimExpired,
+            5 => Self::InitConfig {
+                fee_basis_points: Self::unpack_fee_basis_points(rest)?,
             },
+            6 => Self::UpdateConfig {
+                fee_basis_points: Self::unpack_fee_basis_points(rest)?,
+            },
+            7 => {
+                let (amount, expires_at, allowed_taker) = Self::unpack_init_escrow(rest)?;
+                Self::InitEscrowPda {
+                    amount,
+                    expires_at,
+                    allowed_taker,
+                }
+            }
+            8 => {
+                let deposit = Self::unpack_amount(rest)?;
+                let (amount, expires_at, allowed_taker) = Self::unpack_init_escrow(&rest[8..])?;
+                Self::InitEscrowWithDeposit {
+                    deposit,
+                    amount,
+                    expires_at,
+                    allowed_taker,
+                }
+            }
+            9 => {
+                let deposit = Self::unpack_amount(rest)?;
+                let (amount, expires_at, allowed_taker) = Self::unpack_init_escrow(&rest[8..])?;
+                Self::InitEscrowOfferingLamports {
+                    deposit,
+                    amount,
+                    expires_at,
+                    allowed_taker,
+                }
+            }
+            10 => {
+                let (amount, expires_at, allowed_taker) = Self::unpack_init_escrow(rest)?;
+                Self::InitEscrowExpectingLamports {
+                    amount,
+                    expires_at,
+                    allowed_taker,
+                }
+            }
+            11 => Self::MigrateEscrow,
+            12 => Self::SetTreasuryAccount,
             _ => return Err(InvalidInstruction.into()),
         };
         Ok(escrow)
     }
 
+    #[allow(clippy::type_complexity)]
+    fn unpack_init_escrow(
+        input: &[u8],
+    ) -> Result<(u64, Option<i64>, Option<Pubkey>), ProgramError> {
+        let amount = Self::unpack_amount(input)?;
+        // Clients that don't support expiry or designated takers omit them entirely
+        let expires_at = Self::unpack_expires_at(&input[8..])?;
+        let allowed_taker = Self::unpack_allowed_taker(input.get(17..).unwrap_or(&[]))?;
+        Ok((amount, expires_at, allowed_taker))
+    }
+
     fn unpack_amount(input: &[u8]) -> Result<u64, ProgramError> {
         let amount: u64 = input
             .get(..8)
@@ -71,30 +392,356 @@ impl EscrowInstruction {
         Ok(amount)
     }
 
+    fn unpack_fee_basis_points(input: &[u8]) -> Result<u16, ProgramError> {
+        let fee_basis_points: u16 = input
+            .get(..2)
+            .and_then(|slice| slice.try_into().ok())
+            .map(u16::from_le_bytes)
+            .ok_or(InvalidInstruction)?;
+        Ok(fee_basis_points)
+    }
+
+    fn unpack_expires_at(input: &[u8]) -> Result<Option<i64>, ProgramError> {
+        match input.split_first() {
+            None | Some((0, _)) => Ok(None),
+            Some((1, rest)) => {
+                let expires_at: i64 = rest
+                    .get(..8)
+                    .and_then(|slice| slice.try_into().ok())
+                    .map(i64::from_le_bytes)
+                    .ok_or(InvalidInstruction)?;
+                Ok(Some(expires_at))
+            }
+            _ => Err(InvalidInstruction.into()),
+        }
+    }
+
+    fn unpack_allowed_taker(input: &[u8]) -> Result<Option<Pubkey>, ProgramError> {
+        match input.split_first() {
+            None | Some((0, _)) => Ok(None),
+            Some((1, rest)) => {
+                let allowed_taker = rest.get(..32).map(Pubkey::new).ok_or(InvalidInstruction)?;
+                Ok(Some(allowed_taker))
+            }
+            _ => Err(InvalidInstruction.into()),
+        }
+    }
+
     pub fn pack(&self) -> Vec<u8> {
         use arrayref::mut_array_refs;
 
         match self {
-            EscrowInstruction::InitEscrow { amount } => {
+            EscrowInstruction::InitEscrow {
+                amount,
+                expires_at,
+                allowed_taker,
+            } => Self::pack_init_escrow(0, *amount, *expires_at, *allowed_taker),
+            EscrowInstruction::InitEscrowPda {
+                amount,
+                expires_at,
+                allowed_taker,
+            } => Self::pack_init_escrow(7, *amount, *expires_at, *allowed_taker),
+            EscrowInstruction::InitEscrowWithDeposit {
+                deposit,
+                amount,
+                expires_at,
+                allowed_taker,
+            } => {
+                let init_escrow = Self::pack_init_escrow(8, *amount, *expires_at, *allowed_taker);
+                let (tag, rest) = init_escrow.split_at(1);
+                [tag, &deposit.to_le_bytes(), rest].concat()
+            }
+            EscrowInstruction::InitEscrowOfferingLamports {
+                deposit,
+                amount,
+                expires_at,
+                allowed_taker,
+            } => {
+                let init_escrow = Self::pack_init_escrow(9, *amount, *expires_at, *allowed_taker);
+                let (tag, rest) = init_escrow.split_at(1);
+                [tag, &deposit.to_le_bytes(), rest].concat()
+            }
+            EscrowInstruction::InitEscrowExpectingLamports {
+                amount,
+                expires_at,
+                allowed_taker,
+            } => Self::pack_init_escrow(10, *amount, *expires_at, *allowed_taker),
+            EscrowInstruction::Exchange { amount } => {
                 const LEN: usize = 1 + 8;
                 let mut dst = [0_u8; LEN];
                 let (tag_dst, amount_dst) = mut_array_refs![&mut dst, 1, 8];
 
-                *tag_dst = [0_u8];
+                *tag_dst = [1_u8];
                 *amount_dst = amount.to_le_bytes();
                 dst.to_vec()
             }
-            EscrowInstruction::Exchange { amount } => {
-                const LEN: usize = 1 + 8;
+            EscrowInstruction::CancelEscrow => vec![2_u8],
+            EscrowInstruction::ReclaimExpired => vec![4_u8],
+            EscrowInstruction::MigrateEscrow => vec![11_u8],
+            EscrowInstruction::SetTreasuryAccount => vec![12_u8],
+            EscrowInstruction::InitConfig { fee_basis_points } => {
+                [&[5_u8][..], &fee_basis_points.to_le_bytes()].concat()
+            }
+            EscrowInstruction::UpdateConfig { fee_basis_points } => {
+                [&[6_u8][..], &fee_basis_points.to_le_bytes()].concat()
+            }
+            EscrowInstruction::PartialExchange {
+                amount,
+                expected_amount,
+            } => {
+                const LEN: usize = 1 + 8 + 8;
                 let mut dst = [0_u8; LEN];
-                let (tag_dst, amount_dst) = mut_array_refs![&mut dst, 1, 8];
+                let (tag_dst, amount_dst, expected_amount_dst) = mut_array_refs![&mut dst, 1, 8, 8];
 
-                *tag_dst = [1_u8];
+                *tag_dst = [3_u8];
                 *amount_dst = amount.to_le_bytes();
+                *expected_amount_dst = expected_amount.to_le_bytes();
                 dst.to_vec()
             }
         }
     }
+
+    fn pack_init_escrow(
+        tag: u8,
+        amount: u64,
+        expires_at: Option<i64>,
+        allowed_taker: Option<Pubkey>,
+    ) -> Vec<u8> {
+        use arrayref::mut_array_refs;
+
+        const LEN: usize = 1 + 8 + 1 + 8 + 1 + 32;
+        let mut dst = [0_u8; LEN];
+        let (
+            tag_dst,
+            amount_dst,
+            has_expires_at_dst,
+            expires_at_dst,
+            has_allowed_taker_dst,
+            allowed_taker_dst,
+        ) = mut_array_refs![&mut dst, 1, 8, 1, 8, 1, 32];
+
+        *tag_dst = [tag];
+        *amount_dst = amount.to_le_bytes();
+        if let Some(expires_at) = expires_at {
+            *has_expires_at_dst = [1_u8];
+            *expires_at_dst = expires_at.to_le_bytes();
+        }
+        if let Some(allowed_taker) = allowed_taker {
+            *has_allowed_taker_dst = [1_u8];
+            allowed_taker_dst.copy_from_slice(allowed_taker.as_ref());
+        }
+        dst.to_vec()
+    }
+}
+
+// -----------------
+// Instruction Builders
+// -----------------
+
+/// Creates an [EscrowInstruction::InitEscrow] instruction, Alice has to sign it.
+/// Pass [Escrow::LAMPORTS_MINT] as `mint_y` to create an
+/// [EscrowInstruction::InitEscrowExpectingLamports] instruction instead, in which case
+/// `token_to_receive_account` is the account receiving the lamports.
+#[allow(clippy::too_many_arguments)]
+pub fn init_escrow(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    tmp_token_account: &Pubkey,
+    token_to_receive_account: &Pubkey,
+    escrow_account: &Pubkey,
+    token_program_id: &Pubkey,
+    mint_x: &Pubkey,
+    mint_y: &Pubkey,
+    amount: u64,
+    expires_at: Option<i64>,
+    allowed_taker: Option<Pubkey>,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*initializer, true),
+        AccountMeta::new(*tmp_token_account, false),
+        AccountMeta::new_readonly(*token_to_receive_account, false),
+        AccountMeta::new(*escrow_account, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*mint_x, false),
+    ];
+    let data = if *mint_y == Escrow::LAMPORTS_MINT {
+        EscrowInstruction::InitEscrowExpectingLamports {
+            amount,
+            expires_at,
+            allowed_taker,
+        }
+    } else {
+        accounts.push(AccountMeta::new_readonly(*mint_y, false));
+        EscrowInstruction::InitEscrow {
+            amount,
+            expires_at,
+            allowed_taker,
+        }
+    }
+    .pack();
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    }
+}
+
+/// Creates an [EscrowInstruction::Exchange] instruction, Bob has to sign it.
+/// The PDA and the config account are derived from the `program_id` and the `escrow_account`.
+/// When Alice expects lamports `takers_sending_account` is Bob himself and `treasury_account`
+/// the treasury, when she offers lamports `pdas_tmp_token_account` is the escrow account.
+#[allow(clippy::too_many_arguments)]
+pub fn exchange(
+    program_id: &Pubkey,
+    taker: &Pubkey,
+    takers_sending_account: &Pubkey,
+    takers_receiving_account: &Pubkey,
+    pdas_tmp_token_account: &Pubkey,
+    initializer: &Pubkey,
+    initializers_token_to_receive_account: &Pubkey,
+    escrow_account: &Pubkey,
+    token_program_id: &Pubkey,
+    treasury_account: &Pubkey,
+    mint_x: &Pubkey,
+    mint_y: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let (pda, _) =
+        Pubkey::find_program_address(&[Escrow::PDA_SEED, escrow_account.as_ref()], program_id);
+    exchange_with_pda(
+        program_id,
+        taker,
+        takers_sending_account,
+        takers_receiving_account,
+        pdas_tmp_token_account,
+        initializer,
+        initializers_token_to_receive_account,
+        escrow_account,
+        token_program_id,
+        &pda,
+        treasury_account,
+        mint_x,
+        mint_y,
+        amount,
+    )
+}
+
+/// Same as [exchange] for an escrow of the first release, see [crate::state::EscrowV0], whose
+/// tmp token account is owned by the PDA all v0 escrows share
+#[allow(clippy::too_many_arguments)]
+pub fn exchange_v0(
+    program_id: &Pubkey,
+    taker: &Pubkey,
+    takers_sending_account: &Pubkey,
+    takers_receiving_account: &Pubkey,
+    pdas_tmp_token_account: &Pubkey,
+    initializer: &Pubkey,
+    initializers_token_to_receive_account: &Pubkey,
+    escrow_account: &Pubkey,
+    token_program_id: &Pubkey,
+    treasury_account: &Pubkey,
+    mint_x: &Pubkey,
+    mint_y: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let (pda, _) = Pubkey::find_program_address(&[Escrow::PDA_SEED], program_id);
+    exchange_with_pda(
+        program_id,
+        taker,
+        takers_sending_account,
+        takers_receiving_account,
+        pdas_tmp_token_account,
+        initializer,
+        initializers_token_to_receive_account,
+        escrow_account,
+        token_program_id,
+        &pda,
+        treasury_account,
+        mint_x,
+        mint_y,
+        amount,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn exchange_with_pda(
+    program_id: &Pubkey,
+    taker: &Pubkey,
+    takers_sending_account: &Pubkey,
+    takers_receiving_account: &Pubkey,
+    pdas_tmp_token_account: &Pubkey,
+    initializer: &Pubkey,
+    initializers_token_to_receive_account: &Pubkey,
+    escrow_account: &Pubkey,
+    token_program_id: &Pubkey,
+    pda: &Pubkey,
+    treasury_account: &Pubkey,
+    mint_x: &Pubkey,
+    mint_y: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let (config, _) = Pubkey::find_program_address(&[Config::PDA_SEED], program_id);
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*taker, true),
+        AccountMeta::new(*takers_sending_account, false),
+        AccountMeta::new(*takers_receiving_account, false),
+        AccountMeta::new(*pdas_tmp_token_account, false),
+        AccountMeta::new(*initializer, false),
+        AccountMeta::new(*initializers_token_to_receive_account, false),
+        AccountMeta::new(*escrow_account, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*pda, false),
+        AccountMeta::new_readonly(config, false),
+        AccountMeta::new(*treasury_account, false),
+        // Token-2022 mints with a transfer fee collect the withheld fees when closing the tmp
+        // token account
+        AccountMeta::new(*mint_x, false),
+        AccountMeta::new_readonly(*mint_y, false),
+    ];
+    if *mint_y == Escrow::LAMPORTS_MINT {
+        accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+    }
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: EscrowInstruction::Exchange { amount }.pack(),
+    }
+}
+
+/// Creates an [EscrowInstruction::MigrateEscrow] instruction, Alice has to sign it.
+/// The PDA shared by v0 escrows is derived from the `program_id`.
+#[allow(clippy::too_many_arguments)]
+pub fn migrate_escrow(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    escrow_account: &Pubkey,
+    new_escrow_account: &Pubkey,
+    pdas_tmp_token_account: &Pubkey,
+    initializers_token_to_receive_account: &Pubkey,
+    token_program_id: &Pubkey,
+    mint_x: &Pubkey,
+    mint_y: &Pubkey,
+) -> Instruction {
+    let (pda, _) = Pubkey::find_program_address(&[Escrow::PDA_SEED], program_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*initializer, true),
+            AccountMeta::new(*escrow_account, false),
+            AccountMeta::new(*new_escrow_account, false),
+            AccountMeta::new(*pdas_tmp_token_account, false),
+            AccountMeta::new_readonly(*initializers_token_to_receive_account, false),
+            AccountMeta::new_readonly(*token_program_id, false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new_readonly(*mint_x, false),
+            AccountMeta::new_readonly(*mint_y, false),
+        ],
+        data: EscrowInstruction::MigrateEscrow.pack(),
+    }
 }
 
 #[cfg(test)]
@@ -103,7 +750,99 @@ mod tests {
 
     #[test]
     fn unpack_escrow_init() {
-        let init_escrow = EscrowInstruction::InitEscrow { amount: 10 };
+        let init_escrow = EscrowInstruction::InitEscrow {
+            amount: 10,
+            expires_at: None,
+            allowed_taker: None,
+        };
+        let packed = init_escrow.pack();
+        let unpacked_escrow = EscrowInstruction::unpack(&packed);
+        assert_eq!(unpacked_escrow, Ok(init_escrow));
+    }
+
+    #[test]
+    fn unpack_escrow_init_expiring() {
+        let init_escrow = EscrowInstruction::InitEscrow {
+            amount: 10,
+            expires_at: Some(1_634_400_000),
+            allowed_taker: None,
+        };
+        let packed = init_escrow.pack();
+        let unpacked_escrow = EscrowInstruction::unpack(&packed);
+        assert_eq!(unpacked_escrow, Ok(init_escrow));
+    }
+
+    #[test]
+    fn unpack_escrow_init_without_expiry() {
+        let packed = [&[0_u8][..], &10_u64.to_le_bytes()].concat();
+        let unpacked_escrow = EscrowInstruction::unpack(&packed);
+        assert_eq!(
+            unpacked_escrow,
+            Ok(EscrowInstruction::InitEscrow {
+                amount: 10,
+                expires_at: None,
+                allowed_taker: None,
+            })
+        );
+    }
+
+    #[test]
+    fn unpack_escrow_init_allowed_taker() {
+        let init_escrow = EscrowInstruction::InitEscrow {
+            amount: 10,
+            expires_at: Some(1_634_400_000),
+            allowed_taker: Some(Pubkey::new_unique()),
+        };
+        let packed = init_escrow.pack();
+        let unpacked_escrow = EscrowInstruction::unpack(&packed);
+        assert_eq!(unpacked_escrow, Ok(init_escrow));
+    }
+
+    #[test]
+    fn unpack_escrow_init_pda() {
+        let init_escrow = EscrowInstruction::InitEscrowPda {
+            amount: 10,
+            expires_at: Some(1_634_400_000),
+            allowed_taker: Some(Pubkey::new_unique()),
+        };
+        let packed = init_escrow.pack();
+        let unpacked_escrow = EscrowInstruction::unpack(&packed);
+        assert_eq!(unpacked_escrow, Ok(init_escrow));
+    }
+
+    #[test]
+    fn unpack_escrow_init_with_deposit() {
+        let init_escrow = EscrowInstruction::InitEscrowWithDeposit {
+            deposit: 5000,
+            amount: 10,
+            expires_at: None,
+            allowed_taker: Some(Pubkey::new_unique()),
+        };
+        let packed = init_escrow.pack();
+        let unpacked_escrow = EscrowInstruction::unpack(&packed);
+        assert_eq!(unpacked_escrow, Ok(init_escrow));
+    }
+
+    #[test]
+    fn unpack_escrow_init_offering_lamports() {
+        let init_escrow = EscrowInstruction::InitEscrowOfferingLamports {
+            deposit: 1_000_000_000,
+            amount: 10,
+            expires_at: Some(1_634_400_000),
+            allowed_taker: None,
+        };
+        let packed = init_escrow.pack();
+        let unpacked_escrow = EscrowInstruction::unpack(&packed);
+        assert_eq!(unpacked_escrow, Ok(init_escrow));
+    }
+
+    #[test]
+    fn unpack_escrow_init_expecting_lamports() {
+        let init_escrow = EscrowInstruction::InitEscrowExpectingLamports {
+            amount: 1_000_000_000,
+            expires_at: None,
+            allowed_taker: Some(Pubkey::new_unique()),
+        };
         let packed = init_escrow.pack();
         let unpacked_escrow = EscrowInstruction::unpack(&packed);
         assert_eq!(unpacked_escrow, Ok(init_escrow));
@@ -116,4 +855,255 @@ mod tests {
         let unpacked_escrow = EscrowInstruction::unpack(&packed);
         assert_eq!(unpacked_escrow, Ok(exchange));
     }
+
+    #[test]
+    fn unpack_escrow_partial_exchange() {
+        let partial_exchange = EscrowInstruction::PartialExchange {
+            amount: 10,
+            expected_amount: 5,
+        };
+        let packed = partial_exchange.pack();
+        let unpacked_escrow = EscrowInstruction::unpack(&packed);
+        assert_eq!(unpacked_escrow, Ok(partial_exchange));
+    }
+
+    #[test]
+    fn unpack_escrow_reclaim_expired() {
+        let reclaim = EscrowInstruction::ReclaimExpired;
+        let packed = reclaim.pack();
+        let unpacked_escrow = EscrowInstruction::unpack(&packed);
+        assert_eq!(unpacked_escrow, Ok(reclaim));
+    }
+
+    #[test]
+    fn unpack_escrow_migrate() {
+        let migrate = EscrowInstruction::MigrateEscrow;
+        let packed = migrate.pack();
+        let unpacked_escrow = EscrowInstruction::unpack(&packed);
+        assert_eq!(unpacked_escrow, Ok(migrate));
+    }
+
+    #[test]
+    fn unpack_set_treasury_account() {
+        let set_treasury_account = EscrowInstruction::SetTreasuryAccount;
+        let packed = set_treasury_account.pack();
+        let unpacked_escrow = EscrowInstruction::unpack(&packed);
+        assert_eq!(unpacked_escrow, Ok(set_treasury_account));
+    }
+
+    #[test]
+    fn unpack_init_config() {
+        let init_config = EscrowInstruction::InitConfig {
+            fee_basis_points: 250,
+        };
+        let packed = init_config.pack();
+        let unpacked_config = EscrowInstruction::unpack(&packed);
+        assert_eq!(unpacked_config, Ok(init_config));
+    }
+
+    #[test]
+    fn unpack_update_config() {
+        let update_config = EscrowInstruction::UpdateConfig {
+            fee_basis_points: 100,
+        };
+        let packed = update_config.pack();
+        let unpacked_config = EscrowInstruction::unpack(&packed);
+        assert_eq!(unpacked_config, Ok(update_config));
+    }
+
+    #[test]
+    fn unpack_escrow_cancel() {
+        let cancel = EscrowInstruction::CancelEscrow;
+        let packed = cancel.pack();
+        let unpacked_escrow = EscrowInstruction::unpack(&packed);
+        assert_eq!(unpacked_escrow, Ok(cancel));
+    }
+
+    #[test]
+    fn build_init_escrow() {
+        let program_id = Pubkey::new_unique();
+        let keys: Vec<Pubkey> = (0..7).map(|_| Pubkey::new_unique()).collect();
+        let ix = init_escrow(
+            &program_id,
+            &keys[0],
+            &keys[1],
+            &keys[2],
+            &keys[3],
+            &keys[4],
+            &keys[5],
+            &keys[6],
+            1000,
+            None,
+            None,
+        );
+
+        assert_eq!(ix.program_id, program_id);
+        let accounts: Vec<_> = ix.accounts.iter().map(|account| account.pubkey).collect();
+        assert_eq!(accounts, keys);
+        let signers: Vec<_> = ix
+            .accounts
+            .iter()
+            .map(|account| account.is_signer)
+            .collect();
+        assert_eq!(signers, [true, false, false, false, false, false, false]);
+        let writable: Vec<_> = ix
+            .accounts
+            .iter()
+            .map(|account| account.is_writable)
+            .collect();
+        assert_eq!(writable, [false, true, false, true, false, false, false]);
+        assert_eq!(
+            EscrowInstruction::unpack(&ix.data),
+            Ok(EscrowInstruction::InitEscrow {
+                amount: 1000,
+                expires_at: None,
+                allowed_taker: None,
+            })
+        );
+    }
+
+    #[test]
+    fn build_init_escrow_expecting_lamports() {
+        let key = Pubkey::new_unique();
+        let ix = init_escrow(
+            &key,
+            &key,
+            &key,
+            &key,
+            &key,
+            &key,
+            &key,
+            &Escrow::LAMPORTS_MINT,
+            1000,
+            None,
+            None,
+        );
+
+        assert_eq!(ix.accounts.len(), 6, "no mint of token Y");
+        assert_eq!(
+            EscrowInstruction::unpack(&ix.data),
+            Ok(EscrowInstruction::InitEscrowExpectingLamports {
+                amount: 1000,
+                expires_at: None,
+                allowed_taker: None,
+            })
+        );
+    }
+
+    #[test]
+    fn build_exchange_v0() {
+        let program_id = Pubkey::new_unique();
+        let escrow_account = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let ix = exchange_v0(
+            &program_id,
+            &key,
+            &key,
+            &key,
+            &key,
+            &key,
+            &key,
+            &escrow_account,
+            &key,
+            &key,
+            &key,
+            &key,
+            5000,
+        );
+
+        let current_ix = exchange(
+            &program_id,
+            &key,
+            &key,
+            &key,
+            &key,
+            &key,
+            &key,
+            &escrow_account,
+            &key,
+            &key,
+            &key,
+            &key,
+            5000,
+        );
+        let (pda, _) = Pubkey::find_program_address(&[Escrow::PDA_SEED], &program_id);
+        assert_eq!(
+            ix.accounts[8].pubkey, pda,
+            "v0 escrows share the PDA derived without the escrow account"
+        );
+        assert_eq!(ix.accounts[..8], current_ix.accounts[..8]);
+        assert_eq!(ix.accounts[9..], current_ix.accounts[9..]);
+        assert_eq!(ix.data, current_ix.data);
+    }
+
+    #[test]
+    fn build_exchange() {
+        let program_id = Pubkey::new_unique();
+        let escrow_account = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let ix = exchange(
+            &program_id,
+            &key,
+            &key,
+            &key,
+            &key,
+            &key,
+            &key,
+            &escrow_account,
+            &key,
+            &key,
+            &key,
+            &key,
+            5000,
+        );
+
+        assert_eq!(ix.accounts.len(), 13);
+        assert_eq!(ix.accounts[6].pubkey, escrow_account);
+        let (pda, _) =
+            Pubkey::find_program_address(&[Escrow::PDA_SEED, escrow_account.as_ref()], &program_id);
+        assert_eq!(ix.accounts[8].pubkey, pda);
+        let (config, _) = Pubkey::find_program_address(&[Config::PDA_SEED], &program_id);
+        assert_eq!(ix.accounts[9].pubkey, config);
+
+        let signers: Vec<_> = ix
+            .accounts
+            .iter()
+            .map(|account| account.is_signer)
+            .collect();
+        assert_eq!(signers, [[true].as_ref(), &[false; 12]].concat());
+        let writable: Vec<_> = ix
+            .accounts
+            .iter()
+            .map(|account| account.is_writable)
+            .collect();
+        assert_eq!(
+            writable,
+            [false, true, true, true, true, true, true, false, false, false, true, true, false]
+        );
+        assert_eq!(
+            EscrowInstruction::unpack(&ix.data),
+            Ok(EscrowInstruction::Exchange { amount: 5000 })
+        );
+
+        let ix = exchange(
+            &program_id,
+            &key,
+            &key,
+            &key,
+            &key,
+            &key,
+            &key,
+            &escrow_account,
+            &key,
+            &key,
+            &key,
+            &Escrow::LAMPORTS_MINT,
+            5000,
+        );
+        assert_eq!(
+            ix.accounts[13].pubkey,
+            system_program::id(),
+            "Bob pays lamports via the system program"
+        );
+    }
 }
@@ -14,6 +14,20 @@ pub mod state;
 /// Program Specific Errors
 pub mod error;
 
+/// Structured events logged by the processor
+pub mod event;
+
+/// Classic token program and Token-2022 support
+pub mod token;
+
+/// Borsh (de)serialization of instructions and state, byte-identical to Pack
+#[cfg(feature = "borsh")]
+mod borsh_layout;
+
+/// Off-chain decoding and pretty-printing of escrow accounts
+#[cfg(feature = "client")]
+pub mod view;
+
 #[cfg(test)]
 pub mod test_utils;
 
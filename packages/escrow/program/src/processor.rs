@@ -1,5 +1,7 @@
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    bpf_loader_upgradeable,
+    clock::{Clock, UnixTimestamp},
     entrypoint::ProgramResult,
     msg,
     program::{invoke, invoke_signed},
@@ -7,13 +9,27 @@ use solana_program::{
     program_pack::Pack,
     pubkey::Pubkey,
     rent::Rent,
+    system_instruction,
     sysvar::Sysvar,
 };
 
-use crate::{error::EscrowError, instruction::EscrowInstruction, state::Escrow};
+use crate::{
+    error::EscrowError,
+    event::EscrowEvent,
+    instruction::EscrowInstruction,
+    state::{Config, Escrow, EscrowData, EscrowV0},
+    token,
+};
+use arrayref::{array_ref, array_refs};
+use sol_common::compute;
 use spl_token::instruction::{set_authority, AuthorityType};
 use spl_token::state::Account as TokenAccount;
 
+// Variant of UpgradeableLoaderState::ProgramData and the size of its fields before the program,
+// the upgrade authority always takes up 33 bytes even if there is none
+const PROGRAM_DATA_VARIANT: u32 = 3;
+const PROGRAM_DATA_METADATA_LEN: usize = 4 + 8 + 1 + 32;
+
 pub struct Processor;
 impl Processor {
     pub fn process(
@@ -25,21 +41,135 @@ impl Processor {
 
         use EscrowInstruction::*;
         match instruction {
-            InitEscrow { amount } => {
+            InitEscrow {
+                amount,
+                expires_at,
+                allowed_taker,
+            } => {
                 msg!("Instruction: InitEscrow");
-                Self::process_init_escrow(program_id, accounts, amount)
+                Self::process_init_escrow(
+                    program_id,
+                    accounts,
+                    amount,
+                    expires_at,
+                    allowed_taker,
+                    false,
+                    false,
+                )
             }
             EscrowInstruction::Exchange { amount } => {
                 msg!("Instruction: Exchange");
-                Self::process_exchange(program_id, accounts, amount)
+                Self::process_exchange(program_id, accounts, None, amount)
+            }
+            PartialExchange {
+                amount,
+                expected_amount,
+            } => {
+                msg!("Instruction: PartialExchange");
+                Self::process_exchange(program_id, accounts, Some(amount), expected_amount)
+            }
+            CancelEscrow => {
+                msg!("Instruction: CancelEscrow");
+                Self::process_return_deposit(program_id, accounts, false)
+            }
+            ReclaimExpired => {
+                msg!("Instruction: ReclaimExpired");
+                Self::process_return_deposit(program_id, accounts, true)
+            }
+            InitConfig { fee_basis_points } => {
+                msg!("Instruction: InitConfig");
+                Self::process_init_config(program_id, accounts, fee_basis_points)
+            }
+            UpdateConfig { fee_basis_points } => {
+                msg!("Instruction: UpdateConfig");
+                Self::process_update_config(program_id, accounts, fee_basis_points)
+            }
+            SetTreasuryAccount => {
+                msg!("Instruction: SetTreasuryAccount");
+                Self::process_set_treasury_account(program_id, accounts)
+            }
+            InitEscrowWithDeposit {
+                deposit,
+                amount,
+                expires_at,
+                allowed_taker,
+            } => {
+                msg!("Instruction: InitEscrowWithDeposit");
+                Self::process_init_escrow_with_deposit(
+                    program_id,
+                    accounts,
+                    deposit,
+                    amount,
+                    expires_at,
+                    allowed_taker,
+                )
+            }
+            InitEscrowPda {
+                amount,
+                expires_at,
+                allowed_taker,
+            } => {
+                msg!("Instruction: InitEscrowPda");
+                Self::process_init_escrow(
+                    program_id,
+                    accounts,
+                    amount,
+                    expires_at,
+                    allowed_taker,
+                    true,
+                    false,
+                )
+            }
+            InitEscrowOfferingLamports {
+                deposit,
+                amount,
+                expires_at,
+                allowed_taker,
+            } => {
+                msg!("Instruction: InitEscrowOfferingLamports");
+                Self::process_init_escrow_offering_lamports(
+                    program_id,
+                    accounts,
+                    deposit,
+                    amount,
+                    expires_at,
+                    allowed_taker,
+                )
+            }
+            InitEscrowExpectingLamports {
+                amount,
+                expires_at,
+                allowed_taker,
+            } => {
+                msg!("Instruction: InitEscrowExpectingLamports");
+                Self::process_init_escrow(
+                    program_id,
+                    accounts,
+                    amount,
+                    expires_at,
+                    allowed_taker,
+                    false,
+                    true,
+                )
+            }
+            MigrateEscrow => {
+                msg!("Instruction: MigrateEscrow");
+                Self::process_migrate_escrow(program_id, accounts)
             }
         }
     }
 
+    /// Initializes the escrow, when `create_escrow_account` is set the program creates the escrow
+    /// account at its PDA first instead of expecting the client to have created it.
+    /// When `expects_lamports` is set Alice is paid in lamports instead of token Y.
     fn process_init_escrow(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
         amount: u64,
+        expires_at: Option<UnixTimestamp>,
+        allowed_taker: Option<Pubkey>,
+        create_escrow_account: bool,
+        expects_lamports: bool,
     ) -> ProgramResult {
         let account_iter = &mut accounts.iter();
 
@@ -51,47 +181,74 @@ impl Processor {
 
         // 2. tmp token account (account 1) Alice Token X
         // - tx fails if it's not writable
-        // - tx fails if not owned by token program at the point where we try to transfer it to the PDA
-        // - no explicit checks needed here
+        // - tx fails if not owned by a supported token program
+        // - unpacked only to record the mint of token X
         let tmp_token_account = next_account_info(account_iter)?;
+        let tmp_token_account_info = token::unpack_account(tmp_token_account)?;
 
-        // 3. initializer's receive token account (account 2) Alice Token Y
+        // 3. initializer's receive token account (account 2) Alice Token Y, any account to receive
+        //    lamports
         let token_to_receive_account = next_account_info(account_iter)?;
-        let token_program_id = spl_token::id();
-        if *token_to_receive_account.owner != token_program_id {
-            return Err(ProgramError::IncorrectProgramId);
-        }
+        let token_to_receive_mint = if expects_lamports {
+            Escrow::LAMPORTS_MINT
+        } else {
+            Self::unpack_token_to_receive_account(token_to_receive_account)?.mint
+        };
 
         // 4. escrow account (account 3)
         let escrow_account = next_account_info(account_iter)?;
 
-        let rent = Rent::get()?;
-        if !rent.is_exempt(escrow_account.lamports(), escrow_account.data_len()) {
-            return Err(EscrowError::NotRentExempt.into());
-        }
-
-        // Extract [Escrow] state from it's account data
-        let mut escrow_state = Escrow::unpack_unchecked(&escrow_account.data.borrow())?;
-        if escrow_state.is_initialized {
-            return Err(ProgramError::AccountAlreadyInitialized);
-        }
+        // 5. token program (account 4)
+        let token_program = next_account_info(account_iter)?;
+        token::check_token_program(token_program)?;
 
-        // initialize escrow state
-        escrow_state.is_initialized = true;
-        escrow_state.initializer_pubkey = *initializer.key;
-        escrow_state.tmp_token_account_pubkey = *tmp_token_account.key;
-        escrow_state.initializer_token_to_receive_account_pubkey = *token_to_receive_account.key;
-        escrow_state.expected_amount = amount;
+        // 6. mint of token X (account 5)
+        let mint_x = next_account_info(account_iter)?;
+        let mint_x_decimals = Self::mint_decimals(mint_x, &tmp_token_account_info.mint)?;
 
-        // persist escrow state
-        Escrow::pack(escrow_state, &mut escrow_account.data.borrow_mut())?;
+        // 7. mint of token Y (account 6), not needed when Alice expects lamports
+        let mint_y_decimals = if expects_lamports {
+            Escrow::LAMPORTS_DECIMALS
+        } else {
+            let mint_y = next_account_info(account_iter)?;
+            Self::mint_decimals(mint_y, &token_to_receive_mint)?
+        };
 
-        // Transfer ownership of tmp token to the PDA
-        let (pda, _bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+        let rent = Rent::get()?;
+        if create_escrow_account {
+            // 8. system program (account 7)
+            let system_program = next_account_info(account_iter)?;
+            Self::create_escrow_account(
+                program_id,
+                initializer,
+                tmp_token_account,
+                escrow_account,
+                system_program,
+                &rent,
+            )?;
+        }
 
-        // 6. token program (account 5)
-        let token_program = next_account_info(account_iter)?;
+        let pda = Self::init_escrow_account(
+            program_id,
+            escrow_account,
+            &rent,
+            tmp_token_account_info.amount,
+            Escrow {
+                initializer_pubkey: *initializer.key,
+                tmp_token_account_pubkey: *tmp_token_account.key,
+                initializer_token_to_receive_account_pubkey: *token_to_receive_account.key,
+                expected_amount: amount,
+                expires_at,
+                allowed_taker,
+                mint_x_pubkey: tmp_token_account_info.mint,
+                mint_y_pubkey: token_to_receive_mint,
+                mint_x_decimals,
+                mint_y_decimals,
+                ..Escrow::default()
+            },
+        )?;
 
+        // Transfer ownership of tmp token to the PDA
         /*
         Create instruction that makes PDA the account owner of the tmp_token_account
           token_program_id     = key of program being called
@@ -100,18 +257,18 @@ impl Processor {
           owner_pubkey         = key of current owner
           signer_pubkeys       = public keys signing the CPI (to approve owner change)
         */
-        let owner_change_ix = set_authority(
-            /* token_program_id     */ token_program.key,
-            /* owned_pubkey         */ tmp_token_account.key,
-            /* new_authority_pubkey */ Some(&pda),
-            /* authority_type       */ AuthorityType::AccountOwner,
-            /* owner_pubkey         */ initializer.key,
-            /* signer_pubkeys       */ &[&initializer.key],
+        let owner_change_ix = token::for_token_program(
+            set_authority(
+                /* token_program_id     */ &spl_token::id(),
+                /* owned_pubkey         */ tmp_token_account.key,
+                /* new_authority_pubkey */ Some(&pda),
+                /* authority_type       */ AuthorityType::AccountOwner,
+                /* owner_pubkey         */ initializer.key,
+                /* signer_pubkeys       */ &[initializer.key],
+            ),
+            token_program.key,
         )?;
 
-        // NOTE: spl-token instruction builder verifies that token_program account is the account
-        // of the token program
-
         msg!("Calling the token program to transfer token account ownership...");
 
         // Invoke cross-program instruction
@@ -125,9 +282,356 @@ impl Processor {
 
         Ok(())
     }
+
+    /// Initializes the escrow, creating and funding the tmp token account (vault) which the PDA
+    /// owns from the start instead of having Alice hand over the authority of her own account.
+    fn process_init_escrow_with_deposit(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        deposit: u64,
+        amount: u64,
+        expires_at: Option<UnixTimestamp>,
+        allowed_taker: Option<Pubkey>,
+    ) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+
+        // 1. escrow initializer (account 0)
+        let initializer = next_account_info(account_iter)?;
+        if !initializer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        // 2. vault token account to create (account 1) PDA Token X
+        let vault_account = next_account_info(account_iter)?;
+
+        // 3. initializer's receive token account (account 2) Alice Token Y
+        let token_to_receive_account = next_account_info(account_iter)?;
+        let token_to_receive_account_info =
+            Self::unpack_token_to_receive_account(token_to_receive_account)?;
+
+        // 4. escrow account (account 3)
+        let escrow_account = next_account_info(account_iter)?;
+
+        // 5. token program (account 4)
+        let token_program = next_account_info(account_iter)?;
+        token::check_token_program(token_program)?;
+
+        // 6. initializer's token account to take the deposit from (account 5) Alice Token X
+        let source_token_account = next_account_info(account_iter)?;
+
+        // 7. mint of token X (account 6)
+        let mint_x = next_account_info(account_iter)?;
+        let mint_x_info = token::unpack_mint(mint_x)?;
+
+        // 8. PDA account (account 7)
+        let pda_account = next_account_info(account_iter)?;
+
+        // 9. system program (account 8)
+        let system_program = next_account_info(account_iter)?;
+
+        // 10. rent sysvar (account 9), required by the token program to initialize the vault
+        let rent_sysvar = next_account_info(account_iter)?;
+
+        // 11. mint of token Y (account 10)
+        let mint_y = next_account_info(account_iter)?;
+        let mint_y_decimals = Self::mint_decimals(mint_y, &token_to_receive_account_info.mint)?;
+
+        let vault_seeds = &[Escrow::VAULT_SEED, escrow_account.key.as_ref()];
+        let (vault_pda, vault_bump_seed) = Pubkey::find_program_address(vault_seeds, program_id);
+        if vault_pda != *vault_account.key {
+            return Err(EscrowError::InvalidVaultAddress.into());
+        }
+
+        let rent = Rent::get()?;
+        let pda = Self::init_escrow_account(
+            program_id,
+            escrow_account,
+            &rent,
+            deposit,
+            Escrow {
+                initializer_pubkey: *initializer.key,
+                tmp_token_account_pubkey: *vault_account.key,
+                initializer_token_to_receive_account_pubkey: *token_to_receive_account.key,
+                expected_amount: amount,
+                expires_at,
+                allowed_taker,
+                mint_x_pubkey: *mint_x.key,
+                mint_y_pubkey: token_to_receive_account_info.mint,
+                mint_x_decimals: mint_x_info.decimals,
+                mint_y_decimals,
+                ..Escrow::default()
+            },
+        )?;
+        if pda != *pda_account.key {
+            return Err(EscrowError::InvalidPdaAccount.into());
+        }
+
+        // Fails if the vault was created before
+        let vault_len = token::account_len(mint_x);
+        let create_vault_ix = system_instruction::create_account(
+            // from_pubkey
+            initializer.key,
+            // to_pubkey
+            vault_account.key,
+            // lamports
+            rent.minimum_balance(vault_len),
+            // space
+            vault_len as u64,
+            // owner
+            token_program.key,
+        );
+        msg!("Calling the system program to create the vault...");
+        invoke_signed(
+            &create_vault_ix,
+            &[
+                initializer.clone(),
+                vault_account.clone(),
+                system_program.clone(),
+            ],
+            &[&[
+                Escrow::VAULT_SEED,
+                escrow_account.key.as_ref(),
+                &[vault_bump_seed],
+            ]],
+        )?;
+
+        // The PDA owns the vault from the start, no authority handoff needed
+        let init_vault_ix = token::for_token_program(
+            spl_token::instruction::initialize_account(
+                &spl_token::id(),
+                vault_account.key,
+                mint_x.key,
+                &pda,
+            ),
+            token_program.key,
+        )?;
+        msg!("Calling the token program to initialize the vault...");
+        invoke(
+            &init_vault_ix,
+            &[
+                vault_account.clone(),
+                mint_x.clone(),
+                pda_account.clone(),
+                rent_sysvar.clone(),
+                token_program.clone(),
+            ],
+        )?;
+
+        // Transfer Alice's X into the vault
+        let deposit_ix = token::transfer_checked(
+            token_program.key,
+            // source
+            source_token_account.key,
+            // mint
+            mint_x.key,
+            // destination
+            vault_account.key,
+            // authority
+            initializer.key,
+            deposit,
+            mint_x_info.decimals,
+        )?;
+        msg!("Calling the token program to transfer the deposit to the vault...");
+        invoke(
+            &deposit_ix,
+            &[
+                source_token_account.clone(),
+                mint_x.clone(),
+                vault_account.clone(),
+                initializer.clone(),
+                token_program.clone(),
+            ],
+        )
+    }
+
+    /// Initializes an escrow in which Alice offers lamports, which she deposits into the escrow
+    /// account itself.
+    fn process_init_escrow_offering_lamports(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        deposit: u64,
+        amount: u64,
+        expires_at: Option<UnixTimestamp>,
+        allowed_taker: Option<Pubkey>,
+    ) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+
+        // 1. escrow initializer (account 0)
+        let initializer = next_account_info(account_iter)?;
+        if !initializer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        // 2. escrow account which also holds the deposit (account 1)
+        let escrow_account = next_account_info(account_iter)?;
+
+        // 3. initializer's receive token account (account 2) Alice Token Y
+        let token_to_receive_account = next_account_info(account_iter)?;
+        let token_to_receive_account_info =
+            Self::unpack_token_to_receive_account(token_to_receive_account)?;
+
+        // 4. system program (account 3)
+        let system_program = next_account_info(account_iter)?;
+
+        // 5. mint of token Y (account 4)
+        let mint_y = next_account_info(account_iter)?;
+        let mint_y_decimals = Self::mint_decimals(mint_y, &token_to_receive_account_info.mint)?;
+
+        let rent = Rent::get()?;
+        Self::init_escrow_account(
+            program_id,
+            escrow_account,
+            &rent,
+            deposit,
+            Escrow {
+                initializer_pubkey: *initializer.key,
+                // Exchange and cancel expect the account holding the deposit here
+                tmp_token_account_pubkey: *escrow_account.key,
+                initializer_token_to_receive_account_pubkey: *token_to_receive_account.key,
+                expected_amount: amount,
+                expires_at,
+                allowed_taker,
+                mint_x_pubkey: Escrow::LAMPORTS_MINT,
+                mint_y_pubkey: token_to_receive_account_info.mint,
+                mint_x_decimals: Escrow::LAMPORTS_DECIMALS,
+                mint_y_decimals,
+                ..Escrow::default()
+            },
+        )?;
+
+        msg!("Calling the system program to transfer the deposit to the escrow account...");
+        invoke(
+            &system_instruction::transfer(initializer.key, escrow_account.key, deposit),
+            &[
+                initializer.clone(),
+                escrow_account.clone(),
+                system_program.clone(),
+            ],
+        )
+    }
+
+    /// Unpacks the token account in which Alice receives token Y
+    fn unpack_token_to_receive_account(
+        token_to_receive_account: &AccountInfo,
+    ) -> Result<TokenAccount, ProgramError> {
+        token::unpack_account(token_to_receive_account)
+    }
+
+    /// Checks that the escrow account can hold a new escrow and persists `escrow_state` in it,
+    /// logging that Alice deposited `deposit` of token X.
+    /// Returns the PDA which needs to own the escrow's tmp token account.
+    fn init_escrow_account(
+        program_id: &Pubkey,
+        escrow_account: &AccountInfo,
+        rent: &Rent,
+        deposit: u64,
+        mut escrow_state: Escrow,
+    ) -> Result<Pubkey, ProgramError> {
+        // - has to be owned by this program, otherwise we'd write into someone else's account
+        // - has to be exactly as large as the escrow state, shorter buffers can't be unpacked
+        if escrow_account.owner != program_id {
+            return Err(EscrowError::InvalidEscrowOwner.into());
+        }
+        if escrow_account.data_len() != Escrow::LEN {
+            return Err(EscrowError::InvalidEscrowSize.into());
+        }
+
+        if !rent.is_exempt(escrow_account.lamports(), escrow_account.data_len()) {
+            return Err(EscrowError::NotRentExempt.into());
+        }
+
+        // Extract [Escrow] state from it's account data
+        let current_state = Escrow::unpack_unchecked(&escrow_account.data.borrow())?;
+        if current_state.is_initialized {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        // Each escrow gets its own PDA which will own the tmp token account, derived from the
+        // escrow account key so that it has no authority over any other escrow's tokens
+        compute! { "find pda" =>
+        let (pda, bump_seed) = Pubkey::find_program_address(
+            &[Escrow::PDA_SEED, escrow_account.key.as_ref()],
+            program_id,
+        );
+        }
+
+        // persist escrow state
+        escrow_state.is_initialized = true;
+        escrow_state.bump_seed = bump_seed;
+        let created = EscrowEvent::Created {
+            escrow: *escrow_account.key,
+            initializer: escrow_state.initializer_pubkey,
+            mint_x: escrow_state.mint_x_pubkey,
+            mint_y: escrow_state.mint_y_pubkey,
+            amount_x: deposit,
+            expected_amount: escrow_state.expected_amount,
+        };
+        Escrow::pack(escrow_state, &mut escrow_account.data.borrow_mut())?;
+        created.emit();
+
+        Ok(pda)
+    }
+
+    /// Creates the escrow account at the PDA derived from the initializer and the tmp token
+    /// account, sized to hold the escrow state and funded to be rent exempt by the initializer
+    fn create_escrow_account<'a>(
+        program_id: &Pubkey,
+        initializer: &AccountInfo<'a>,
+        tmp_token_account: &AccountInfo<'a>,
+        escrow_account: &AccountInfo<'a>,
+        system_program: &AccountInfo<'a>,
+        rent: &Rent,
+    ) -> ProgramResult {
+        let (escrow_account_pda, bump_seed) = Pubkey::find_program_address(
+            &[
+                Escrow::ACCOUNT_SEED,
+                initializer.key.as_ref(),
+                tmp_token_account.key.as_ref(),
+            ],
+            program_id,
+        );
+        if escrow_account_pda != *escrow_account.key {
+            return Err(EscrowError::InvalidEscrowAddress.into());
+        }
+
+        // Fails if the escrow account was created before
+        let create_escrow_acc_ix = system_instruction::create_account(
+            // from_pubkey
+            initializer.key,
+            // to_pubkey
+            escrow_account.key,
+            // lamports
+            rent.minimum_balance(Escrow::LEN),
+            // space
+            Escrow::LEN as u64,
+            // owner
+            program_id,
+        );
+        msg!("Calling the system program to create the escrow account...");
+        invoke_signed(
+            &create_escrow_acc_ix,
+            &[
+                initializer.clone(),
+                escrow_account.clone(),
+                system_program.clone(),
+            ],
+            &[&[
+                Escrow::ACCOUNT_SEED,
+                initializer.key.as_ref(),
+                tmp_token_account.key.as_ref(),
+                &[bump_seed],
+            ]],
+        )
+    }
+
+    /// Exchanges the tokens of both parties.
+    /// When `amount_from_taker` is provided Bob only fills that part of the offer and the escrow
+    /// stays open for the remainder, otherwise he pays everything the escrow still expects.
+    /// Either leg may be native lamports instead of a token, see [Escrow::LAMPORTS_MINT].
     fn process_exchange(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
+        amount_from_taker: Option<u64>,
         amount_expected_by_taker: u64,
     ) -> ProgramResult {
         let account_iter = &mut accounts.iter();
@@ -146,14 +650,6 @@ impl Processor {
 
         // 4. PDA's tmp token account to get tokens from, which we'll close at the end (account 3)
         let pdas_tmp_token_account = next_account_info(account_iter)?;
-        let pdas_tmp_token_account_info =
-            TokenAccount::unpack(&pdas_tmp_token_account.data.borrow())?;
-
-        if amount_expected_by_taker != pdas_tmp_token_account_info.amount {
-            return Err(EscrowError::ExpectedAmountMismatch.into());
-        }
-
-        let (pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
 
         // 5. initializer's main account to send rent fees to (account 4) Alice
         let initializers_main_account = next_account_info(account_iter)?;
@@ -162,289 +658,1456 @@ impl Processor {
         let initializers_token_to_receive_account = next_account_info(account_iter)?;
 
         // 7.  escrow account holding the escrow info (account 6)
+        // - read in place, the escrow is only borrowed mutably again to record a partial fill
+        // - v0 escrows can't be read in place, they are upgraded in memory instead
         let escrow_account = next_account_info(account_iter)?;
-        let escrow_info = Escrow::unpack(&escrow_account.data.borrow())?;
+        if escrow_account.owner != program_id {
+            return Err(EscrowError::InvalidEscrowOwner.into());
+        }
+        let escrow_data = escrow_account.data.borrow();
+        let is_v0 = escrow_data.len() == EscrowV0::LEN;
+        let v0_escrow_info;
+        let escrow_info = if is_v0 {
+            v0_escrow_info = EscrowData::from(&Self::unpack_v0_escrow(
+                program_id,
+                &escrow_data,
+                pdas_tmp_token_account,
+                Some(initializers_token_to_receive_account),
+            )?);
+            &v0_escrow_info
+        } else {
+            compute! { "load escrow" =>
+            let escrow_info = EscrowData::load(&escrow_data)?;
+            }
+            escrow_info
+        };
 
         if escrow_info.tmp_token_account_pubkey != *pdas_tmp_token_account.key {
-            return Err(ProgramError::InvalidAccountData);
+            return Err(EscrowError::EscrowAccountMismatch.into());
         }
         if escrow_info.initializer_pubkey != *initializers_main_account.key {
-            return Err(ProgramError::InvalidAccountData);
+            return Err(EscrowError::EscrowAccountMismatch.into());
         }
         if escrow_info.initializer_token_to_receive_account_pubkey
             != *initializers_token_to_receive_account.key
         {
-            return Err(ProgramError::InvalidAccountData);
+            return Err(EscrowError::EscrowAccountMismatch.into());
+        }
+
+        // Bob has to send token Y and receive token X, the token program only ensures that each
+        // transfer stays within a single mint
+        if escrow_info.expects_lamports() {
+            // Bob pays the lamports himself
+            if takers_sending_token_account.key != taker.key {
+                return Err(ProgramError::InvalidAccountData);
+            }
+        } else {
+            Self::check_token_account_mint(
+                takers_sending_token_account,
+                &escrow_info.mint_y_pubkey,
+            )?;
+        }
+        if !escrow_info.offers_lamports() {
+            Self::check_token_account_mint(
+                takers_token_to_receive_account,
+                &escrow_info.mint_x_pubkey,
+            )?;
+        }
+
+        if !escrow_info.can_be_taken_by(taker.key) {
+            return Err(EscrowError::UnauthorizedTaker.into());
+        }
+
+        // Only escrows that can expire need to pay for reading the clock
+        if escrow_info.expires_at().is_some()
+            && escrow_info.is_expired(Clock::get()?.unix_timestamp)
+        {
+            return Err(EscrowError::EscrowExpired.into());
+        }
+
+        let deposit = if escrow_info.offers_lamports() {
+            Self::lamports_deposit(escrow_account)?
+        } else {
+            token::unpack_account(pdas_tmp_token_account)?.amount
+        };
+        let (amount_to_initializer, amount_to_taker) = match amount_from_taker {
+            None => (escrow_info.expected_amount(), deposit),
+            Some(amount_from_taker) => (
+                amount_from_taker,
+                escrow_info
+                    .amount_to_taker(deposit, amount_from_taker)
+                    .ok_or(EscrowError::InvalidFillAmount)?,
+            ),
+        };
+
+        // Rebuilding the PDA from the bump seed stored at init is a lot cheaper than searching for
+        // it via find_program_address on every exchange
+        // v0 escrows share a single PDA
+        let bump_seed = [escrow_info.bump_seed];
+        let seeds = [Escrow::PDA_SEED, escrow_account.key.as_ref(), &bump_seed];
+        let v0_seeds = [Escrow::PDA_SEED, &bump_seed];
+        let pda_seeds: &[&[u8]] = if is_v0 { &v0_seeds } else { &seeds };
+        compute! { "create pda" =>
+        let pda = Pubkey::create_program_address(pda_seeds, program_id)?;
         }
 
         // 8. Token Program (account 7)
         let token_program = next_account_info(account_iter)?;
+        token::check_token_program(token_program)?;
 
         // 9. PDA account (account 8)
         let pda_account = next_account_info(account_iter)?;
+        if *pda_account.key != pda {
+            return Err(EscrowError::InvalidPdaAccount.into());
+        }
+
+        // 10. config account (account 9)
+        // - no fee is charged until the admin initialized the config
+        let config_account = next_account_info(account_iter)?;
+        let config = Self::unpack_optional_config(program_id, config_account)?;
+
+        // 11. treasury's token account receiving the fee (account 10) Treasury Y
+        // - the one the admin set for mint Y, the treasury itself when Alice expects lamports
+        // - ignored without a config or when there is no treasury token account for mint Y, no
+        //   fee is charged then
+        let treasury_token_account = next_account_info(account_iter)?;
+        let treasury = match &config {
+            Some(config) if escrow_info.expects_lamports() => Some(&config.treasury_pubkey),
+            Some(config) => config.treasury_account(&escrow_info.mint_y_pubkey),
+            None => None,
+        };
+        if treasury.map_or(false, |treasury| treasury != treasury_token_account.key) {
+            return Err(EscrowError::InvalidTreasuryAccount.into());
+        }
+
+        // 12. mint of token X (account 11), ignored when Alice offers lamports
+        let mint_x = next_account_info(account_iter)?;
+        if !escrow_info.offers_lamports() && *mint_x.key != escrow_info.mint_x_pubkey {
+            return Err(EscrowError::MintMismatch.into());
+        }
+
+        // 13. mint of token Y (account 12), ignored when Bob pays lamports
+        let mint_y = next_account_info(account_iter)?;
+        if !escrow_info.expects_lamports() && *mint_y.key != escrow_info.mint_y_pubkey {
+            return Err(EscrowError::MintMismatch.into());
+        }
+
+        // v0 escrows didn't record the decimals either
+        let (mint_x_decimals, mint_y_decimals) = if is_v0 {
+            (
+                token::unpack_mint(mint_x)?.decimals,
+                token::unpack_mint(mint_y)?.decimals,
+            )
+        } else {
+            (escrow_info.mint_x_decimals, escrow_info.mint_y_decimals)
+        };
+
+        // 14. system program (account 13), only needed to move Bob's lamports
+        let system_program = if escrow_info.expects_lamports() {
+            Some(next_account_info(account_iter)?)
+        } else {
+            None
+        };
+
+        // Bob only receives what's left of his X after the mint withheld its transfer fee
+        let amount_received_by_taker = if escrow_info.offers_lamports() {
+            amount_to_taker
+        } else {
+            amount_to_taker
+                .checked_sub(token::transfer_fee(mint_x, amount_to_taker)?)
+                .ok_or(EscrowError::AmountOverflow)?
+        };
+        if amount_expected_by_taker != amount_received_by_taker {
+            return Err(EscrowError::ExpectedAmountMismatch.into());
+        }
 
         // -----------------
         // Transfers
         // -----------------
 
+        // The fee is skimmed from what Bob sends to Alice
+        let fee = match (&config, treasury) {
+            (Some(config), Some(_)) => config.fee(amount_to_initializer),
+            _ => 0,
+        };
+        if fee > 0 {
+            msg!("Transferring the fee to the treasury...");
+            Self::transfer_from_taker(
+                token_program,
+                system_program,
+                mint_y,
+                mint_y_decimals,
+                takers_sending_token_account,
+                treasury_token_account,
+                taker,
+                fee,
+            )?;
+        }
+
         // Transfer Bob's Y directly to Alice's Y
-        let transfer_to_initializer_ix = spl_token::instruction::transfer(
-            token_program.key,
-            // source
-            takers_sending_token_account.key,
-            // destination
-            initializers_token_to_receive_account.key,
-            // authority
-            taker.key,
-            // signer
-            &[&taker.key],
-            escrow_info.expected_amount,
-        )?;
-        msg!("Calling the token program to transfer tokens to the escrow's initializer...");
-        invoke(
-            &transfer_to_initializer_ix,
-            &[
-                takers_sending_token_account.clone(),
-                initializers_token_to_receive_account.clone(),
-                taker.clone(),
-                token_program.clone(),
-            ],
+        msg!("Transferring to the escrow's initializer...");
+        Self::transfer_from_taker(
+            token_program,
+            system_program,
+            mint_y,
+            mint_y_decimals,
+            takers_sending_token_account,
+            initializers_token_to_receive_account,
+            taker,
+            amount_to_initializer - fee,
         )?;
 
         // Transfer Alice's X stored in tmp account during init to Bob's X
-        let transfer_to_taker_ix = spl_token::instruction::transfer(
-            token_program.key,
-            // source
-            pdas_tmp_token_account.key,
-            // destination
-            takers_token_to_receive_account.key,
-            // authority
-            &pda,
-            // signer
-            &[&pda],
-            pdas_tmp_token_account_info.amount,
-        )?;
-        msg!("Calling the token program to transfer tokens to the taker...");
-        invoke_signed(
-            &transfer_to_taker_ix,
-            &[
-                pdas_tmp_token_account.clone(),
-                takers_token_to_receive_account.clone(),
-                pda_account.clone(),
-                token_program.clone(),
-            ],
-            // signers_seeds: &[&[&[u8]]]
-            &[&[&b"escrow"[..], &[bump_seed]]],
-        )?;
+        if escrow_info.offers_lamports() {
+            msg!("Transferring lamports to the taker...");
+            Self::transfer_lamports(
+                escrow_account,
+                takers_token_to_receive_account,
+                amount_to_taker,
+            )?;
+        } else {
+            let transfer_to_taker_ix = token::transfer_checked(
+                token_program.key,
+                // source
+                pdas_tmp_token_account.key,
+                // mint
+                mint_x.key,
+                // destination
+                takers_token_to_receive_account.key,
+                // authority
+                &pda,
+                amount_to_taker,
+                mint_x_decimals,
+            )?;
+            msg!("Calling the token program to transfer tokens to the taker...");
+            invoke_signed(
+                &transfer_to_taker_ix,
+                &[
+                    pdas_tmp_token_account.clone(),
+                    mint_x.clone(),
+                    takers_token_to_receive_account.clone(),
+                    pda_account.clone(),
+                    token_program.clone(),
+                ],
+                // signers_seeds: &[&[&[u8]]]
+                &[pda_seeds],
+            )?;
+        }
+
+        let expected_amount = escrow_info.expected_amount();
+        let offers_lamports = escrow_info.offers_lamports();
+        EscrowEvent::Exchanged {
+            escrow: *escrow_account.key,
+            initializer: escrow_info.initializer_pubkey,
+            taker: *taker.key,
+            mint_x: escrow_info.mint_x_pubkey,
+            mint_y: escrow_info.mint_y_pubkey,
+            amount_x: amount_to_taker,
+            amount_y: amount_to_initializer,
+            fee,
+            remaining_amount: expected_amount.saturating_sub(amount_to_initializer),
+        }
+        .emit();
+        drop(escrow_data);
+
+        if amount_to_initializer < expected_amount {
+            msg!("Partially filled, keeping the escrow open for the remainder...");
+            let remaining_amount = expected_amount - amount_to_initializer;
+            if is_v0 {
+                let mut escrow_info = EscrowV0::unpack(&escrow_account.data.borrow())?;
+                escrow_info.expected_amount = remaining_amount;
+                EscrowV0::pack(escrow_info, &mut escrow_account.data.borrow_mut())?;
+            } else {
+                EscrowData::load_mut(&mut escrow_account.data.borrow_mut())?
+                    .set_expected_amount(remaining_amount);
+            }
+            return Ok(());
+        }
 
         // -----------------
         // Cleanup
         // -----------------
 
-        let close_pdas_tmp_acc_ix = spl_token::instruction::close_account(
-            token_program.key,
-            // account_pubkey
-            pdas_tmp_token_account.key,
-            // destination_pubkey
-            initializers_main_account.key,
-            // owner_pubkey
-            &pda,
-            // signer_pubkeys
-            &[&pda],
-        )?;
-        msg!("Calling the token program to close pda's temp account...");
-        invoke_signed(
-            &close_pdas_tmp_acc_ix,
-            &[
-                pdas_tmp_token_account.clone(),
-                initializers_main_account.clone(),
-                pda_account.clone(),
-                token_program.clone(),
-            ],
-            &[&[&b"escrow"[..], &[bump_seed]]],
-        )?;
-
-        msg!("Closing the escrow account...");
-        // Move remaining lamports from escrow to Alice and remove escrow data
-        **initializers_main_account.lamports.borrow_mut() = initializers_main_account
-            .lamports()
-            .checked_add(escrow_account.lamports())
-            .ok_or(EscrowError::AmountOverflow)?;
-        **escrow_account.lamports.borrow_mut() = 0;
-        *escrow_account.data.borrow_mut() = &mut [];
+        if !offers_lamports {
+            Self::close_pdas_tmp_account(
+                token_program,
+                mint_x,
+                pdas_tmp_token_account,
+                initializers_main_account,
+                pda_account,
+                pda_seeds,
+            )?;
+        }
 
-        Ok(())
+        Self::close_escrow_account(escrow_account, initializers_main_account)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use solana_program::sysvar;
-
-    use crate::test_utils::EscrowAccounts;
+    /// Returns the tokens held in the tmp token account to the initializer (Alice) and closes the
+    /// escrow.
+    /// Alice may cancel at any time by signing, anyone else may reclaim the tokens for her once the
+    /// escrow expired.
+    fn process_return_deposit(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        is_reclaim: bool,
+    ) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
 
-    use super::*;
-    use solana_sdk::account::{create_account_for_test, Account};
+        // 1. initializer (account 0) Alice
+        let initializer = next_account_info(account_iter)?;
+        if !is_reclaim && !initializer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
 
-    #[test]
-    fn init_escrow() {
-        // -----------------
-        // Signer
-        // -----------------
-        let initializer_pkey = &Pubkey::new_unique();
-        let initializer_lamp = &mut 0;
-        let initializer_data = &mut [0u8];
+        // 2. PDA's tmp token account to return tokens from, which we'll close at the end (account 1)
+        let pdas_tmp_token_account = next_account_info(account_iter)?;
 
-        // -----------------
-        // Alice Token X tmp
-        // -----------------
-        let tmp_pkey = &Pubkey::new_unique();
-        let tmp_lamp = &mut 0;
-        let tmp_data = &mut [0u8];
+        // 3. initializer's token account that gets the tokens back (account 2) Alice X
+        let initializers_token_to_return_account = next_account_info(account_iter)?;
 
-        // -----------------
-        // Initializer's Token Account
-        // -----------------
-        let tok_to_receive_pkey = &Pubkey::new_unique();
-        let tok_to_receive_lamp = &mut 0;
-        let tok_to_receive_data = &mut [0u8];
-        let tok_to_receive_owner = &spl_token::id();
+        // 4. escrow account holding the escrow info (account 3)
+        let escrow_account = next_account_info(account_iter)?;
+        if escrow_account.owner != program_id {
+            return Err(EscrowError::InvalidEscrowOwner.into());
+        }
+        let is_v0 = escrow_account.data_len() == EscrowV0::LEN;
+        let escrow_info = if is_v0 {
+            Self::unpack_v0_escrow(
+                program_id,
+                &escrow_account.data.borrow(),
+                pdas_tmp_token_account,
+                None,
+            )?
+        } else {
+            compute! { "unpack escrow" =>
+            let escrow_info = Escrow::unpack(&escrow_account.data.borrow())?;
+            }
+            escrow_info
+        };
 
-        // -----------------
-        // Escrow Account
-        // -----------------
-        // Defaulting to create essentially empty escrow which is filled when processed
-        let escrow_pkey = &Pubkey::new_unique();
-        let escrow_data = &mut [0u8; Escrow::LEN];
-        let escrow_lamp = &mut Rent::default().minimum_balance(Escrow::LEN);
+        // Only the initializer may cancel the trade and only the initializer gets tokens and rent
+        if escrow_info.initializer_pubkey != *initializer.key {
+            return Err(EscrowError::EscrowAccountMismatch.into());
+        }
+        if escrow_info.tmp_token_account_pubkey != *pdas_tmp_token_account.key {
+            return Err(EscrowError::EscrowAccountMismatch.into());
+        }
+
+        if is_reclaim && !escrow_info.is_expired(Clock::get()?.unix_timestamp) {
+            return Err(EscrowError::EscrowNotExpired.into());
+        }
+
+        let cancelled = |amount_x| EscrowEvent::Cancelled {
+            escrow: *escrow_account.key,
+            initializer: escrow_info.initializer_pubkey,
+            mint_x: escrow_info.mint_x_pubkey,
+            amount_x,
+            reclaimed: is_reclaim,
+        };
+
+        // The lamports Alice offered are held by the escrow account itself
+        if escrow_info.offers_lamports() {
+            cancelled(Self::lamports_deposit(escrow_account)?).emit();
+            return Self::close_escrow_account(escrow_account, initializer);
+        }
+
+        if is_reclaim {
+            // Alice didn't pick the account to return tokens to herself, so it has to be hers
+            let initializers_token_to_return_account_info =
+                token::unpack_account(initializers_token_to_return_account)?;
+            if initializers_token_to_return_account_info.owner != escrow_info.initializer_pubkey {
+                return Err(EscrowError::InvalidTokenAccountOwner.into());
+            }
+        }
+        let pdas_tmp_token_account_info = token::unpack_account(pdas_tmp_token_account)?;
+
+        // v0 escrows share a single PDA
+        let bump_seed = [escrow_info.bump_seed];
+        let seeds = [Escrow::PDA_SEED, escrow_account.key.as_ref(), &bump_seed];
+        let v0_seeds = [Escrow::PDA_SEED, &bump_seed];
+        let pda_seeds: &[&[u8]] = if is_v0 { &v0_seeds } else { &seeds };
+        let pda = Pubkey::create_program_address(pda_seeds, program_id)?;
+
+        // 5. Token Program (account 4)
+        let token_program = next_account_info(account_iter)?;
+        token::check_token_program(token_program)?;
+
+        // 6. PDA account (account 5)
+        let pda_account = next_account_info(account_iter)?;
+
+        // 7. mint of token X (account 6)
+        let mint_x = next_account_info(account_iter)?;
+        let mint_x_decimals = if is_v0 {
+            // v0 escrows didn't record the decimals
+            Self::mint_decimals(mint_x, &escrow_info.mint_x_pubkey)?
+        } else if *mint_x.key != escrow_info.mint_x_pubkey {
+            return Err(EscrowError::MintMismatch.into());
+        } else {
+            escrow_info.mint_x_decimals
+        };
 
         // -----------------
-        // Rent account
+        // Transfers
         // -----------------
-        let rent_pkey = &sysvar::rent::id();
-        let rent_acc = &mut rent_sysvar();
+
+        // Transfer Alice's X stored in tmp account during init back to Alice's X
+        let transfer_to_initializer_ix = token::transfer_checked(
+            token_program.key,
+            // source
+            pdas_tmp_token_account.key,
+            // mint
+            mint_x.key,
+            // destination
+            initializers_token_to_return_account.key,
+            // authority
+            &pda,
+            pdas_tmp_token_account_info.amount,
+            mint_x_decimals,
+        )?;
+        msg!("Calling the token program to return tokens to the escrow's initializer...");
+        invoke_signed(
+            &transfer_to_initializer_ix,
+            &[
+                pdas_tmp_token_account.clone(),
+                mint_x.clone(),
+                initializers_token_to_return_account.clone(),
+                pda_account.clone(),
+                token_program.clone(),
+            ],
+            &[pda_seeds],
+        )?;
+        cancelled(pdas_tmp_token_account_info.amount).emit();
 
         // -----------------
-        // Token Program account
+        // Cleanup
         // -----------------
-        let token_program_pkey = &spl_token::id();
-        let token_program_lamp = &mut 0;
-        let token_program_data = &mut [0u8];
-
-        let escrow_accounts = EscrowAccounts::new(
-            initializer_pkey,
-            initializer_lamp,
-            initializer_data,
-            tmp_pkey,
-            tmp_lamp,
-            tmp_data,
-            tok_to_receive_pkey,
-            tok_to_receive_lamp,
-            tok_to_receive_data,
-            tok_to_receive_owner,
-            escrow_pkey,
-            escrow_lamp,
-            escrow_data,
-            rent_pkey,
-            rent_acc,
-            token_program_pkey,
-            token_program_lamp,
-            token_program_data,
-        );
-
-        let infos = &escrow_accounts.account_infos();
-        let program_id = Pubkey::new_unique();
-
-        let init_escrow = EscrowInstruction::InitEscrow { amount: 10 };
+
+        Self::close_pdas_tmp_account(
+            token_program,
+            mint_x,
+            pdas_tmp_token_account,
+            initializer,
+            pda_account,
+            pda_seeds,
+        )?;
+
+        Self::close_escrow_account(escrow_account, initializer)
+    }
+
+    /// Copies an escrow of the first release into a new escrow account in the current layout and
+    /// hands its tmp token account over from the PDA shared by v0 escrows to the new escrow's PDA.
+    /// Programs built against this runtime can't resize accounts, thus the v0 escrow account is
+    /// closed instead of being rewritten in place.
+    fn process_migrate_escrow(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+
+        // 1. initializer (account 0) Alice
+        let initializer = next_account_info(account_iter)?;
+        if !initializer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        // 2. escrow account in the v0 layout (account 1)
+        let escrow_account = next_account_info(account_iter)?;
+        if escrow_account.owner != program_id {
+            return Err(EscrowError::InvalidEscrowOwner.into());
+        }
+        if escrow_account.data_len() != EscrowV0::LEN {
+            return Err(EscrowError::InvalidEscrowSize.into());
+        }
+        let escrow_info = EscrowV0::unpack(&escrow_account.data.borrow())?;
+        if escrow_info.initializer_pubkey != *initializer.key {
+            return Err(EscrowError::EscrowAccountMismatch.into());
+        }
+
+        // 3. new escrow account (account 2)
+        let new_escrow_account = next_account_info(account_iter)?;
+
+        // 4. PDA's tmp token account (account 3) Alice Token X
+        let pdas_tmp_token_account = next_account_info(account_iter)?;
+        if escrow_info.tmp_token_account_pubkey != *pdas_tmp_token_account.key {
+            return Err(EscrowError::EscrowAccountMismatch.into());
+        }
+        let pdas_tmp_token_account_info = token::unpack_account(pdas_tmp_token_account)?;
+
+        // 5. initializer's token account that will receive tokens (account 4) Alice Token Y
+        let initializers_token_to_receive_account = next_account_info(account_iter)?;
+        if escrow_info.initializer_token_to_receive_account_pubkey
+            != *initializers_token_to_receive_account.key
+        {
+            return Err(EscrowError::EscrowAccountMismatch.into());
+        }
+        let token_to_receive_mint =
+            Self::unpack_token_to_receive_account(initializers_token_to_receive_account)?.mint;
+
+        // 6. token program (account 5)
+        let token_program = next_account_info(account_iter)?;
+        token::check_token_program(token_program)?;
+
+        // 7. PDA shared by v0 escrows (account 6)
+        let pda_account = next_account_info(account_iter)?;
+        let (v0_pda, v0_bump_seed) = Self::v0_pda(program_id);
+        if *pda_account.key != v0_pda {
+            return Err(EscrowError::InvalidPdaAccount.into());
+        }
+
+        // 8. mint of token X (account 7)
+        let mint_x = next_account_info(account_iter)?;
+        let mint_x_decimals = Self::mint_decimals(mint_x, &pdas_tmp_token_account_info.mint)?;
+
+        // 9. mint of token Y (account 8)
+        let mint_y = next_account_info(account_iter)?;
+        let mint_y_decimals = Self::mint_decimals(mint_y, &token_to_receive_mint)?;
+
+        // v0 didn't record the mints, those of the escrow's token accounts are the ones traded
+        let rent = Rent::get()?;
+        let pda = Self::init_escrow_account(
+            program_id,
+            new_escrow_account,
+            &rent,
+            pdas_tmp_token_account_info.amount,
+            escrow_info.upgrade(
+                pdas_tmp_token_account_info.mint,
+                mint_x_decimals,
+                token_to_receive_mint,
+                mint_y_decimals,
+            ),
+        )?;
+
+        let owner_change_ix = token::for_token_program(
+            set_authority(
+                &spl_token::id(),
+                pdas_tmp_token_account.key,
+                Some(&pda),
+                AuthorityType::AccountOwner,
+                pda_account.key,
+                &[pda_account.key],
+            ),
+            token_program.key,
+        )?;
+        msg!("Calling the token program to transfer token account ownership to the new escrow...");
+        invoke_signed(
+            &owner_change_ix,
+            &[
+                pdas_tmp_token_account.clone(),
+                pda_account.clone(),
+                token_program.clone(),
+            ],
+            &[&[Escrow::PDA_SEED, &[v0_bump_seed]]],
+        )?;
+
+        Self::close_escrow_account(escrow_account, initializer)
+    }
+
+    /// PDA which has authority over the tmp token accounts of all v0 escrows, see [EscrowV0]
+    fn v0_pda(program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[Escrow::PDA_SEED], program_id)
+    }
+
+    /// Unpacks a v0 escrow as an escrow in the current layout, see [EscrowV0::upgrade].
+    /// v0 didn't record the mints, those of the escrow's token accounts are the ones traded.
+    /// Without Alice's Y account mint Y is [EscrowV0::UNKNOWN_MINT], only Exchange needs it.
+    /// The decimals are left at zero, they have to be read from the mint accounts.
+    fn unpack_v0_escrow(
+        program_id: &Pubkey,
+        data: &[u8],
+        pdas_tmp_token_account: &AccountInfo,
+        initializers_token_to_receive_account: Option<&AccountInfo>,
+    ) -> Result<Escrow, ProgramError> {
+        let escrow_info = EscrowV0::unpack(data)?;
+        let mint_x = token::unpack_account(pdas_tmp_token_account)?.mint;
+        let mint_y = match initializers_token_to_receive_account {
+            Some(account) => Self::unpack_token_to_receive_account(account)?.mint,
+            None => EscrowV0::UNKNOWN_MINT,
+        };
+        let mut escrow = escrow_info.upgrade(mint_x, 0, mint_y, 0);
+        escrow.bump_seed = Self::v0_pda(program_id).1;
+        Ok(escrow)
+    }
+
+    fn process_init_config(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        fee_basis_points: u16,
+    ) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+
+        // 1. admin (account 0)
+        let admin = next_account_info(account_iter)?;
+        if !admin.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        // 2. config account (account 1)
+        let config_account = next_account_info(account_iter)?;
+        let (config_pda, bump_seed) = Pubkey::find_program_address(&[Config::PDA_SEED], program_id);
+        if config_pda != *config_account.key {
+            return Err(EscrowError::InvalidConfigAccount.into());
+        }
+
+        // 3. treasury (account 2)
+        let treasury = next_account_info(account_iter)?;
+
+        // 4. system program (account 3)
+        let system_program = next_account_info(account_iter)?;
+
+        // 5. program data account of this program (account 4)
+        // - only the upgrade authority may become admin, otherwise anyone could front-run the
+        //   deployment and take over the fees
+        let program_data = next_account_info(account_iter)?;
+        if Self::upgrade_authority(program_id, program_data)? != Some(*admin.key) {
+            return Err(EscrowError::UnauthorizedAdmin.into());
+        }
+
+        if fee_basis_points > Config::MAX_FEE_BASIS_POINTS {
+            return Err(EscrowError::InvalidFee.into());
+        }
+
+        // Fails if the config account was created before
+        let rent = Rent::get()?;
+        let create_config_acc_ix = system_instruction::create_account(
+            // from_pubkey
+            admin.key,
+            // to_pubkey
+            config_account.key,
+            // lamports
+            rent.minimum_balance(Config::LEN),
+            // space
+            Config::LEN as u64,
+            // owner
+            program_id,
+        );
+        msg!("Calling the system program to create the config account...");
+        invoke_signed(
+            &create_config_acc_ix,
+            &[
+                admin.clone(),
+                config_account.clone(),
+                system_program.clone(),
+            ],
+            &[&[Config::PDA_SEED, &[bump_seed]]],
+        )?;
+
+        let config = Config {
+            is_initialized: true,
+            admin_pubkey: *admin.key,
+            fee_basis_points,
+            treasury_pubkey: *treasury.key,
+            bump_seed,
+            treasury_accounts: Default::default(),
+        };
+        Config::pack(config, &mut config_account.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn process_update_config(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        fee_basis_points: u16,
+    ) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+
+        // 1. admin (account 0)
+        let admin = next_account_info(account_iter)?;
+        if !admin.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        // 2. config account (account 1)
+        let config_account = next_account_info(account_iter)?;
+        let mut config = Self::unpack_config(program_id, config_account)?;
+        if config.admin_pubkey != *admin.key {
+            return Err(EscrowError::UnauthorizedAdmin.into());
+        }
+
+        // 3. new admin (account 2)
+        let new_admin = next_account_info(account_iter)?;
+
+        // 4. treasury (account 3)
+        let treasury = next_account_info(account_iter)?;
+
+        if fee_basis_points > Config::MAX_FEE_BASIS_POINTS {
+            return Err(EscrowError::InvalidFee.into());
+        }
+
+        // The token accounts of the previous treasury have to be replaced as well
+        if config.treasury_pubkey != *treasury.key {
+            config.treasury_accounts = Default::default();
+        }
+        config.admin_pubkey = *new_admin.key;
+        config.fee_basis_points = fee_basis_points;
+        config.treasury_pubkey = *treasury.key;
+        Config::pack(config, &mut config_account.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn process_set_treasury_account(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+
+        // 1. admin (account 0)
+        let admin = next_account_info(account_iter)?;
+        if !admin.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        // 2. config account (account 1)
+        let config_account = next_account_info(account_iter)?;
+        let mut config = Self::unpack_config(program_id, config_account)?;
+        if config.admin_pubkey != *admin.key {
+            return Err(EscrowError::UnauthorizedAdmin.into());
+        }
+
+        // 3. treasury's token account (account 2)
+        let treasury_token_account = next_account_info(account_iter)?;
+        let treasury_token_account_info = token::unpack_account(treasury_token_account)?;
+        if treasury_token_account_info.owner != config.treasury_pubkey {
+            return Err(EscrowError::InvalidTreasuryAccount.into());
+        }
+
+        config.set_treasury_account(
+            &treasury_token_account_info.mint,
+            treasury_token_account.key,
+        )?;
+        Config::pack(config, &mut config_account.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    /// Upgrade authority of this program as recorded in its ProgramData account by the upgradeable
+    /// BPF loader, `None` once the program was made immutable
+    fn upgrade_authority(
+        program_id: &Pubkey,
+        program_data: &AccountInfo,
+    ) -> Result<Option<Pubkey>, ProgramError> {
+        let (program_data_address, _) =
+            Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+        if *program_data.key != program_data_address
+            || *program_data.owner != bpf_loader_upgradeable::id()
+        {
+            return Err(EscrowError::InvalidProgramDataAccount.into());
+        }
+
+        // UpgradeableLoaderState::ProgramData as serialized by bincode, followed by the program:
+        // u32 variant, u64 slot, Option<Pubkey> upgrade authority
+        let data = program_data.data.borrow();
+        let metadata = data
+            .get(..PROGRAM_DATA_METADATA_LEN)
+            .ok_or(EscrowError::InvalidProgramDataAccount)?;
+        let (variant, _slot, has_authority, authority) = array_refs![
+            array_ref![metadata, 0, PROGRAM_DATA_METADATA_LEN],
+            4,
+            8,
+            1,
+            32
+        ];
+        if u32::from_le_bytes(*variant) != PROGRAM_DATA_VARIANT {
+            return Err(EscrowError::InvalidProgramDataAccount.into());
+        }
+        match has_authority {
+            [0] => Ok(None),
+            [1] => Ok(Some(Pubkey::new_from_array(*authority))),
+            _ => Err(EscrowError::InvalidProgramDataAccount.into()),
+        }
+    }
+
+    /// Extracts the [Config] from the config account after verifying that it is the program's
+    /// config PDA
+    fn unpack_config(
+        program_id: &Pubkey,
+        config_account: &AccountInfo,
+    ) -> Result<Config, ProgramError> {
+        if config_account.owner != program_id {
+            return Err(EscrowError::InvalidConfigAccount.into());
+        }
+        let config = Config::unpack(&config_account.data.borrow())?;
+        let config_pda =
+            Pubkey::create_program_address(&[Config::PDA_SEED, &[config.bump_seed]], program_id)?;
+        if config_pda != *config_account.key {
+            return Err(EscrowError::InvalidConfigAccount.into());
+        }
+        Ok(config)
+    }
+
+    /// Like [Self::unpack_config] but `None` if the config PDA wasn't initialized yet, which is
+    /// only looked up then so that exchanges don't pay for the derivation
+    fn unpack_optional_config(
+        program_id: &Pubkey,
+        config_account: &AccountInfo,
+    ) -> Result<Option<Config>, ProgramError> {
+        if config_account.owner == program_id {
+            return Self::unpack_config(program_id, config_account).map(Some);
+        }
+        let (config_pda, _) = Pubkey::find_program_address(&[Config::PDA_SEED], program_id);
+        if config_pda != *config_account.key {
+            return Err(EscrowError::InvalidConfigAccount.into());
+        }
+        Ok(None)
+    }
+
+    /// Checks that the account is a token account of the given mint
+    fn check_token_account_mint(token_account: &AccountInfo, mint: &Pubkey) -> ProgramResult {
+        let token_account_info = token::unpack_account(token_account)?;
+        if token_account_info.mint != *mint {
+            return Err(EscrowError::MintMismatch.into());
+        }
+        Ok(())
+    }
+
+    /// Decimals of the mint after verifying that it is the `expected_mint`
+    fn mint_decimals(mint: &AccountInfo, expected_mint: &Pubkey) -> Result<u8, ProgramError> {
+        if mint.key != expected_mint {
+            return Err(EscrowError::MintMismatch.into());
+        }
+        Ok(token::unpack_mint(mint)?.decimals)
+    }
+
+    /// Lamports Alice offered, everything the escrow account holds above its rent exempt minimum
+    fn lamports_deposit(escrow_account: &AccountInfo) -> Result<u64, ProgramError> {
+        let rent = Rent::get()?;
+        escrow_account
+            .lamports()
+            .checked_sub(rent.minimum_balance(escrow_account.data_len()))
+            .ok_or_else(|| EscrowError::NotRentExempt.into())
+    }
+
+    /// Transfers `amount` of what Bob pays from his account, either tokens of `mint` with the
+    /// given `decimals` or, when the `system_program` is provided, lamports
+    #[allow(clippy::too_many_arguments)]
+    fn transfer_from_taker<'a>(
+        token_program: &AccountInfo<'a>,
+        system_program: Option<&AccountInfo<'a>>,
+        mint: &AccountInfo<'a>,
+        decimals: u8,
+        source: &AccountInfo<'a>,
+        destination: &AccountInfo<'a>,
+        taker: &AccountInfo<'a>,
+        amount: u64,
+    ) -> ProgramResult {
+        match system_program {
+            Some(system_program) => invoke(
+                &system_instruction::transfer(source.key, destination.key, amount),
+                &[source.clone(), destination.clone(), system_program.clone()],
+            ),
+            None => {
+                let transfer_ix = token::transfer_checked(
+                    token_program.key,
+                    source.key,
+                    mint.key,
+                    destination.key,
+                    taker.key,
+                    amount,
+                    decimals,
+                )?;
+                invoke(
+                    &transfer_ix,
+                    &[
+                        source.clone(),
+                        mint.clone(),
+                        destination.clone(),
+                        taker.clone(),
+                        token_program.clone(),
+                    ],
+                )
+            }
+        }
+    }
+
+    /// Closes the PDA's tmp token account, sending its rent to `destination`.
+    /// Token-2022 refuses to close accounts which still hold withheld transfer fees, so those are
+    /// harvested to the mint first.
+    fn close_pdas_tmp_account<'a>(
+        token_program: &AccountInfo<'a>,
+        mint: &AccountInfo<'a>,
+        pdas_tmp_token_account: &AccountInfo<'a>,
+        destination: &AccountInfo<'a>,
+        pda_account: &AccountInfo<'a>,
+        pda_seeds: &[&[u8]],
+    ) -> ProgramResult {
+        if token::has_transfer_fee(mint) {
+            let harvest_ix = token::harvest_withheld_tokens_to_mint(
+                token_program.key,
+                mint.key,
+                &[pdas_tmp_token_account.key],
+            );
+            msg!("Calling the token program to harvest withheld fees of pda's temp account...");
+            invoke(
+                &harvest_ix,
+                &[
+                    mint.clone(),
+                    pdas_tmp_token_account.clone(),
+                    token_program.clone(),
+                ],
+            )?;
+        }
+
+        let close_pdas_tmp_acc_ix = token::for_token_program(
+            spl_token::instruction::close_account(
+                &spl_token::id(),
+                // account_pubkey
+                pdas_tmp_token_account.key,
+                // destination_pubkey
+                destination.key,
+                // owner_pubkey
+                pda_account.key,
+                // signer_pubkeys
+                &[pda_account.key],
+            ),
+            token_program.key,
+        )?;
+        msg!("Calling the token program to close pda's temp account...");
+        invoke_signed(
+            &close_pdas_tmp_acc_ix,
+            &[
+                pdas_tmp_token_account.clone(),
+                destination.clone(),
+                pda_account.clone(),
+                token_program.clone(),
+            ],
+            &[pda_seeds],
+        )
+    }
+
+    /// Moves lamports out of an account owned by this program
+    fn transfer_lamports(
+        source: &AccountInfo,
+        destination: &AccountInfo,
+        amount: u64,
+    ) -> ProgramResult {
+        **source.lamports.borrow_mut() = source
+            .lamports()
+            .checked_sub(amount)
+            .ok_or(EscrowError::AmountOverflow)?;
+        **destination.lamports.borrow_mut() = destination
+            .lamports()
+            .checked_add(amount)
+            .ok_or(EscrowError::AmountOverflow)?;
+        Ok(())
+    }
+
+    /// Moves remaining lamports from the escrow account to the initializer (Alice) and clears the
+    /// escrow data.
+    /// The runtime doesn't allow changing the size of the account data, but it purges accounts
+    /// without lamports once the transaction completes. Until then the account still exists, an
+    /// instruction later in the same transaction could even fund it again, so the data is zeroed
+    /// to leave an uninitialized escrow behind rather than one that can still be taken.
+    fn close_escrow_account(
+        escrow_account: &AccountInfo,
+        initializers_main_account: &AccountInfo,
+    ) -> ProgramResult {
+        msg!("Closing the escrow account...");
+        **initializers_main_account.lamports.borrow_mut() = initializers_main_account
+            .lamports()
+            .checked_add(escrow_account.lamports())
+            .ok_or(EscrowError::AmountOverflow)?;
+        **escrow_account.lamports.borrow_mut() = 0;
+        escrow_account.data.borrow_mut().fill(0);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{set_rent_syscall_stubs, EscrowFixture, TestAccount};
+
+    use super::*;
+
+    /// Processes InitEscrow with the accounts of the fixture
+    fn process_init_escrow(fixture: &mut EscrowFixture) -> ProgramResult {
+        set_rent_syscall_stubs();
+
+        let init_escrow = EscrowInstruction::InitEscrow {
+            amount: 10,
+            expires_at: None,
+            allowed_taker: None,
+        };
         let init_escrow_ix = init_escrow.pack();
 
-        Processor::process(&program_id, infos, &init_escrow_ix)
-            .expect("Program should have processed fine");
+        let program_id = fixture.program_id;
+        Processor::process(
+            &program_id,
+            &fixture.init_escrow_accounts(),
+            &init_escrow_ix,
+        )
+    }
+
+    #[test]
+    fn init_escrow() {
+        let mut fixture = EscrowFixture::default();
+
+        process_init_escrow(&mut fixture).expect("Program should have processed fine");
+
+        let escrow = Escrow::unpack(&fixture.escrow_account.data).expect("Escrow is initialized");
+        assert_eq!(escrow.expected_amount, 10);
+        assert_eq!(escrow.mint_x_decimals, 6);
+        assert_eq!(escrow.mint_y_decimals, 9);
     }
 
     #[test]
     fn init_escrow_invalid_signer() {
-        // -----------------
-        // Signer
-        // -----------------
-        let initializer_pkey = &Pubkey::new_unique();
-        let initializer_lamp = &mut 0;
-        let initializer_data = &mut [0u8];
+        let mut fixture = EscrowFixture::default().with_signer(false);
 
-        // -----------------
-        // Alice Token X tmp
-        // -----------------
-        let tmp_pkey = &Pubkey::new_unique();
-        let tmp_lamp = &mut 0;
-        let tmp_data = &mut [0u8];
+        assert_eq!(
+            process_init_escrow(&mut fixture),
+            Err(ProgramError::MissingRequiredSignature),
+            "Detects missing signature"
+        );
+    }
 
-        // -----------------
-        // Initializer's Token Account
-        // -----------------
-        let tok_to_receive_pkey = &Pubkey::new_unique();
-        let tok_to_receive_lamp = &mut 0;
-        let tok_to_receive_data = &mut [0u8];
-        let tok_to_receive_owner = &spl_token::id();
+    #[test]
+    fn init_escrow_foreign_escrow_account() {
+        let mut fixture = EscrowFixture::default().with_escrow_owner(Pubkey::new_unique());
 
-        // -----------------
-        // Escrow Account
-        // -----------------
-        // Defaulting to create essentially empty escrow which is filled when processed
-        let escrow_pkey = &Pubkey::new_unique();
-        let escrow_data = &mut [0u8; Escrow::LEN];
-        let escrow_lamp = &mut Rent::default().minimum_balance(Escrow::LEN);
+        assert_eq!(
+            process_init_escrow(&mut fixture),
+            Err(EscrowError::InvalidEscrowOwner.into()),
+            "Detects escrow account owned by another program"
+        );
+    }
 
-        // -----------------
-        // Rent account
-        // -----------------
-        let rent_pkey = &sysvar::rent::id();
-        let rent_acc = &mut rent_sysvar();
+    #[test]
+    fn init_escrow_short_escrow_account() {
+        let mut fixture = EscrowFixture::default().with_escrow_len(Escrow::LEN - 1);
 
-        // -----------------
-        // Token Program account
-        // -----------------
-        let token_program_pkey = &spl_token::id();
-        let token_program_lamp = &mut 0;
-        let token_program_data = &mut [0u8];
-
-        let mut escrow_accounts = EscrowAccounts::new(
-            initializer_pkey,
-            initializer_lamp,
-            initializer_data,
-            tmp_pkey,
-            tmp_lamp,
-            tmp_data,
-            tok_to_receive_pkey,
-            tok_to_receive_lamp,
-            tok_to_receive_data,
-            tok_to_receive_owner,
-            escrow_pkey,
-            escrow_lamp,
-            escrow_data,
-            rent_pkey,
-            rent_acc,
-            token_program_pkey,
-            token_program_lamp,
-            token_program_data,
-        );
-
-        let program_id = Pubkey::new_unique();
-
-        let init_escrow = EscrowInstruction::InitEscrow { amount: 10 };
-        let init_escrow_ix = init_escrow.pack();
+        assert_eq!(
+            process_init_escrow(&mut fixture),
+            Err(EscrowError::InvalidEscrowSize.into()),
+            "Detects escrow account too small to hold the escrow state"
+        );
+    }
+
+    #[test]
+    fn cancel_escrow_not_initializer() {
+        let mut fixture = EscrowFixture::default().initialized();
+        fixture.initializer = TestAccount::signer();
+
+        let cancel_escrow_ix = EscrowInstruction::CancelEscrow.pack();
+
+        let program_id = fixture.program_id;
+        assert_eq!(
+            Processor::process(
+                &program_id,
+                &fixture.cancel_escrow_accounts(),
+                &cancel_escrow_ix
+            ),
+            Err(EscrowError::EscrowAccountMismatch.into()),
+            "Only the initializer can cancel"
+        );
+    }
+
+    #[test]
+    fn cancel_escrow_foreign_escrow_account() {
+        let mut fixture = EscrowFixture::default()
+            .initialized()
+            .with_escrow_owner(Pubkey::new_unique());
+
+        let cancel_escrow_ix = EscrowInstruction::CancelEscrow.pack();
+
+        let program_id = fixture.program_id;
+        assert_eq!(
+            Processor::process(
+                &program_id,
+                &fixture.cancel_escrow_accounts(),
+                &cancel_escrow_ix
+            ),
+            Err(EscrowError::InvalidEscrowOwner.into()),
+            "Rejects escrows owned by another program, even with a valid discriminator"
+        );
+    }
+
+    #[test]
+    fn close_escrow_account_clears_data() {
+        let mut fixture = EscrowFixture::default().initialized();
+        let escrow_lamports = fixture.escrow_account.lamports;
+        let initializer_lamports = fixture.initializer.lamports;
 
-        // Make signer invalic
-        escrow_accounts.initializer_acc.is_signer = false;
-        let infos = &escrow_accounts.account_infos();
+        Processor::close_escrow_account(
+            &fixture.escrow_account.account_info(),
+            &fixture.initializer.account_info(),
+        )
+        .unwrap();
 
+        assert_eq!(fixture.escrow_account.lamports, 0);
         assert_eq!(
-            Processor::process(&program_id, infos, &init_escrow_ix),
+            fixture.initializer.lamports,
+            initializer_lamports + escrow_lamports
+        );
+        assert_eq!(fixture.escrow_account.data, vec![0u8; Escrow::LEN]);
+        assert_eq!(
+            EscrowData::load(&fixture.escrow_account.data).err(),
+            Some(ProgramError::UninitializedAccount),
+            "A closed escrow can't be taken, even before the runtime purges it"
+        );
+    }
+
+    fn exchange_ix() -> Vec<u8> {
+        EscrowInstruction::Exchange {
+            amount: EscrowFixture::X_AMOUNT,
+        }
+        .pack()
+    }
+
+    #[test]
+    fn exchange_fake_token_program() {
+        let mut fixture = EscrowFixture::default().initialized();
+        fixture.token_program.key = Pubkey::new_unique();
+
+        let program_id = fixture.program_id;
+        assert_eq!(
+            Processor::process(&program_id, &fixture.exchange_accounts(), &exchange_ix()),
+            Err(EscrowError::InvalidTokenProgram.into()),
+            "Rejects a token program impostor"
+        );
+    }
+
+    #[test]
+    fn exchange_fake_pda() {
+        let mut fixture = EscrowFixture::default().initialized();
+        fixture.pda_account.key = Pubkey::new_unique();
+
+        let program_id = fixture.program_id;
+        assert_eq!(
+            Processor::process(&program_id, &fixture.exchange_accounts(), &exchange_ix()),
+            Err(EscrowError::InvalidPdaAccount.into()),
+            "Rejects a PDA not derived from the escrow account"
+        );
+    }
+
+    #[test]
+    fn exchange_pda_of_other_escrow() {
+        let mut fixture = EscrowFixture::default().initialized();
+        let other_escrow = Pubkey::new_unique();
+        fixture.pda_account.key = Pubkey::find_program_address(
+            &[Escrow::PDA_SEED, other_escrow.as_ref()],
+            &fixture.program_id,
+        )
+        .0;
+
+        let program_id = fixture.program_id;
+        assert_eq!(
+            Processor::process(&program_id, &fixture.exchange_accounts(), &exchange_ix()),
+            Err(EscrowError::InvalidPdaAccount.into()),
+            "Rejects the PDA of another escrow"
+        );
+    }
+
+    #[test]
+    fn exchange_other_mint() {
+        let mut fixture = EscrowFixture::default().initialized();
+        fixture.mint_x = TestAccount::mint(6);
+
+        let program_id = fixture.program_id;
+        assert_eq!(
+            Processor::process(&program_id, &fixture.exchange_accounts(), &exchange_ix()),
+            Err(EscrowError::MintMismatch.into()),
+            "Rejects a mint other than the escrow's token X"
+        );
+    }
+
+    #[test]
+    fn exchange_invalid_signer() {
+        let mut fixture = EscrowFixture::default().initialized().with_signer(false);
+
+        let program_id = fixture.program_id;
+        assert_eq!(
+            Processor::process(&program_id, &fixture.exchange_accounts(), &exchange_ix()),
             Err(ProgramError::MissingRequiredSignature),
-            "Detects missing signature"
+            "Bob has to sign"
+        );
+    }
+
+    #[test]
+    fn exchange_paying_other_account() {
+        let mut fixture = EscrowFixture::default().initialized();
+        fixture.initializers_token_to_receive_account =
+            TestAccount::token_account(fixture.mint_y.key, fixture.taker.key, 0);
+
+        let program_id = fixture.program_id;
+        assert_eq!(
+            Processor::process(&program_id, &fixture.exchange_accounts(), &exchange_ix()),
+            Err(EscrowError::EscrowAccountMismatch.into()),
+            "Bob can only pay into the account Alice recorded"
+        );
+    }
+
+    #[test]
+    fn exchange_v0_escrow() {
+        let mut fixture = EscrowFixture::default().initialized_v0();
+
+        // Without a system program account Exchange would fail if it took the escrow for one
+        // expecting lamports
+        let program_id = fixture.program_id;
+        Processor::process(&program_id, &fixture.exchange_accounts(), &exchange_ix()).unwrap();
+        assert_eq!(
+            fixture.escrow_account.data,
+            vec![0u8; EscrowV0::LEN],
+            "Closes the v0 escrow"
+        );
+    }
+
+    #[test]
+    fn unpack_v0_escrow_without_mint_y() {
+        let mut fixture = EscrowFixture::default().initialized_v0();
+
+        let escrow = Processor::unpack_v0_escrow(
+            &fixture.program_id,
+            &fixture.escrow_account.data.clone(),
+            &fixture.tmp_token_account.account_info(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(escrow.mint_x_pubkey, fixture.mint_x.key);
+        assert!(
+            !escrow.expects_lamports(),
+            "v0 escrows only ever traded tokens"
+        );
+    }
+
+    #[test]
+    fn exchange_config_as_escrow() {
+        let mut fixture = EscrowFixture::default().initialized();
+        fixture.escrow_account.data[..8].copy_from_slice(&Config::DISCRIMINATOR);
+
+        let program_id = fixture.program_id;
+        assert_eq!(
+            Processor::process(&program_id, &fixture.exchange_accounts(), &exchange_ix()),
+            Err(EscrowError::InvalidAccountDiscriminator.into()),
+            "Rejects program owned accounts of other types"
+        );
+    }
+
+    #[test]
+    fn exchange_foreign_escrow_account() {
+        let mut fixture = EscrowFixture::default()
+            .initialized()
+            .with_escrow_owner(Pubkey::new_unique());
+
+        let program_id = fixture.program_id;
+        assert_eq!(
+            Processor::process(&program_id, &fixture.exchange_accounts(), &exchange_ix()),
+            Err(EscrowError::InvalidEscrowOwner.into()),
+            "Rejects escrows owned by another program, even with a valid discriminator"
+        );
+    }
+
+    fn init_config_ix() -> Vec<u8> {
+        EscrowInstruction::InitConfig {
+            fee_basis_points: 250,
+        }
+        .pack()
+    }
+
+    #[test]
+    fn init_config_not_upgrade_authority() {
+        let mut fixture = EscrowFixture {
+            admin: TestAccount::signer(),
+            ..EscrowFixture::default()
+        };
+
+        let program_id = fixture.program_id;
+        assert_eq!(
+            Processor::process(
+                &program_id,
+                &fixture.init_config_accounts(),
+                &init_config_ix()
+            ),
+            Err(EscrowError::UnauthorizedAdmin.into()),
+            "Nobody but the upgrade authority can claim the config after the deployment"
+        );
+    }
+
+    #[test]
+    fn init_config_immutable_program() {
+        let mut fixture = EscrowFixture::default();
+        fixture.program_data = TestAccount::program_data(&fixture.program_id, None);
+
+        let program_id = fixture.program_id;
+        assert_eq!(
+            Processor::process(
+                &program_id,
+                &fixture.init_config_accounts(),
+                &init_config_ix()
+            ),
+            Err(EscrowError::UnauthorizedAdmin.into()),
+            "Programs without upgrade authority have no admin"
+        );
+    }
+
+    #[test]
+    fn init_config_fake_program_data() {
+        let mut fixture = EscrowFixture::default();
+        fixture.program_data.owner = Pubkey::new_unique();
+
+        let program_id = fixture.program_id;
+        assert_eq!(
+            Processor::process(
+                &program_id,
+                &fixture.init_config_accounts(),
+                &init_config_ix()
+            ),
+            Err(EscrowError::InvalidProgramDataAccount.into()),
+            "Rejects program data not kept by the upgradeable loader"
+        );
+    }
+
+    #[test]
+    fn init_config_program_data_of_other_program() {
+        let mut fixture = EscrowFixture::default();
+        fixture.program_data =
+            TestAccount::program_data(&Pubkey::new_unique(), Some(fixture.admin.key));
+
+        let program_id = fixture.program_id;
+        assert_eq!(
+            Processor::process(
+                &program_id,
+                &fixture.init_config_accounts(),
+                &init_config_ix()
+            ),
+            Err(EscrowError::InvalidProgramDataAccount.into()),
+            "Rejects the upgrade authority of another program"
+        );
+    }
+
+    fn update_config_ix() -> Vec<u8> {
+        EscrowInstruction::UpdateConfig {
+            fee_basis_points: 100,
+        }
+        .pack()
+    }
+
+    #[test]
+    fn update_config() {
+        let mut fixture = EscrowFixture::default();
+
+        let program_id = fixture.program_id;
+        Processor::process(
+            &program_id,
+            &fixture.update_config_accounts(),
+            &update_config_ix(),
+        )
+        .unwrap();
+
+        let config = Config::unpack(&fixture.config_account.data).unwrap();
+        assert_eq!(config.admin_pubkey, fixture.taker.key);
+        assert_eq!(config.fee_basis_points, 100);
+        assert_eq!(config.treasury_pubkey, fixture.treasury.key);
+        assert_eq!(
+            config.treasury_account(&fixture.mint_y.key),
+            Some(&fixture.treasury_token_account.key)
+        );
+    }
+
+    #[test]
+    fn update_config_other_treasury() {
+        let mut fixture = EscrowFixture {
+            treasury: TestAccount::new(solana_program::system_program::id(), vec![]),
+            ..EscrowFixture::default()
+        };
+
+        let program_id = fixture.program_id;
+        Processor::process(
+            &program_id,
+            &fixture.update_config_accounts(),
+            &update_config_ix(),
+        )
+        .unwrap();
+
+        let config = Config::unpack(&fixture.config_account.data).unwrap();
+        assert_eq!(config.treasury_pubkey, fixture.treasury.key);
+        assert_eq!(
+            config.treasury_account(&fixture.mint_y.key),
+            None,
+            "The token accounts of the previous treasury are removed"
+        );
+    }
+
+    #[test]
+    fn update_config_not_admin() {
+        let mut fixture = EscrowFixture {
+            admin: TestAccount::signer(),
+            ..EscrowFixture::default()
+        };
+
+        let program_id = fixture.program_id;
+        assert_eq!(
+            Processor::process(
+                &program_id,
+                &fixture.update_config_accounts(),
+                &update_config_ix()
+            ),
+            Err(EscrowError::UnauthorizedAdmin.into()),
+            "Only the admin can update the config"
         );
     }
 
-    fn rent_sysvar() -> Account {
-        create_account_for_test(&Rent::default())
+    fn set_treasury_account_ix() -> Vec<u8> {
+        EscrowInstruction::SetTreasuryAccount.pack()
+    }
+
+    #[test]
+    fn set_treasury_account() {
+        let mut fixture = EscrowFixture::default();
+        let mint = TestAccount::mint(6);
+        fixture.treasury_token_account =
+            TestAccount::token_account(mint.key, fixture.treasury.key, 0);
+
+        let program_id = fixture.program_id;
+        Processor::process(
+            &program_id,
+            &fixture.set_treasury_account_accounts(),
+            &set_treasury_account_ix(),
+        )
+        .unwrap();
+
+        let config = Config::unpack(&fixture.config_account.data).unwrap();
+        assert_eq!(
+            config.treasury_account(&mint.key),
+            Some(&fixture.treasury_token_account.key)
+        );
+        assert!(
+            config.treasury_account(&fixture.mint_y.key).is_some(),
+            "Keeps the token accounts of other mints"
+        );
+    }
+
+    #[test]
+    fn set_treasury_account_not_admin() {
+        let mut fixture = EscrowFixture {
+            admin: TestAccount::signer(),
+            ..EscrowFixture::default()
+        };
+
+        let program_id = fixture.program_id;
+        assert_eq!(
+            Processor::process(
+                &program_id,
+                &fixture.set_treasury_account_accounts(),
+                &set_treasury_account_ix()
+            ),
+            Err(EscrowError::UnauthorizedAdmin.into())
+        );
+    }
+
+    #[test]
+    fn set_treasury_account_not_owned_by_treasury() {
+        let mut fixture = EscrowFixture::default();
+        fixture.treasury_token_account =
+            TestAccount::token_account(fixture.mint_y.key, fixture.taker.key, 0);
+
+        let program_id = fixture.program_id;
+        assert_eq!(
+            Processor::process(
+                &program_id,
+                &fixture.set_treasury_account_accounts(),
+                &set_treasury_account_ix()
+            ),
+            Err(EscrowError::InvalidTreasuryAccount.into()),
+            "Fees may only go to token accounts of the treasury"
+        );
     }
 }
@@ -1,4 +1,5 @@
 use solana_program::{
+    clock::UnixTimestamp,
     program_error::ProgramError,
     program_pack::{IsInitialized, Pack, Sealed},
     pubkey::Pubkey,
@@ -6,9 +7,11 @@ use solana_program::{
 
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
 
+use crate::error::EscrowError;
+
 #[derive(Default, Debug)]
 pub struct Escrow {
-    /// Determines if escrow account is already in use
+    /// Determines if escrow account is already in use, stored as the layout version
     pub is_initialized: bool,
 
     /// Alice's pubkey
@@ -23,13 +26,123 @@ pub struct Escrow {
 
     /// Used to check that Bob sends enough of his token
     pub expected_amount: u64,
+
+    /// Bump seed of the PDA that owns the tmp token account, derived via [Escrow::PDA_SEED] and
+    /// the escrow account key
+    pub bump_seed: u8,
+
+    /// Unix timestamp at which the escrow expires, escrows without one never expire
+    pub expires_at: Option<UnixTimestamp>,
+
+    /// The only taker (Bob) allowed to accept the trade, anyone may if not set
+    pub allowed_taker: Option<Pubkey>,
+
+    /// Mint of token X which Alice offers, Bob's receiving account has to hold the same
+    pub mint_x_pubkey: Pubkey,
+
+    /// Mint of token Y which Alice expects, Bob's sending account has to hold the same
+    pub mint_y_pubkey: Pubkey,
+
+    /// Decimals of token X at init, every transfer of token X is checked against them
+    pub mint_x_decimals: u8,
+
+    /// Decimals of token Y at init, every transfer of token Y is checked against them
+    pub mint_y_decimals: u8,
+}
+
+impl Escrow {
+    /// Version of the layout in which escrows are stored, written right after the
+    /// discriminator. Zero marks an uninitialized escrow.
+    /// Escrows of the first release predate versioning, see [EscrowV0].
+    pub const VERSION: u8 = 1;
+
+    /// Identifies escrow accounts among the accounts owned by the program, the first 8 bytes of
+    /// `sha256("account:Escrow")`
+    pub const DISCRIMINATOR: [u8; 8] = [31, 213, 123, 187, 186, 22, 218, 155];
+
+    /// Seed prefix of the PDA that has authority over the escrow's tmp token account
+    pub const PDA_SEED: &'static [u8] = b"escrow";
+
+    /// Seed prefix of the escrow account itself when the program creates it, see
+    /// [crate::instruction::EscrowInstruction::InitEscrowPda]
+    pub const ACCOUNT_SEED: &'static [u8] = b"escrow_account";
+
+    /// Seed prefix of the tmp token account when the program creates it, see
+    /// [crate::instruction::EscrowInstruction::InitEscrowWithDeposit]
+    pub const VAULT_SEED: &'static [u8] = b"vault";
+
+    /// Stands in for the mint of a leg that is traded as native lamports instead of an SPL token.
+    /// Same as the system program id, which can never be a mint.
+    pub const LAMPORTS_MINT: Pubkey = Pubkey::new_from_array([0; 32]);
+
+    /// Decimals recorded for a leg traded as native lamports, same as SOL
+    pub const LAMPORTS_DECIMALS: u8 = 9;
+
+    /// Determines if Alice offers native lamports instead of token X
+    pub fn offers_lamports(&self) -> bool {
+        self.mint_x_pubkey == Self::LAMPORTS_MINT
+    }
+
+    /// Determines if Alice expects native lamports instead of token Y
+    pub fn expects_lamports(&self) -> bool {
+        self.mint_y_pubkey == Self::LAMPORTS_MINT
+    }
+
+    /// Determines if the escrow can no longer be taken at the given time
+    pub fn is_expired(&self, now: UnixTimestamp) -> bool {
+        matches!(self.expires_at, Some(expires_at) if now >= expires_at)
+    }
+
+    /// Determines if the given taker (Bob) is allowed to accept the trade
+    pub fn can_be_taken_by(&self, taker: &Pubkey) -> bool {
+        match self.allowed_taker {
+            Some(allowed_taker) => allowed_taker == *taker,
+            None => true,
+        }
+    }
+
+    /// Amount of token X Bob receives when sending `amount_from_taker` of token Y while the tmp
+    /// token account still holds `deposit` of token X.
+    /// Rounds down so that partial fills never favor Bob.
+    /// Returns `None` if Bob sends nothing, more than is still expected or too little to receive
+    /// any token X.
+    pub fn amount_to_taker(&self, deposit: u64, amount_from_taker: u64) -> Option<u64> {
+        amount_to_taker(self.expected_amount, deposit, amount_from_taker)
+    }
+}
+
+fn amount_to_taker(expected_amount: u64, deposit: u64, amount_from_taker: u64) -> Option<u64> {
+    if amount_from_taker == 0 || amount_from_taker > expected_amount {
+        return None;
+    }
+    // amount_from_taker <= expected_amount, thus the result is at most the deposit
+    let amount = deposit as u128 * amount_from_taker as u128 / expected_amount as u128;
+    match amount {
+        0 => None,
+        amount => Some(amount as u64),
+    }
 }
 
 // -----------------
 // Pack Implementation
 // -----------------
 
-// NOTE: this is what BorshSerialize, BorshDeserialize do for us
+// NOTE: this is what BorshSerialize, BorshDeserialize do for us, with the `borsh` feature
+// they are implemented on top of the same layout in `borsh_layout`
+
+/// Checks the discriminator of program owned state, accounts that were never initialized or
+/// were closed are all zeros
+pub(crate) fn check_discriminator(
+    discriminator: &[u8; 8],
+    expected: &[u8; 8],
+    is_initialized: bool,
+) -> Result<(), ProgramError> {
+    if discriminator == expected || (!is_initialized && *discriminator == [0; 8]) {
+        Ok(())
+    } else {
+        Err(EscrowError::InvalidAccountDiscriminator.into())
+    }
+}
 
 /// Solana version of `Sized`
 impl Sealed for Escrow {}
@@ -41,7 +154,7 @@ impl IsInitialized for Escrow {
 }
 
 impl Pack for Escrow {
-    const LEN: usize = 105; // 1 + 32 + 32 + 32 + 8
+    const LEN: usize = 222; // 8 + 214
 
     fn pack_into_slice(&self, dst: &mut [u8]) {
         let Escrow {
@@ -50,26 +163,58 @@ impl Pack for Escrow {
             tmp_token_account_pubkey,
             initializer_token_to_receive_account_pubkey,
             expected_amount,
+            bump_seed,
+            expires_at,
+            allowed_taker,
+            mint_x_pubkey,
+            mint_y_pubkey,
+            mint_x_decimals,
+            mint_y_decimals,
         } = self;
 
-        // allocate u8 buffer of size 105
+        // allocate u8 buffer of size 222
         let dst = array_mut_ref![dst, 0, Escrow::LEN];
         // get offsets of individual buffer chunks
         let (
-            /* bool:    1 byte  */ is_initialized_dst,
+            /* [u8; 8]: 8 bytes */ discriminator_dst,
+            /* u8:      1 byte  */ version_dst,
             /* Pubkey: 32 bytes */ initializer_pubkey_dst,
             /* Pubkey: 32 bytes */ tmp_token_account_pubkey_dst,
             /* Pubkey: 32 bytes */ initializer_token_to_receive_account_pubkey_dst,
             /* u64:     8 bytes */ expected_amount_dst,
-        ) = mut_array_refs![dst, 1, 32, 32, 32, 8];
+            /* u8:      1 byte  */ bump_seed_dst,
+            /* bool:    1 byte  */ has_expires_at_dst,
+            /* i64:     8 bytes */ expires_at_dst,
+            /* bool:    1 byte  */ has_allowed_taker_dst,
+            /* Pubkey: 32 bytes */ allowed_taker_dst,
+            /* Pubkey: 32 bytes */ mint_x_pubkey_dst,
+            /* Pubkey: 32 bytes */ mint_y_pubkey_dst,
+            /* u8:      1 byte  */ mint_x_decimals_dst,
+            /* u8:      1 byte  */ mint_y_decimals_dst,
+        ) = mut_array_refs![dst, 8, 1, 32, 32, 32, 8, 1, 1, 8, 1, 32, 32, 32, 1, 1];
 
         // memcpy escrow content into chunks one by one
-        is_initialized_dst[0] = *is_initialized as u8;
+        if *is_initialized {
+            *discriminator_dst = Escrow::DISCRIMINATOR;
+            version_dst[0] = Escrow::VERSION;
+        } else {
+            *discriminator_dst = [0; 8];
+            version_dst[0] = 0;
+        }
         initializer_pubkey_dst.copy_from_slice(initializer_pubkey.as_ref());
         tmp_token_account_pubkey_dst.copy_from_slice(tmp_token_account_pubkey.as_ref());
         initializer_token_to_receive_account_pubkey_dst
             .copy_from_slice(initializer_token_to_receive_account_pubkey.as_ref());
         *expected_amount_dst = expected_amount.to_le_bytes();
+        bump_seed_dst[0] = *bump_seed;
+        has_expires_at_dst[0] = expires_at.is_some() as u8;
+        *expires_at_dst = expires_at.unwrap_or_default().to_le_bytes();
+        has_allowed_taker_dst[0] = allowed_taker.is_some() as u8;
+        allowed_taker_dst.copy_from_slice(allowed_taker.unwrap_or_default().as_ref());
+        mint_x_pubkey_dst.copy_from_slice(mint_x_pubkey.as_ref());
+        mint_y_pubkey_dst.copy_from_slice(mint_y_pubkey.as_ref());
+        mint_x_decimals_dst[0] = *mint_x_decimals;
+        mint_y_decimals_dst[0] = *mint_y_decimals;
     }
 
     fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
@@ -77,25 +222,49 @@ impl Pack for Escrow {
         let src = array_ref![src, 0, Escrow::LEN];
         // get offsets of individual buffer chunks
         let (
-            /* bool:    1 byte  */ is_initialized,
+            /* [u8; 8]: 8 bytes */ discriminator,
+            /* u8:      1 byte  */ version,
             /* Pubkey: 32 bytes */ initializer_pubkey,
             /* Pubkey: 32 bytes */ tmp_token_account_pubkey,
             /* Pubkey: 32 bytes */ initializer_token_to_receive_account_pubkey,
             /* u64:     8 bytes */ expected_amount,
-        ) = array_refs![src, 1, 32, 32, 32, 8];
+            /* u8:      1 byte  */ bump_seed,
+            /* bool:    1 byte  */ has_expires_at,
+            /* i64:     8 bytes */ expires_at,
+            /* bool:    1 byte  */ has_allowed_taker,
+            /* Pubkey: 32 bytes */ allowed_taker,
+            /* Pubkey: 32 bytes */ mint_x_pubkey,
+            /* Pubkey: 32 bytes */ mint_y_pubkey,
+            /* u8:      1 byte  */ mint_x_decimals,
+            /* u8:      1 byte  */ mint_y_decimals,
+        ) = array_refs![src, 8, 1, 32, 32, 32, 8, 1, 1, 8, 1, 32, 32, 32, 1, 1];
 
         // convert memory content of each chunk into Rust types
-        let is_initialized = match is_initialized {
+        let is_initialized = match version {
             [0] => false,
-            [1] => true,
+            [Escrow::VERSION] => true,
             _ => return Err(ProgramError::InvalidAccountData),
         };
+        check_discriminator(discriminator, &Escrow::DISCRIMINATOR, is_initialized)?;
 
         let initializer_pubkey = Pubkey::new_from_array(*initializer_pubkey);
         let tmp_token_account_pubkey = Pubkey::new_from_array(*tmp_token_account_pubkey);
         let initializer_token_to_receive_account_pubkey =
             Pubkey::new_from_array(*initializer_token_to_receive_account_pubkey);
         let expected_amount = u64::from_le_bytes(*expected_amount);
+        let bump_seed = bump_seed[0];
+        let expires_at = match has_expires_at {
+            [0] => None,
+            [1] => Some(i64::from_le_bytes(*expires_at)),
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+        let allowed_taker = match has_allowed_taker {
+            [0] => None,
+            [1] => Some(Pubkey::new_from_array(*allowed_taker)),
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+        let mint_x_pubkey = Pubkey::new_from_array(*mint_x_pubkey);
+        let mint_y_pubkey = Pubkey::new_from_array(*mint_y_pubkey);
 
         let escrow = Escrow {
             is_initialized,
@@ -103,8 +272,901 @@ impl Pack for Escrow {
             tmp_token_account_pubkey,
             initializer_token_to_receive_account_pubkey,
             expected_amount,
+            bump_seed,
+            expires_at,
+            allowed_taker,
+            mint_x_pubkey,
+            mint_y_pubkey,
+            mint_x_decimals: mint_x_decimals[0],
+            mint_y_decimals: mint_y_decimals[0],
+        };
+
+        Ok(escrow)
+    }
+}
+
+// -----------------
+// Escrow V0
+// -----------------
+
+/// Escrow in the layout of the first release, which has neither a discriminator nor a version.
+/// The tmp token accounts of these escrows are owned by the single PDA derived from
+/// [Escrow::PDA_SEED] alone rather than by a PDA per escrow.
+/// Exchange and CancelEscrow still accept them as they are,
+/// [crate::instruction::EscrowInstruction::MigrateEscrow] moves them into the current layout.
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct EscrowV0 {
+    /// Determines if escrow account is already in use
+    pub is_initialized: bool,
+
+    /// Alice's pubkey
+    pub initializer_pubkey: Pubkey,
+
+    /// Program sends tokens from this account to Bob's account when Bob takes the trade
+    pub tmp_token_account_pubkey: Pubkey,
+
+    /// Bob's tokens will be sent to this account
+    pub initializer_token_to_receive_account_pubkey: Pubkey,
+
+    /// Used to check that Bob sends enough of his token
+    pub expected_amount: u64,
+}
+
+impl EscrowV0 {
+    /// Stands in for the mint of token Y when Alice's token Y account isn't at hand, as when
+    /// cancelling. v0 escrows only ever traded tokens, unlike [Escrow::LAMPORTS_MINT] this
+    /// doesn't turn them into escrows expecting lamports.
+    pub const UNKNOWN_MINT: Pubkey = Pubkey::new_from_array([0xff; 32]);
+
+    /// Same trade in the current layout. The first release recorded neither the mints nor the
+    /// bump seed, the mints are those of the escrow's token accounts.
+    pub fn upgrade(
+        &self,
+        mint_x_pubkey: Pubkey,
+        mint_x_decimals: u8,
+        mint_y_pubkey: Pubkey,
+        mint_y_decimals: u8,
+    ) -> Escrow {
+        Escrow {
+            is_initialized: self.is_initialized,
+            initializer_pubkey: self.initializer_pubkey,
+            tmp_token_account_pubkey: self.tmp_token_account_pubkey,
+            initializer_token_to_receive_account_pubkey: self
+                .initializer_token_to_receive_account_pubkey,
+            expected_amount: self.expected_amount,
+            mint_x_pubkey,
+            mint_y_pubkey,
+            mint_x_decimals,
+            mint_y_decimals,
+            ..Escrow::default()
+        }
+    }
+}
+
+impl Sealed for EscrowV0 {}
+
+impl IsInitialized for EscrowV0 {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for EscrowV0 {
+    const LEN: usize = 105; // 1 + 32 + 32 + 32 + 8
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let EscrowV0 {
+            is_initialized,
+            initializer_pubkey,
+            tmp_token_account_pubkey,
+            initializer_token_to_receive_account_pubkey,
+            expected_amount,
+        } = self;
+
+        let dst = array_mut_ref![dst, 0, EscrowV0::LEN];
+        let (
+            /* bool:    1 byte  */ is_initialized_dst,
+            /* Pubkey: 32 bytes */ initializer_pubkey_dst,
+            /* Pubkey: 32 bytes */ tmp_token_account_pubkey_dst,
+            /* Pubkey: 32 bytes */ initializer_token_to_receive_account_pubkey_dst,
+            /* u64:     8 bytes */ expected_amount_dst,
+        ) = mut_array_refs![dst, 1, 32, 32, 32, 8];
+
+        is_initialized_dst[0] = *is_initialized as u8;
+        initializer_pubkey_dst.copy_from_slice(initializer_pubkey.as_ref());
+        tmp_token_account_pubkey_dst.copy_from_slice(tmp_token_account_pubkey.as_ref());
+        initializer_token_to_receive_account_pubkey_dst
+            .copy_from_slice(initializer_token_to_receive_account_pubkey.as_ref());
+        *expected_amount_dst = expected_amount.to_le_bytes();
+    }
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let src = array_ref![src, 0, EscrowV0::LEN];
+        let (
+            /* bool:    1 byte  */ is_initialized,
+            /* Pubkey: 32 bytes */ initializer_pubkey,
+            /* Pubkey: 32 bytes */ tmp_token_account_pubkey,
+            /* Pubkey: 32 bytes */ initializer_token_to_receive_account_pubkey,
+            /* u64:     8 bytes */ expected_amount,
+        ) = array_refs![src, 1, 32, 32, 32, 8];
+
+        let is_initialized = match is_initialized {
+            [0] => false,
+            [1] => true,
+            _ => return Err(ProgramError::InvalidAccountData),
         };
 
+        Ok(EscrowV0 {
+            is_initialized,
+            initializer_pubkey: Pubkey::new_from_array(*initializer_pubkey),
+            tmp_token_account_pubkey: Pubkey::new_from_array(*tmp_token_account_pubkey),
+            initializer_token_to_receive_account_pubkey: Pubkey::new_from_array(
+                *initializer_token_to_receive_account_pubkey,
+            ),
+            expected_amount: u64::from_le_bytes(*expected_amount),
+        })
+    }
+}
+
+// -----------------
+// Zero-Copy View
+// -----------------
+
+/// Escrow read in place from the account data instead of being unpacked into an [Escrow].
+/// Covers everything after the discriminator, see [Escrow::pack_into_slice].
+/// Only made of byte arrays and [Pubkey]s, which are transparent byte arrays, thus it has no
+/// padding, an alignment of 1 and any bytes are valid.
+#[repr(C)]
+pub struct EscrowData {
+    version: u8,
+    pub initializer_pubkey: Pubkey,
+    pub tmp_token_account_pubkey: Pubkey,
+    pub initializer_token_to_receive_account_pubkey: Pubkey,
+    expected_amount: [u8; 8],
+    pub bump_seed: u8,
+    has_expires_at: u8,
+    expires_at: [u8; 8],
+    has_allowed_taker: u8,
+    allowed_taker: Pubkey,
+    pub mint_x_pubkey: Pubkey,
+    pub mint_y_pubkey: Pubkey,
+    pub mint_x_decimals: u8,
+    pub mint_y_decimals: u8,
+}
+
+// The account data EscrowData is cast from has no alignment guarantees and has to be covered
+// exactly, both fail to compile should a field ever break this
+const _: [(); 1] = [(); std::mem::align_of::<EscrowData>()];
+const _: [(); Escrow::LEN - 8] = [(); std::mem::size_of::<EscrowData>()];
+
+impl EscrowData {
+    /// Views the data of an initialized escrow account, performing the same checks as
+    /// [Escrow::unpack]
+    pub fn load(data: &[u8]) -> Result<&EscrowData, ProgramError> {
+        let body = Self::body(data)?;
+        // SAFETY: `body` is exactly `size_of::<EscrowData>()` bytes long and EscrowData has an
+        // alignment of 1, both asserted at compile time above, so the pointer is valid for the
+        // cast wherever the account data starts. Any bytes are a valid EscrowData, so reading
+        // them is sound even before `check`. The reference borrows `data` and can't outlive it.
+        let escrow = unsafe { &*(body.as_ptr() as *const EscrowData) };
+        escrow.check(data)?;
         Ok(escrow)
     }
+
+    /// Same as [EscrowData::load], but allows to update the escrow in place
+    pub fn load_mut(data: &mut [u8]) -> Result<&mut EscrowData, ProgramError> {
+        Self::load(data)?;
+        let body = &mut data[8..];
+        // SAFETY: same as in `load`, which also checked the length of `data`. The reference
+        // borrows `data` mutably, so nothing else can access the bytes while it lives, and any
+        // bytes written through it are a valid EscrowData.
+        Ok(unsafe { &mut *(body.as_mut_ptr() as *mut EscrowData) })
+    }
+
+    /// Body of the escrow, which follows the discriminator
+    fn body(data: &[u8]) -> Result<&[u8], ProgramError> {
+        if data.len() != Escrow::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(&data[8..])
+    }
+
+    fn check(&self, data: &[u8]) -> Result<(), ProgramError> {
+        let is_initialized = match self.version {
+            0 => false,
+            Escrow::VERSION => true,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+        if self.has_expires_at > 1 || self.has_allowed_taker > 1 {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        check_discriminator(
+            array_ref![data, 0, 8],
+            &Escrow::DISCRIMINATOR,
+            is_initialized,
+        )?;
+        if !is_initialized {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        Ok(())
+    }
+
+    pub fn expected_amount(&self) -> u64 {
+        u64::from_le_bytes(self.expected_amount)
+    }
+
+    pub fn set_expected_amount(&mut self, expected_amount: u64) {
+        self.expected_amount = expected_amount.to_le_bytes();
+    }
+
+    pub fn expires_at(&self) -> Option<UnixTimestamp> {
+        match self.has_expires_at {
+            0 => None,
+            _ => Some(i64::from_le_bytes(self.expires_at)),
+        }
+    }
+
+    pub fn allowed_taker(&self) -> Option<&Pubkey> {
+        match self.has_allowed_taker {
+            0 => None,
+            _ => Some(&self.allowed_taker),
+        }
+    }
+
+    /// See [Escrow::offers_lamports]
+    pub fn offers_lamports(&self) -> bool {
+        self.mint_x_pubkey == Escrow::LAMPORTS_MINT
+    }
+
+    /// See [Escrow::expects_lamports]
+    pub fn expects_lamports(&self) -> bool {
+        self.mint_y_pubkey == Escrow::LAMPORTS_MINT
+    }
+
+    /// See [Escrow::is_expired]
+    pub fn is_expired(&self, now: UnixTimestamp) -> bool {
+        matches!(self.expires_at(), Some(expires_at) if now >= expires_at)
+    }
+
+    /// See [Escrow::can_be_taken_by]
+    pub fn can_be_taken_by(&self, taker: &Pubkey) -> bool {
+        match self.allowed_taker() {
+            Some(allowed_taker) => allowed_taker == taker,
+            None => true,
+        }
+    }
+
+    /// See [Escrow::amount_to_taker]
+    pub fn amount_to_taker(&self, deposit: u64, amount_from_taker: u64) -> Option<u64> {
+        amount_to_taker(self.expected_amount(), deposit, amount_from_taker)
+    }
+}
+
+/// Lays out an unpacked escrow the way [EscrowData::load] would find it in the account data, so
+/// that escrows which can't be read in place, like [EscrowV0], can be handled the same way
+impl From<&Escrow> for EscrowData {
+    fn from(escrow: &Escrow) -> Self {
+        Self {
+            version: if escrow.is_initialized {
+                Escrow::VERSION
+            } else {
+                0
+            },
+            initializer_pubkey: escrow.initializer_pubkey,
+            tmp_token_account_pubkey: escrow.tmp_token_account_pubkey,
+            initializer_token_to_receive_account_pubkey: escrow
+                .initializer_token_to_receive_account_pubkey,
+            expected_amount: escrow.expected_amount.to_le_bytes(),
+            bump_seed: escrow.bump_seed,
+            has_expires_at: escrow.expires_at.is_some() as u8,
+            expires_at: escrow.expires_at.unwrap_or_default().to_le_bytes(),
+            has_allowed_taker: escrow.allowed_taker.is_some() as u8,
+            allowed_taker: escrow.allowed_taker.unwrap_or_default(),
+            mint_x_pubkey: escrow.mint_x_pubkey,
+            mint_y_pubkey: escrow.mint_y_pubkey,
+            mint_x_decimals: escrow.mint_x_decimals,
+            mint_y_decimals: escrow.mint_y_decimals,
+        }
+    }
+}
+
+// -----------------
+// Config
+// -----------------
+
+/// Program wide configuration stored in the PDA derived via [Config::PDA_SEED]
+#[derive(Default, Debug)]
+pub struct Config {
+    /// Determines if config account is already in use
+    pub is_initialized: bool,
+
+    /// The only account allowed to update the config
+    pub admin_pubkey: Pubkey,
+
+    /// Fee skimmed from the tokens Bob sends to Alice on each exchange, 100 = 1%
+    pub fee_basis_points: u16,
+
+    /// Receives the fees paid in lamports and owns the treasury token accounts
+    pub treasury_pubkey: Pubkey,
+
+    /// Bump seed of the config PDA
+    pub bump_seed: u8,
+
+    /// The treasury token account receiving the fees of each mint, no fee is charged in mints
+    /// without one. Unused entries are zeroed.
+    pub treasury_accounts: [TreasuryAccount; Config::MAX_TREASURY_ACCOUNTS],
+}
+
+/// Treasury token account of one mint, see [Config::treasury_accounts]
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct TreasuryAccount {
+    pub mint_pubkey: Pubkey,
+    pub token_account_pubkey: Pubkey,
+}
+
+impl TreasuryAccount {
+    const LEN: usize = 64; // 32 + 32
+}
+
+impl Config {
+    /// Seed of the PDA holding the config
+    pub const PDA_SEED: &'static [u8] = b"config";
+
+    /// Basis points that make up the entire amount
+    pub const MAX_FEE_BASIS_POINTS: u16 = 10_000;
+
+    /// Identifies the config account among the accounts owned by the program, the first 8 bytes
+    /// of `sha256("account:Config")`
+    pub const DISCRIMINATOR: [u8; 8] = [155, 12, 170, 224, 30, 250, 204, 130];
+
+    /// Number of mints the treasury can collect fees in
+    pub const MAX_TREASURY_ACCOUNTS: usize = 8;
+
+    /// Fee skimmed from `amount`, rounded down in favor of Alice
+    pub fn fee(&self, amount: u64) -> u64 {
+        // fee_basis_points <= MAX_FEE_BASIS_POINTS, thus the result is at most the amount
+        (amount as u128 * self.fee_basis_points as u128 / Self::MAX_FEE_BASIS_POINTS as u128) as u64
+    }
+
+    /// The treasury token account receiving the fees paid in `mint`
+    pub fn treasury_account(&self, mint: &Pubkey) -> Option<&Pubkey> {
+        self.treasury_accounts
+            .iter()
+            .find(|treasury_account| treasury_account.mint_pubkey == *mint)
+            .map(|treasury_account| &treasury_account.token_account_pubkey)
+    }
+
+    /// Sets the treasury token account of `mint`, replacing the previous one if there is any
+    pub fn set_treasury_account(
+        &mut self,
+        mint: &Pubkey,
+        token_account: &Pubkey,
+    ) -> Result<(), EscrowError> {
+        let unused = Pubkey::default();
+        let treasury_account = match self
+            .treasury_accounts
+            .iter()
+            .position(|treasury_account| treasury_account.mint_pubkey == *mint)
+        {
+            Some(index) => &mut self.treasury_accounts[index],
+            None => self
+                .treasury_accounts
+                .iter_mut()
+                .find(|treasury_account| treasury_account.mint_pubkey == unused)
+                .ok_or(EscrowError::TreasuryAccountsFull)?,
+        };
+        *treasury_account = TreasuryAccount {
+            mint_pubkey: *mint,
+            token_account_pubkey: *token_account,
+        };
+        Ok(())
+    }
+}
+
+impl Sealed for Config {}
+
+impl IsInitialized for Config {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for Config {
+    const LEN: usize = 588; // 8 + 1 + 32 + 2 + 32 + 1 + 8 * 64
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let Config {
+            is_initialized,
+            admin_pubkey,
+            fee_basis_points,
+            treasury_pubkey,
+            bump_seed,
+            treasury_accounts,
+        } = self;
+
+        let dst = array_mut_ref![dst, 0, Config::LEN];
+        let (
+            /* [u8; 8]: 8 bytes */ discriminator_dst,
+            /* bool:    1 byte  */ is_initialized_dst,
+            /* Pubkey: 32 bytes */ admin_pubkey_dst,
+            /* u16:     2 bytes */ fee_basis_points_dst,
+            /* Pubkey: 32 bytes */ treasury_pubkey_dst,
+            /* u8:      1 byte  */ bump_seed_dst,
+            /* [TreasuryAccount; 8]: 512 bytes */ treasury_accounts_dst,
+        ) = mut_array_refs![dst, 8, 1, 32, 2, 32, 1, 512];
+
+        *discriminator_dst = if *is_initialized {
+            Config::DISCRIMINATOR
+        } else {
+            [0; 8]
+        };
+        is_initialized_dst[0] = *is_initialized as u8;
+        admin_pubkey_dst.copy_from_slice(admin_pubkey.as_ref());
+        *fee_basis_points_dst = fee_basis_points.to_le_bytes();
+        treasury_pubkey_dst.copy_from_slice(treasury_pubkey.as_ref());
+        bump_seed_dst[0] = *bump_seed;
+        for (treasury_account, dst) in treasury_accounts
+            .iter()
+            .zip(treasury_accounts_dst.chunks_exact_mut(TreasuryAccount::LEN))
+        {
+            let (mint_pubkey_dst, token_account_pubkey_dst) = dst.split_at_mut(32);
+            mint_pubkey_dst.copy_from_slice(treasury_account.mint_pubkey.as_ref());
+            token_account_pubkey_dst
+                .copy_from_slice(treasury_account.token_account_pubkey.as_ref());
+        }
+    }
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let src = array_ref![src, 0, Config::LEN];
+        let (
+            /* [u8; 8]: 8 bytes */ discriminator,
+            /* bool:    1 byte  */ is_initialized,
+            /* Pubkey: 32 bytes */ admin_pubkey,
+            /* u16:     2 bytes */ fee_basis_points,
+            /* Pubkey: 32 bytes */ treasury_pubkey,
+            /* u8:      1 byte  */ bump_seed,
+            /* [TreasuryAccount; 8]: 512 bytes */ treasury_accounts_src,
+        ) = array_refs![src, 8, 1, 32, 2, 32, 1, 512];
+
+        let is_initialized = match is_initialized {
+            [0] => false,
+            [1] => true,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+        check_discriminator(discriminator, &Config::DISCRIMINATOR, is_initialized)?;
+
+        let mut treasury_accounts = [TreasuryAccount::default(); Config::MAX_TREASURY_ACCOUNTS];
+        for (treasury_account, src) in treasury_accounts
+            .iter_mut()
+            .zip(treasury_accounts_src.chunks_exact(TreasuryAccount::LEN))
+        {
+            let (mint_pubkey, token_account_pubkey) = src.split_at(32);
+            treasury_account.mint_pubkey = Pubkey::new(mint_pubkey);
+            treasury_account.token_account_pubkey = Pubkey::new(token_account_pubkey);
+        }
+
+        Ok(Config {
+            is_initialized,
+            admin_pubkey: Pubkey::new_from_array(*admin_pubkey),
+            fee_basis_points: u16::from_le_bytes(*fee_basis_points),
+            treasury_pubkey: Pubkey::new_from_array(*treasury_pubkey),
+            bump_seed: bump_seed[0],
+            treasury_accounts,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::hash::hash;
+
+    fn escrow_expecting(expected_amount: u64) -> Escrow {
+        Escrow {
+            expected_amount,
+            ..Escrow::default()
+        }
+    }
+
+    #[test]
+    fn amount_to_taker_proportional() {
+        let escrow = escrow_expecting(1000);
+        assert_eq!(escrow.amount_to_taker(5000, 1000), Some(5000), "full fill");
+        assert_eq!(
+            escrow.amount_to_taker(5000, 200),
+            Some(1000),
+            "partial fill"
+        );
+    }
+
+    #[test]
+    fn amount_to_taker_rounds_down() {
+        let escrow = escrow_expecting(3);
+        assert_eq!(escrow.amount_to_taker(10, 1), Some(3), "10/3 rounded down");
+        assert_eq!(escrow.amount_to_taker(10, 2), Some(6), "20/3 rounded down");
+        assert_eq!(
+            escrow.amount_to_taker(10, 3),
+            Some(10),
+            "full fill gets all"
+        );
+    }
+
+    #[test]
+    fn amount_to_taker_invalid_fills() {
+        let escrow = escrow_expecting(1000);
+        assert_eq!(escrow.amount_to_taker(5000, 0), None, "sends nothing");
+        assert_eq!(escrow.amount_to_taker(5000, 1001), None, "sends too much");
+        assert_eq!(escrow.amount_to_taker(10, 99), None, "receives nothing");
+    }
+
+    #[test]
+    fn expiry() {
+        let escrow = Escrow {
+            expires_at: Some(1000),
+            ..Escrow::default()
+        };
+        assert!(!escrow.is_expired(999), "before deadline");
+        assert!(escrow.is_expired(1000), "at deadline");
+        assert!(!Escrow::default().is_expired(i64::MAX), "never expires");
+    }
+
+    #[test]
+    fn allowed_taker() {
+        let bob = Pubkey::new_unique();
+        let escrow = Escrow {
+            allowed_taker: Some(bob),
+            ..Escrow::default()
+        };
+        assert!(escrow.can_be_taken_by(&bob), "designated taker");
+        assert!(
+            !escrow.can_be_taken_by(&Pubkey::new_unique()),
+            "other taker"
+        );
+        assert!(Escrow::default().can_be_taken_by(&bob), "anyone");
+    }
+
+    #[test]
+    fn lamports_legs() {
+        let mint = Pubkey::new_unique();
+        let escrow = Escrow {
+            mint_x_pubkey: Escrow::LAMPORTS_MINT,
+            mint_y_pubkey: mint,
+            ..Escrow::default()
+        };
+        assert!(escrow.offers_lamports());
+        assert!(!escrow.expects_lamports());
+        assert_eq!(Escrow::LAMPORTS_MINT, solana_program::system_program::id());
+    }
+
+    #[test]
+    fn pack_decimals() {
+        let escrow = Escrow {
+            is_initialized: true,
+            mint_x_decimals: 6,
+            mint_y_decimals: Escrow::LAMPORTS_DECIMALS,
+            ..Escrow::default()
+        };
+        let mut data = [0u8; Escrow::LEN];
+        Escrow::pack(escrow, &mut data).unwrap();
+
+        let escrow = Escrow::unpack(&data).unwrap();
+        assert_eq!(escrow.mint_x_decimals, 6);
+        assert_eq!(escrow.mint_y_decimals, 9);
+    }
+
+    #[test]
+    fn pack_current_version() {
+        let mut data = [0u8; Escrow::LEN];
+        Escrow::pack(
+            Escrow {
+                is_initialized: true,
+                ..Escrow::default()
+            },
+            &mut data,
+        )
+        .unwrap();
+        assert_eq!(data[..8], Escrow::DISCRIMINATOR);
+        assert_eq!(data[8], Escrow::VERSION);
+
+        Escrow::default().pack_into_slice(&mut data);
+        assert_eq!(data[..9], [0; 9], "uninitialized");
+    }
+
+    /// Escrow as the first release stored it
+    fn v0_escrow() -> EscrowV0 {
+        EscrowV0 {
+            is_initialized: true,
+            initializer_pubkey: Pubkey::new_unique(),
+            tmp_token_account_pubkey: Pubkey::new_unique(),
+            initializer_token_to_receive_account_pubkey: Pubkey::new_unique(),
+            expected_amount: 1000,
+        }
+    }
+
+    #[test]
+    fn unpack_v0() {
+        let escrow = v0_escrow();
+        let mut data = Vec::with_capacity(EscrowV0::LEN);
+        data.push(1);
+        data.extend_from_slice(escrow.initializer_pubkey.as_ref());
+        data.extend_from_slice(escrow.tmp_token_account_pubkey.as_ref());
+        data.extend_from_slice(escrow.initializer_token_to_receive_account_pubkey.as_ref());
+        data.extend_from_slice(&1000u64.to_le_bytes());
+
+        assert_eq!(EscrowV0::unpack(&data), Ok(escrow.clone()));
+        assert_eq!(
+            Escrow::unpack(&data).unwrap_err(),
+            ProgramError::InvalidAccountData,
+            "v0 escrows are a separate layout"
+        );
+
+        let mut packed = [0u8; EscrowV0::LEN];
+        EscrowV0::pack(escrow, &mut packed).unwrap();
+        assert_eq!(packed[..], data[..], "same bytes as the first release");
+    }
+
+    #[test]
+    fn upgrade_v0() {
+        let v0 = v0_escrow();
+        let mint_x = Pubkey::new_unique();
+        let mint_y = Pubkey::new_unique();
+
+        let escrow = v0.upgrade(mint_x, 6, mint_y, 9);
+        assert!(escrow.is_initialized);
+        assert_eq!(escrow.initializer_pubkey, v0.initializer_pubkey);
+        assert_eq!(escrow.tmp_token_account_pubkey, v0.tmp_token_account_pubkey);
+        assert_eq!(
+            escrow.initializer_token_to_receive_account_pubkey,
+            v0.initializer_token_to_receive_account_pubkey
+        );
+        assert_eq!(escrow.expected_amount, 1000);
+        assert_eq!((escrow.mint_x_pubkey, escrow.mint_x_decimals), (mint_x, 6));
+        assert_eq!((escrow.mint_y_pubkey, escrow.mint_y_decimals), (mint_y, 9));
+        assert_eq!(escrow.expires_at, None, "v0 escrows never expire");
+        assert_eq!(escrow.allowed_taker, None, "anyone may take v0 escrows");
+    }
+
+    #[test]
+    fn unpack_unknown_version() {
+        let mut data = [0u8; Escrow::LEN];
+        data[..8].copy_from_slice(&Escrow::DISCRIMINATOR);
+        data[8] = Escrow::VERSION + 1;
+        assert_eq!(
+            Escrow::unpack(&data).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+
+        let mut data = [0u8; EscrowV0::LEN];
+        data[0] = 2;
+        assert_eq!(
+            EscrowV0::unpack(&data).unwrap_err(),
+            ProgramError::InvalidAccountData,
+            "v0 escrows only have an is_initialized flag"
+        );
+    }
+
+    #[test]
+    fn discriminators() {
+        for (name, discriminator) in [
+            ("Escrow", Escrow::DISCRIMINATOR),
+            ("Config", Config::DISCRIMINATOR),
+        ] {
+            let hash = hash(format!("account:{}", name).as_bytes());
+            assert_eq!(hash.to_bytes()[..8], discriminator, "{}", name);
+        }
+    }
+
+    #[test]
+    fn unpack_wrong_discriminator() {
+        let mut data = [0u8; Escrow::LEN];
+        Escrow::pack(
+            Escrow {
+                is_initialized: true,
+                ..Escrow::default()
+            },
+            &mut data,
+        )
+        .unwrap();
+        data[..8].copy_from_slice(&Config::DISCRIMINATOR);
+        assert_eq!(
+            Escrow::unpack(&data).unwrap_err(),
+            EscrowError::InvalidAccountDiscriminator.into()
+        );
+
+        data[..8].fill(0);
+        assert_eq!(
+            Escrow::unpack(&data).unwrap_err(),
+            EscrowError::InvalidAccountDiscriminator.into(),
+            "initialized escrows need a discriminator"
+        );
+
+        let mut data = [0u8; Config::LEN];
+        Config::pack(
+            Config {
+                is_initialized: true,
+                ..Config::default()
+            },
+            &mut data,
+        )
+        .unwrap();
+        data[..8].copy_from_slice(&Escrow::DISCRIMINATOR);
+        assert_eq!(
+            Config::unpack(&data).unwrap_err(),
+            EscrowError::InvalidAccountDiscriminator.into()
+        );
+    }
+
+    #[test]
+    fn pack_config() {
+        let mut config = Config {
+            is_initialized: true,
+            admin_pubkey: Pubkey::new_unique(),
+            fee_basis_points: 30,
+            treasury_pubkey: Pubkey::new_unique(),
+            bump_seed: 255,
+            ..Config::default()
+        };
+        let mint = Pubkey::new_unique();
+        let token_account = Pubkey::new_unique();
+        config.set_treasury_account(&mint, &token_account).unwrap();
+
+        let mut data = [0u8; Config::LEN];
+        Config::pack(config, &mut data).unwrap();
+        let config = Config::unpack(&data).unwrap();
+        assert_eq!(config.fee_basis_points, 30);
+        assert_eq!(config.bump_seed, 255);
+        assert_eq!(config.treasury_account(&mint), Some(&token_account));
+        assert_eq!(config.treasury_account(&Pubkey::new_unique()), None);
+    }
+
+    #[test]
+    fn set_treasury_account() {
+        let mut config = Config::default();
+        let mint = Pubkey::new_unique();
+        config
+            .set_treasury_account(&mint, &Pubkey::new_unique())
+            .unwrap();
+        let token_account = Pubkey::new_unique();
+        config.set_treasury_account(&mint, &token_account).unwrap();
+        assert_eq!(
+            config.treasury_account(&mint),
+            Some(&token_account),
+            "replaces the token account of the mint"
+        );
+
+        for _ in 1..Config::MAX_TREASURY_ACCOUNTS {
+            config
+                .set_treasury_account(&Pubkey::new_unique(), &Pubkey::new_unique())
+                .unwrap();
+        }
+        assert_eq!(
+            config.set_treasury_account(&Pubkey::new_unique(), &Pubkey::new_unique()),
+            Err(EscrowError::TreasuryAccountsFull)
+        );
+        assert_eq!(
+            config.set_treasury_account(&mint, &Pubkey::new_unique()),
+            Ok(()),
+            "mints which already have a treasury account can still be updated"
+        );
+    }
+
+    #[test]
+    fn escrow_data_layout() {
+        assert_eq!(std::mem::size_of::<EscrowData>(), Escrow::LEN - 8);
+        assert_eq!(std::mem::align_of::<EscrowData>(), 1);
+    }
+
+    #[test]
+    fn escrow_data_matches_unpack() {
+        let escrow = Escrow {
+            is_initialized: true,
+            initializer_pubkey: Pubkey::new_unique(),
+            tmp_token_account_pubkey: Pubkey::new_unique(),
+            initializer_token_to_receive_account_pubkey: Pubkey::new_unique(),
+            expected_amount: 1000,
+            bump_seed: 254,
+            expires_at: Some(1_634_400_000),
+            allowed_taker: Some(Pubkey::new_unique()),
+            mint_x_pubkey: Pubkey::new_unique(),
+            mint_y_pubkey: Escrow::LAMPORTS_MINT,
+            mint_x_decimals: 6,
+            mint_y_decimals: Escrow::LAMPORTS_DECIMALS,
+        };
+        let mut data = [0u8; Escrow::LEN];
+        escrow.pack_into_slice(&mut data);
+
+        let escrow_data = EscrowData::load(&data).unwrap();
+        assert_eq!(escrow_data.initializer_pubkey, escrow.initializer_pubkey);
+        assert_eq!(
+            escrow_data.tmp_token_account_pubkey,
+            escrow.tmp_token_account_pubkey
+        );
+        assert_eq!(
+            escrow_data.initializer_token_to_receive_account_pubkey,
+            escrow.initializer_token_to_receive_account_pubkey
+        );
+        assert_eq!(escrow_data.expected_amount(), 1000);
+        assert_eq!(escrow_data.bump_seed, 254);
+        assert_eq!(escrow_data.expires_at(), escrow.expires_at);
+        assert_eq!(escrow_data.allowed_taker(), escrow.allowed_taker.as_ref());
+        assert_eq!(escrow_data.mint_x_pubkey, escrow.mint_x_pubkey);
+        assert!(escrow_data.expects_lamports());
+        assert_eq!(escrow_data.mint_x_decimals, 6);
+        assert_eq!(escrow_data.mint_y_decimals, 9);
+
+        EscrowData::load_mut(&mut data)
+            .unwrap()
+            .set_expected_amount(400);
+        let escrow = Escrow::unpack(&data).unwrap();
+        assert_eq!(escrow.expected_amount, 400, "updated in place");
+    }
+
+    #[test]
+    fn escrow_data_from_escrow() {
+        let escrow = v0_escrow().upgrade(Pubkey::new_unique(), 6, Pubkey::new_unique(), 9);
+        let escrow_data = EscrowData::from(&escrow);
+        assert_eq!(escrow_data.version, Escrow::VERSION);
+        assert_eq!(escrow_data.initializer_pubkey, escrow.initializer_pubkey);
+        assert_eq!(
+            escrow_data.tmp_token_account_pubkey,
+            escrow.tmp_token_account_pubkey
+        );
+        assert_eq!(
+            escrow_data.initializer_token_to_receive_account_pubkey,
+            escrow.initializer_token_to_receive_account_pubkey
+        );
+        assert_eq!(escrow_data.expected_amount(), escrow.expected_amount);
+        assert_eq!(escrow_data.expires_at(), None);
+        assert_eq!(escrow_data.allowed_taker(), None);
+        assert_eq!(escrow_data.mint_x_pubkey, escrow.mint_x_pubkey);
+        assert_eq!(escrow_data.mint_y_pubkey, escrow.mint_y_pubkey);
+        assert_eq!(escrow_data.mint_x_decimals, 6);
+        assert_eq!(escrow_data.mint_y_decimals, 9);
+    }
+
+    #[test]
+    fn escrow_data_invalid() {
+        let mut data = [0u8; Escrow::LEN];
+        assert_eq!(
+            EscrowData::load(&data).err(),
+            Some(ProgramError::UninitializedAccount)
+        );
+
+        Escrow::pack(
+            Escrow {
+                is_initialized: true,
+                ..Escrow::default()
+            },
+            &mut data,
+        )
+        .unwrap();
+        data[..8].copy_from_slice(&Config::DISCRIMINATOR);
+        assert_eq!(
+            EscrowData::load(&data).err(),
+            Some(EscrowError::InvalidAccountDiscriminator.into())
+        );
+
+        data[..8].copy_from_slice(&Escrow::DISCRIMINATOR);
+        data[8 + 1 + 32 * 3 + 8 + 1] = 2;
+        assert_eq!(
+            EscrowData::load(&data).err(),
+            Some(ProgramError::InvalidAccountData),
+            "has_expires_at is a bool"
+        );
+
+        assert_eq!(
+            EscrowData::load(&data[1..]).err(),
+            Some(ProgramError::InvalidAccountData),
+            "size"
+        );
+    }
+
+    #[test]
+    fn amount_to_taker_large_amounts() {
+        let escrow = escrow_expecting(u64::MAX);
+        assert_eq!(
+            escrow.amount_to_taker(u64::MAX, u64::MAX / 2),
+            Some(u64::MAX / 2)
+        );
+    }
 }
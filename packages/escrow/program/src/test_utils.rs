@@ -1,149 +1,392 @@
 use solana_program::program_pack::Pack;
-use solana_program::{account_info::AccountInfo, pubkey::Pubkey, rent::Rent};
+use solana_program::{
+    account_info::AccountInfo,
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    entrypoint::SUCCESS,
+    program_stubs,
+    pubkey::Pubkey,
+    rent::Rent,
+};
+use spl_token::state::{Account as TokenAccount, AccountState, Mint};
 
-use solana_sdk::account::{create_account_for_test, Account};
+use crate::state::{Config, Escrow, EscrowV0};
 
-use crate::state::Escrow;
+// -----------------
+// Sysvars
+// -----------------
+struct RentSyscallStubs;
 
-pub struct EscrowAccounts<'a> {
-    pub initializer_acc: AccountInfo<'a>,
-    pub tmp_acc: AccountInfo<'a>,
-    pub tok_to_receive_acc: AccountInfo<'a>,
-    pub escrow_acc: AccountInfo<'a>,
-    pub rent: AccountInfo<'a>,
-    pub token_program_acc: AccountInfo<'a>,
+impl program_stubs::SyscallStubs for RentSyscallStubs {
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { *(var_addr as *mut Rent) = Rent::default() };
+        SUCCESS
+    }
 }
 
-impl<'a> EscrowAccounts<'a> {
-    pub fn new(
-        // Signer
-        initializer_pkey: &'a Pubkey,
-        initializer_lamp: &'a mut u64,
-        initializer_data: &'a mut [u8],
-
-        // Initializer's Token Account
-        tmp_pkey: &'a Pubkey,
-        tmp_lamp: &'a mut u64,
-        tmp_data: &'a mut [u8],
-
-        tok_to_receive_pkey: &'a Pubkey,
-        tok_to_receive_lamp: &'a mut u64,
-        tok_to_receive_data: &'a mut [u8],
-        tok_to_receive_owner: &'a Pubkey,
-
-        // Escrow Account
-        escrow_pkey: &'a Pubkey,
-        escrow_lamp: &'a mut u64,
-        escrow_data: &'a mut [u8; Escrow::LEN],
-
-        // Rent account
-        rent_pkey: &'a Pubkey,
-        rent_acc: &'a mut Account,
-
-        token_program_pkey: &'a Pubkey,
-        token_program_lamp: &'a mut u64,
-        token_program_data: &'a mut [u8],
-    ) -> Self {
-        let initializer_acc = AccountInfo::new(
-            initializer_pkey,
-            true, // initializer
-            false,
-            initializer_lamp,
-            initializer_data,
-            initializer_pkey,
-            false,
-            0,
-        );
-        let tmp_acc = AccountInfo::new(
-            tmp_pkey, false, true, // writable
-            tmp_lamp, tmp_data, tmp_pkey, false, 0,
-        );
-        let tok_to_receive_acc = AccountInfo::new(
-            &tok_to_receive_pkey,
-            false,
-            false,
-            tok_to_receive_lamp,
-            tok_to_receive_data,
-            &tok_to_receive_owner,
-            false,
+/// Makes `Rent::get` work outside of the runtime, the default stubs don't support any sysvar
+pub fn set_rent_syscall_stubs() {
+    program_stubs::set_syscall_stubs(Box::new(RentSyscallStubs));
+}
+
+// -----------------
+// Owned Accounts
+// -----------------
+
+/// Account that owns its data so that [AccountInfo]s can be borrowed from it repeatedly
+pub struct TestAccount {
+    pub key: Pubkey,
+    pub owner: Pubkey,
+    pub lamports: u64,
+    pub data: Vec<u8>,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+impl TestAccount {
+    pub fn new(owner: Pubkey, data: Vec<u8>) -> Self {
+        Self {
+            key: Pubkey::new_unique(),
+            owner,
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            is_signer: false,
+            is_writable: true,
+        }
+    }
+
+    pub fn signer() -> Self {
+        Self {
+            is_signer: true,
+            ..Self::new(solana_program::system_program::id(), vec![])
+        }
+    }
+
+    pub fn token_account(mint: Pubkey, owner: Pubkey, amount: u64) -> Self {
+        let mut data = vec![0u8; TokenAccount::LEN];
+        TokenAccount {
+            mint,
+            owner,
+            amount,
+            state: AccountState::Initialized,
+            ..TokenAccount::default()
+        }
+        .pack_into_slice(&mut data);
+        Self::new(spl_token::id(), data)
+    }
+
+    pub fn mint(decimals: u8) -> Self {
+        let mut data = vec![0u8; Mint::LEN];
+        Mint {
+            decimals,
+            is_initialized: true,
+            ..Mint::default()
+        }
+        .pack_into_slice(&mut data);
+        Self::new(spl_token::id(), data)
+    }
+
+    /// ProgramData account which the upgradeable loader keeps for `program_id`, without the
+    /// program itself
+    pub fn program_data(program_id: &Pubkey, upgrade_authority: Option<Pubkey>) -> Self {
+        let mut data = bincode::serialize(&UpgradeableLoaderState::ProgramData {
+            slot: 0,
+            upgrade_authority_address: upgrade_authority,
+        })
+        .unwrap();
+        // The program follows at a fixed offset, whether there is an upgrade authority or not
+        data.resize(
+            UpgradeableLoaderState::programdata_data_offset().unwrap(),
             0,
         );
-        let escrow_acc = create_empty_escrow_acc(escrow_pkey, escrow_lamp, escrow_data);
-        let rent = create_rent_acc(rent_pkey, rent_acc);
+        let mut program_data = Self::new(bpf_loader_upgradeable::id(), data);
+        program_data.key =
+            Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id()).0;
+        program_data
+    }
 
-        let token_program_acc = AccountInfo::new(
-            token_program_pkey,
-            false,
-            false,
-            token_program_lamp,
-            token_program_data,
-            token_program_pkey,
+    pub fn account_info(&mut self) -> AccountInfo {
+        AccountInfo::new(
+            &self.key,
+            self.is_signer,
+            self.is_writable,
+            &mut self.lamports,
+            &mut self.data,
+            &self.owner,
             false,
             0,
-        );
+        )
+    }
+}
+
+// -----------------
+// Escrow Fixture
+// -----------------
+
+/// All accounts involved in an escrow, owned so that each test only states how it deviates from
+/// the happy path. Alice trades 5000 of token X for 1000 of token Y, Bob takes the trade.
+pub struct EscrowFixture {
+    pub program_id: Pubkey,
+
+    // Alice
+    pub initializer: TestAccount,
+    /// Alice's main token X account, gets the tokens back when the escrow is cancelled
+    pub initializers_token_account: TestAccount,
+    /// Holds the X Alice offers, owned by Alice until the escrow is initialized, then by the PDA
+    pub tmp_token_account: TestAccount,
+    pub initializers_token_to_receive_account: TestAccount,
+    pub escrow_account: TestAccount,
+
+    // Bob
+    pub taker: TestAccount,
+    pub takers_sending_token_account: TestAccount,
+    pub takers_token_to_receive_account: TestAccount,
+
+    pub token_program: TestAccount,
+    pub pda_account: TestAccount,
+    pub config_account: TestAccount,
+    pub treasury_token_account: TestAccount,
+
+    // Config
+    /// Upgrade authority of the program, thus the admin of the config
+    pub admin: TestAccount,
+    pub treasury: TestAccount,
+    /// ProgramData account of the program recording the admin as upgrade authority
+    pub program_data: TestAccount,
+    pub system_program: TestAccount,
+
+    pub mint_x: TestAccount,
+    pub mint_y: TestAccount,
+}
+
+impl Default for EscrowFixture {
+    /// Accounts as Alice submits them to InitEscrow, with an empty escrow account
+    fn default() -> Self {
+        let program_id = Pubkey::new_unique();
+
+        let mint_x = TestAccount::mint(6);
+        let mint_y = TestAccount::mint(9);
+
+        let initializer = TestAccount::signer();
+        let taker = TestAccount::signer();
+
+        let escrow_account = TestAccount::new(program_id, vec![0u8; Escrow::LEN]);
+        let mut pda_account = TestAccount::new(solana_program::system_program::id(), vec![]);
+        pda_account.key = Pubkey::find_program_address(
+            &[Escrow::PDA_SEED, escrow_account.key.as_ref()],
+            &program_id,
+        )
+        .0;
+
+        let admin = TestAccount::signer();
+        let treasury = TestAccount::new(solana_program::system_program::id(), vec![]);
+        let (config_pda, bump_seed) =
+            Pubkey::find_program_address(&[Config::PDA_SEED], &program_id);
+        let mut config_account = TestAccount::new(program_id, vec![0u8; Config::LEN]);
+        config_account.key = config_pda;
+        let treasury_token_account = TestAccount::token_account(mint_y.key, treasury.key, 0);
+        let mut config = Config {
+            is_initialized: true,
+            admin_pubkey: admin.key,
+            fee_basis_points: 0,
+            treasury_pubkey: treasury.key,
+            bump_seed,
+            ..Config::default()
+        };
+        config
+            .set_treasury_account(&mint_y.key, &treasury_token_account.key)
+            .unwrap();
+        config.pack_into_slice(&mut config_account.data);
+
+        let mut token_program = TestAccount::new(Pubkey::default(), vec![]);
+        token_program.key = spl_token::id();
+        let mut system_program = TestAccount::new(Pubkey::default(), vec![]);
+        system_program.key = solana_program::system_program::id();
+        let program_data = TestAccount::program_data(&program_id, Some(admin.key));
 
         Self {
-            initializer_acc,
-            tmp_acc,
-            tok_to_receive_acc,
-            escrow_acc,
-            rent,
-            token_program_acc,
+            program_id,
+            initializers_token_account: TestAccount::token_account(mint_x.key, initializer.key, 0),
+            tmp_token_account: TestAccount::token_account(
+                mint_x.key,
+                initializer.key,
+                Self::X_AMOUNT,
+            ),
+            initializers_token_to_receive_account: TestAccount::token_account(
+                mint_y.key,
+                initializer.key,
+                0,
+            ),
+            initializer,
+            escrow_account,
+            takers_sending_token_account: TestAccount::token_account(
+                mint_y.key,
+                taker.key,
+                Self::Y_AMOUNT,
+            ),
+            takers_token_to_receive_account: TestAccount::token_account(mint_x.key, taker.key, 0),
+            taker,
+            token_program,
+            pda_account,
+            config_account,
+            treasury_token_account,
+            mint_x,
+            mint_y,
+            admin,
+            treasury,
+            program_data,
+            system_program,
         }
     }
+}
 
-    pub fn account_infos(&'a self) -> Vec<AccountInfo> {
-        let EscrowAccounts {
-            initializer_acc,
-            tmp_acc,
-            tok_to_receive_acc,
-            escrow_acc,
-            rent,
-            token_program_acc,
-        } = self;
+impl EscrowFixture {
+    pub const X_AMOUNT: u64 = 5000;
+    pub const Y_AMOUNT: u64 = 1000;
 
+    /// Determines if Alice and Bob sign their instructions
+    pub fn with_signer(mut self, is_signer: bool) -> Self {
+        self.initializer.is_signer = is_signer;
+        self.taker.is_signer = is_signer;
+        self
+    }
+
+    pub fn with_escrow_owner(mut self, owner: Pubkey) -> Self {
+        self.escrow_account.owner = owner;
+        self
+    }
+
+    /// Replaces the escrow account with an empty one of `len` bytes
+    pub fn with_escrow_len(mut self, len: usize) -> Self {
+        self.escrow_account.data = vec![0u8; len];
+        self
+    }
+
+    /// Accounts in the state a successful InitEscrow leaves them in, ready to be exchanged or
+    /// cancelled
+    pub fn initialized(mut self) -> Self {
+        let (pda, bump_seed) = Pubkey::find_program_address(
+            &[Escrow::PDA_SEED, self.escrow_account.key.as_ref()],
+            &self.program_id,
+        );
+        self.tmp_token_account = TestAccount {
+            key: self.tmp_token_account.key,
+            ..TestAccount::token_account(self.mint_x.key, pda, Self::X_AMOUNT)
+        };
+        Escrow {
+            is_initialized: true,
+            initializer_pubkey: self.initializer.key,
+            tmp_token_account_pubkey: self.tmp_token_account.key,
+            initializer_token_to_receive_account_pubkey: self
+                .initializers_token_to_receive_account
+                .key,
+            expected_amount: Self::Y_AMOUNT,
+            bump_seed,
+            mint_x_pubkey: self.mint_x.key,
+            mint_y_pubkey: self.mint_y.key,
+            mint_x_decimals: 6,
+            mint_y_decimals: 9,
+            ..Escrow::default()
+        }
+        .pack_into_slice(&mut self.escrow_account.data);
+        self
+    }
+
+    /// Accounts in the state the first release left them in, see [EscrowV0], with the tmp token
+    /// account owned by the PDA shared by all v0 escrows
+    pub fn initialized_v0(mut self) -> Self {
+        let (pda, _) = Pubkey::find_program_address(&[Escrow::PDA_SEED], &self.program_id);
+        self.pda_account.key = pda;
+        self.tmp_token_account = TestAccount {
+            key: self.tmp_token_account.key,
+            ..TestAccount::token_account(self.mint_x.key, pda, Self::X_AMOUNT)
+        };
+        self.escrow_account.data = vec![0u8; EscrowV0::LEN];
+        EscrowV0 {
+            is_initialized: true,
+            initializer_pubkey: self.initializer.key,
+            tmp_token_account_pubkey: self.tmp_token_account.key,
+            initializer_token_to_receive_account_pubkey: self
+                .initializers_token_to_receive_account
+                .key,
+            expected_amount: Self::Y_AMOUNT,
+        }
+        .pack_into_slice(&mut self.escrow_account.data);
+        self
+    }
+
+    /// Accounts of InitEscrow in the order the instruction expects them
+    pub fn init_escrow_accounts(&mut self) -> Vec<AccountInfo> {
         vec![
-            /* 0 */ initializer_acc.clone(),
-            /* 1 */ tmp_acc.clone(),
-            /* 2 */ tok_to_receive_acc.clone(),
-            /* 3 */ escrow_acc.clone(),
-            /* 4 */ rent.clone(),
-            /* 5 */ token_program_acc.clone(),
+            /* 0 */ self.initializer.account_info(),
+            /* 1 */ self.tmp_token_account.account_info(),
+            /* 2 */ self.initializers_token_to_receive_account.account_info(),
+            /* 3 */ self.escrow_account.account_info(),
+            /* 4 */ self.token_program.account_info(),
+            /* 5 */ self.mint_x.account_info(),
+            /* 6 */ self.mint_y.account_info(),
         ]
     }
-}
 
-// -----------------
-// Escrow Account
-// -----------------
-pub fn create_empty_escrow_acc<'a>(
-    pubkey: &'a Pubkey,
-    lamports: &'a mut u64,
-    data: &'a mut [u8; Escrow::LEN],
-) -> AccountInfo<'a> {
-    let escrow = Escrow::default();
-    escrow.pack_into_slice(data);
-    let escrow_acc = AccountInfo::new(pubkey, false, true, lamports, data, pubkey, false, 0);
-    escrow_acc
-}
+    /// Accounts of Exchange in the order the instruction expects them
+    pub fn exchange_accounts(&mut self) -> Vec<AccountInfo> {
+        vec![
+            /*  0 */ self.taker.account_info(),
+            /*  1 */ self.takers_sending_token_account.account_info(),
+            /*  2 */ self.takers_token_to_receive_account.account_info(),
+            /*  3 */ self.tmp_token_account.account_info(),
+            /*  4 */ self.initializer.account_info(),
+            /*  5 */ self.initializers_token_to_receive_account.account_info(),
+            /*  6 */ self.escrow_account.account_info(),
+            /*  7 */ self.token_program.account_info(),
+            /*  8 */ self.pda_account.account_info(),
+            /*  9 */ self.config_account.account_info(),
+            /* 10 */ self.treasury_token_account.account_info(),
+            /* 11 */ self.mint_x.account_info(),
+            /* 12 */ self.mint_y.account_info(),
+        ]
+    }
 
-// -----------------
-// Rent account
-// -----------------
-fn create_rent_acc<'a>(rent_pkey: &'a Pubkey, account: &'a mut Account) -> AccountInfo<'a> {
-    let rent_acc = AccountInfo::new(
-        rent_pkey,
-        false,
-        false,
-        &mut account.lamports,
-        &mut account.data,
-        &mut account.owner,
-        account.executable,
-        account.rent_epoch,
-    );
-    rent_acc
-}
+    /// Accounts of InitConfig in the order the instruction expects them
+    pub fn init_config_accounts(&mut self) -> Vec<AccountInfo> {
+        vec![
+            /* 0 */ self.admin.account_info(),
+            /* 1 */ self.config_account.account_info(),
+            /* 2 */ self.treasury.account_info(),
+            /* 3 */ self.system_program.account_info(),
+            /* 4 */ self.program_data.account_info(),
+        ]
+    }
 
-fn rent_sysvar() -> Account {
-    create_account_for_test(&Rent::default())
+    /// Accounts of UpdateConfig in the order the instruction expects them, handing the config
+    /// over to Bob and keeping the treasury
+    pub fn update_config_accounts(&mut self) -> Vec<AccountInfo> {
+        vec![
+            /* 0 */ self.admin.account_info(),
+            /* 1 */ self.config_account.account_info(),
+            /* 2 */ self.taker.account_info(),
+            /* 3 */ self.treasury.account_info(),
+        ]
+    }
+
+    /// Accounts of SetTreasuryAccount in the order the instruction expects them
+    pub fn set_treasury_account_accounts(&mut self) -> Vec<AccountInfo> {
+        vec![
+            /* 0 */ self.admin.account_info(),
+            /* 1 */ self.config_account.account_info(),
+            /* 2 */ self.treasury_token_account.account_info(),
+        ]
+    }
+
+    /// Accounts of CancelEscrow in the order the instruction expects them
+    pub fn cancel_escrow_accounts(&mut self) -> Vec<AccountInfo> {
+        vec![
+            /* 0 */ self.initializer.account_info(),
+            /* 1 */ self.tmp_token_account.account_info(),
+            /* 2 */ self.initializers_token_account.account_info(),
+            /* 3 */ self.escrow_account.account_info(),
+            /* 4 */ self.token_program.account_info(),
+            /* 5 */ self.pda_account.account_info(),
+            /* 6 */ self.mint_x.account_info(),
+        ]
+    }
 }
@@ -0,0 +1,338 @@
+use std::convert::TryInto;
+
+use solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    sysvar::Sysvar,
+};
+use spl_token::state::{Account as TokenAccount, Mint};
+
+use crate::error::EscrowError;
+
+/// Token-2022, shares the instruction and base account layouts of the classic token program.
+/// Of its extensions only transfer fees are supported, that is TransferFeeConfig on the mint and
+/// TransferFeeAmount on the token accounts. Mints with any other extension, say a transfer hook,
+/// interest or confidential transfers, are not supported.
+pub mod spl_token_2022 {
+    solana_program::declare_id!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+}
+
+/// Determines if the escrow supports tokens of the given token program
+pub fn is_token_program(program_id: &Pubkey) -> bool {
+    *program_id == spl_token::id() || *program_id == spl_token_2022::id()
+}
+
+pub fn check_token_program(token_program: &AccountInfo) -> ProgramResult {
+    if !is_token_program(token_program.key) {
+        return Err(EscrowError::InvalidTokenProgram.into());
+    }
+    Ok(())
+}
+
+// -----------------
+// Accounts
+// -----------------
+
+// Token-2022 appends an account type and extensions after the base layout
+const ACCOUNT_TYPE_OFFSET: usize = TokenAccount::LEN;
+const ACCOUNT_TYPE_MINT: u8 = 1;
+const EXTENSION_TYPE_TRANSFER_FEE_CONFIG: u16 = 1;
+/// Size of a token account holding the TransferFeeAmount extension that transfer fee mints
+/// require: base + account type + extension type + length + withheld amount
+const ACCOUNT_WITH_TRANSFER_FEE_AMOUNT_LEN: usize = TokenAccount::LEN + 1 + 2 + 2 + 8;
+
+/// Unpacks the base of a token account of either token program, ignoring any extensions
+pub fn unpack_account(account: &AccountInfo) -> Result<TokenAccount, ProgramError> {
+    if !is_token_program(account.owner) {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let data = account.data.borrow();
+    TokenAccount::unpack(
+        data.get(..TokenAccount::LEN)
+            .ok_or(ProgramError::InvalidAccountData)?,
+    )
+}
+
+/// Unpacks the base of a mint of either token program, ignoring any extensions
+pub fn unpack_mint(mint: &AccountInfo) -> Result<Mint, ProgramError> {
+    if !is_token_program(mint.owner) {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let data = mint.data.borrow();
+    Mint::unpack(
+        data.get(..Mint::LEN)
+            .ok_or(ProgramError::InvalidAccountData)?,
+    )
+}
+
+/// Size of a token account of the given mint, which only grows for Token-2022 transfer fee mints
+pub fn account_len(mint: &AccountInfo) -> usize {
+    if has_transfer_fee(mint) {
+        ACCOUNT_WITH_TRANSFER_FEE_AMOUNT_LEN
+    } else {
+        TokenAccount::LEN
+    }
+}
+
+// -----------------
+// Transfer Fees
+// -----------------
+
+/// Fee of a Token-2022 TransferFeeConfig extension which applies from `epoch` on
+#[derive(Debug, PartialEq)]
+struct TransferFee {
+    epoch: u64,
+    maximum_fee: u64,
+    transfer_fee_basis_points: u16,
+}
+
+impl TransferFee {
+    const LEN: usize = 8 + 8 + 2;
+
+    fn unpack(src: &[u8]) -> Self {
+        Self {
+            epoch: u64::from_le_bytes(src[..8].try_into().unwrap()),
+            maximum_fee: u64::from_le_bytes(src[8..16].try_into().unwrap()),
+            transfer_fee_basis_points: u16::from_le_bytes(src[16..18].try_into().unwrap()),
+        }
+    }
+
+    /// Same as Token-2022, rounds up in favor of the fee collector
+    fn fee(&self, amount: u64) -> u64 {
+        if self.transfer_fee_basis_points == 0 || amount == 0 {
+            return 0;
+        }
+        let fee = (amount as u128 * self.transfer_fee_basis_points as u128 + 9_999) / 10_000;
+        (fee as u64).min(self.maximum_fee)
+    }
+}
+
+/// Older and newer fee of the TransferFeeConfig extension of a mint, if it has one
+fn transfer_fee_config(mint_data: &[u8]) -> Option<(TransferFee, TransferFee)> {
+    if mint_data.get(ACCOUNT_TYPE_OFFSET) != Some(&ACCOUNT_TYPE_MINT) {
+        return None;
+    }
+    // Extensions are stored as type (u16) length (u16) value
+    let mut tlv = &mint_data[ACCOUNT_TYPE_OFFSET + 1..];
+    while tlv.len() >= 4 {
+        let extension_type = u16::from_le_bytes([tlv[0], tlv[1]]);
+        let len = u16::from_le_bytes([tlv[2], tlv[3]]) as usize;
+        let value = tlv.get(4..4 + len)?;
+        if extension_type == EXTENSION_TYPE_TRANSFER_FEE_CONFIG {
+            // Authorities (2 x 32 bytes) and withheld amount (8 bytes) precede the fees
+            let fees = value.get(72..72 + 2 * TransferFee::LEN)?;
+            return Some((
+                TransferFee::unpack(&fees[..TransferFee::LEN]),
+                TransferFee::unpack(&fees[TransferFee::LEN..]),
+            ));
+        }
+        tlv = &tlv[4 + len..];
+    }
+    None
+}
+
+fn transfer_fee_at(mint_data: &[u8], epoch: u64, amount: u64) -> u64 {
+    match transfer_fee_config(mint_data) {
+        Some((_, newer)) if epoch >= newer.epoch => newer.fee(amount),
+        Some((older, _)) => older.fee(amount),
+        None => 0,
+    }
+}
+
+/// Determines if the mint is a Token-2022 mint with the TransferFeeConfig extension
+pub fn has_transfer_fee(mint: &AccountInfo) -> bool {
+    transfer_fee_config(&mint.data.borrow()).is_some()
+}
+
+/// Fee the mint withholds when transferring `amount`, so that the recipient receives
+/// `amount - fee`. Only Token-2022 mints with a transfer fee charge one.
+/// Token-2022 caps the fee at 100%, a mint that claims more is read as is, so callers must not
+/// assume that the fee is at most `amount`.
+pub fn transfer_fee(mint: &AccountInfo, amount: u64) -> Result<u64, ProgramError> {
+    let mint_data = mint.data.borrow();
+    // Only mints that charge a fee need to pay for reading the clock
+    if transfer_fee_config(&mint_data).is_none() {
+        return Ok(0);
+    }
+    Ok(transfer_fee_at(&mint_data, Clock::get()?.epoch, amount))
+}
+
+// -----------------
+// Instructions
+// -----------------
+
+const TOKEN_INSTRUCTION_TRANSFER_FEE_EXTENSION: u8 = 26;
+const TRANSFER_FEE_INSTRUCTION_HARVEST_WITHHELD_TOKENS_TO_MINT: u8 = 4;
+
+/// Points an instruction built via the `spl_token` builders, which only accept the classic token
+/// program, to the given token program. Both programs share the instruction layout.
+pub fn for_token_program(
+    instruction: Result<Instruction, ProgramError>,
+    token_program_id: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let mut instruction = instruction?;
+    instruction.program_id = *token_program_id;
+    Ok(instruction)
+}
+
+pub fn transfer_checked(
+    token_program_id: &Pubkey,
+    source: &Pubkey,
+    mint: &Pubkey,
+    destination: &Pubkey,
+    authority: &Pubkey,
+    amount: u64,
+    decimals: u8,
+) -> Result<Instruction, ProgramError> {
+    for_token_program(
+        spl_token::instruction::transfer_checked(
+            &spl_token::id(),
+            source,
+            mint,
+            destination,
+            authority,
+            &[authority],
+            amount,
+            decimals,
+        ),
+        token_program_id,
+    )
+}
+
+/// Token-2022 TransferFeeExtension::HarvestWithheldTokensToMint, which anyone may invoke to move
+/// the fees withheld in token accounts to their mint
+pub fn harvest_withheld_tokens_to_mint(
+    token_program_id: &Pubkey,
+    mint: &Pubkey,
+    sources: &[&Pubkey],
+) -> Instruction {
+    let mut accounts = vec![AccountMeta::new(*mint, false)];
+    accounts.extend(
+        sources
+            .iter()
+            .map(|source| AccountMeta::new(**source, false)),
+    );
+    Instruction {
+        program_id: *token_program_id,
+        accounts,
+        data: vec![
+            TOKEN_INSTRUCTION_TRANSFER_FEE_EXTENSION,
+            TRANSFER_FEE_INSTRUCTION_HARVEST_WITHHELD_TOKENS_TO_MINT,
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Token-2022 mint with a TransferFeeConfig extension preceded by another extension
+    fn transfer_fee_mint(older: (u64, u64, u16), newer: (u64, u64, u16)) -> Vec<u8> {
+        let mut data = vec![0u8; TokenAccount::LEN];
+        Mint {
+            decimals: 6,
+            is_initialized: true,
+            ..Mint::default()
+        }
+        .pack_into_slice(&mut data[..Mint::LEN]);
+        data.push(ACCOUNT_TYPE_MINT);
+
+        // MintCloseAuthority
+        data.extend_from_slice(&3u16.to_le_bytes());
+        data.extend_from_slice(&32u16.to_le_bytes());
+        data.extend_from_slice(&[7u8; 32]);
+
+        data.extend_from_slice(&EXTENSION_TYPE_TRANSFER_FEE_CONFIG.to_le_bytes());
+        data.extend_from_slice(&108u16.to_le_bytes());
+        data.extend_from_slice(&[0u8; 72]);
+        for (epoch, maximum_fee, basis_points) in [older, newer] {
+            data.extend_from_slice(&epoch.to_le_bytes());
+            data.extend_from_slice(&maximum_fee.to_le_bytes());
+            data.extend_from_slice(&basis_points.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn token_programs() {
+        assert!(is_token_program(&spl_token::id()));
+        assert!(is_token_program(&spl_token_2022::id()));
+        assert!(!is_token_program(&Pubkey::new_unique()));
+    }
+
+    #[test]
+    fn unpack_extended_account() {
+        let owner = Pubkey::new_unique();
+        let mut data = vec![0u8; ACCOUNT_WITH_TRANSFER_FEE_AMOUNT_LEN];
+        TokenAccount {
+            owner,
+            amount: 42,
+            state: spl_token::state::AccountState::Initialized,
+            ..TokenAccount::default()
+        }
+        .pack_into_slice(&mut data[..TokenAccount::LEN]);
+        let key = Pubkey::new_unique();
+        let lamports = &mut 0;
+        let token_program_id = spl_token_2022::id();
+        let account = AccountInfo::new(
+            &key,
+            false,
+            false,
+            lamports,
+            &mut data,
+            &token_program_id,
+            false,
+            0,
+        );
+
+        let token_account = unpack_account(&account).expect("unpacks the base account");
+        assert_eq!(token_account.owner, owner);
+        assert_eq!(token_account.amount, 42);
+    }
+
+    #[test]
+    fn unpack_account_of_other_program() {
+        let key = Pubkey::new_unique();
+        let lamports = &mut 0;
+        let data = &mut [0u8; TokenAccount::LEN];
+        let owner = Pubkey::new_unique();
+        let account = AccountInfo::new(&key, false, false, lamports, data, &owner, false, 0);
+
+        assert_eq!(
+            unpack_account(&account),
+            Err(ProgramError::IncorrectProgramId)
+        );
+    }
+
+    #[test]
+    fn classic_mint_has_no_transfer_fee() {
+        let mut data = vec![0u8; Mint::LEN];
+        Mint {
+            is_initialized: true,
+            ..Mint::default()
+        }
+        .pack_into_slice(&mut data);
+        assert_eq!(transfer_fee_config(&data), None);
+        assert_eq!(transfer_fee_at(&data, 0, 1000), 0);
+    }
+
+    #[test]
+    fn transfer_fee_rounds_up() {
+        let data = transfer_fee_mint((0, u64::MAX, 100), (10, u64::MAX, 100));
+        assert_eq!(transfer_fee_at(&data, 0, 1000), 10, "1%");
+        assert_eq!(transfer_fee_at(&data, 0, 1001), 11, "rounded up");
+        assert_eq!(transfer_fee_at(&data, 0, 0), 0, "nothing transferred");
+    }
+
+    #[test]
+    fn transfer_fee_by_epoch() {
+        let data = transfer_fee_mint((0, u64::MAX, 100), (10, 5, 500));
+        assert_eq!(transfer_fee_at(&data, 9, 1000), 10, "older fee");
+        assert_eq!(transfer_fee_at(&data, 10, 1000), 5, "newer fee capped");
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt;
+
+use solana_program::{program_error::ProgramError, program_pack::Pack, pubkey::Pubkey};
+use thiserror::Error;
+
+use crate::state::{Escrow, EscrowV0};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ViewError {
+    /// Invalid Base64
+    #[error("Invalid Base64: {0}")]
+    InvalidBase64(#[from] base64::DecodeError),
+
+    /// Invalid Escrow Account
+    #[error("Invalid Escrow Account: {0}")]
+    InvalidEscrow(#[from] ProgramError),
+}
+
+/// Escrow decoded from the data of an escrow account, in any layout the program supports.
+/// Decodes via [Pack], thus always agrees with the program.
+#[derive(Debug)]
+pub enum EscrowView {
+    /// Escrow of the first release, which didn't record the mints
+    V0(EscrowV0),
+
+    /// Escrow in the layout of [Escrow::VERSION]
+    Current(Escrow),
+}
+
+impl EscrowView {
+    /// Decodes the raw data of an escrow account
+    pub fn from_bytes(data: &[u8]) -> Result<Self, ViewError> {
+        let view = match data.len() {
+            EscrowV0::LEN => EscrowView::V0(EscrowV0::unpack(data)?),
+            _ => EscrowView::Current(Escrow::unpack(data)?),
+        };
+        Ok(view)
+    }
+
+    /// Decodes the data of an escrow account dumped as base64, for example by
+    /// `solana account <escrow> --output json`
+    pub fn from_base64(dump: &str) -> Result<Self, ViewError> {
+        let data = base64::decode(dump.trim())?;
+        Self::from_bytes(&data)
+    }
+}
+
+/// Mint of a leg, or lamports for legs traded as native lamports
+struct Leg<'a>(&'a Pubkey, u8);
+
+impl fmt::Display for Leg<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Leg(mint, decimals) = self;
+        if **mint == Escrow::LAMPORTS_MINT {
+            write!(f, "lamports")
+        } else {
+            write!(f, "{} ({} decimals)", mint, decimals)
+        }
+    }
+}
+
+impl fmt::Display for EscrowView {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let escrow = match self {
+            EscrowView::V0(escrow) => {
+                writeln!(f, "Escrow (v0, needs to be migrated)")?;
+                writeln!(
+                    f,
+                    "  initializer:             {}",
+                    escrow.initializer_pubkey
+                )?;
+                writeln!(
+                    f,
+                    "  tmp token account:       {}",
+                    escrow.tmp_token_account_pubkey
+                )?;
+                writeln!(
+                    f,
+                    "  receiving account:       {}",
+                    escrow.initializer_token_to_receive_account_pubkey
+                )?;
+                return write!(
+                    f,
+                    "  expects:                 {} of the receiving account's mint",
+                    escrow.expected_amount
+                );
+            }
+            EscrowView::Current(escrow) => escrow,
+        };
+        writeln!(f, "Escrow (version {})", Escrow::VERSION)?;
+        writeln!(
+            f,
+            "  initializer:             {}",
+            escrow.initializer_pubkey
+        )?;
+        writeln!(
+            f,
+            "  tmp token account:       {}",
+            escrow.tmp_token_account_pubkey
+        )?;
+        writeln!(
+            f,
+            "  receiving account:       {}",
+            escrow.initializer_token_to_receive_account_pubkey
+        )?;
+        writeln!(
+            f,
+            "  offers:                  {}",
+            Leg(&escrow.mint_x_pubkey, escrow.mint_x_decimals)
+        )?;
+        writeln!(
+            f,
+            "  expects:                 {} {}",
+            escrow.expected_amount,
+            Leg(&escrow.mint_y_pubkey, escrow.mint_y_decimals)
+        )?;
+        match escrow.expires_at {
+            Some(expires_at) => writeln!(f, "  expires at:              {}", expires_at)?,
+            None => writeln!(f, "  expires at:              never")?,
+        }
+        match escrow.allowed_taker {
+            Some(allowed_taker) => writeln!(f, "  allowed taker:           {}", allowed_taker)?,
+            None => writeln!(f, "  allowed taker:           anyone")?,
+        }
+        write!(f, "  bump seed:               {}", escrow.bump_seed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn escrow() -> Escrow {
+        Escrow {
+            is_initialized: true,
+            initializer_pubkey: Pubkey::new_from_array([1; 32]),
+            tmp_token_account_pubkey: Pubkey::new_from_array([2; 32]),
+            initializer_token_to_receive_account_pubkey: Pubkey::new_from_array([3; 32]),
+            expected_amount: 100_000,
+            bump_seed: 254,
+            mint_x_pubkey: Pubkey::new_from_array([4; 32]),
+            mint_y_pubkey: Escrow::LAMPORTS_MINT,
+            mint_x_decimals: 6,
+            mint_y_decimals: Escrow::LAMPORTS_DECIMALS,
+            ..Escrow::default()
+        }
+    }
+
+    #[test]
+    fn decode_large_amount() {
+        let mut data = [0u8; Escrow::LEN];
+        escrow().pack_into_slice(&mut data);
+
+        match EscrowView::from_bytes(&data).unwrap() {
+            EscrowView::Current(escrow) => {
+                assert_eq!(escrow.expected_amount, 100_000, "more than a byte")
+            }
+            view => panic!("decoded {:?}", view),
+        }
+    }
+
+    #[test]
+    fn decode_base64_dump() {
+        let mut data = vec![0u8; Escrow::LEN];
+        escrow().pack_into_slice(&mut data);
+        let dump = format!("{}\n", base64::encode(&data));
+
+        match EscrowView::from_base64(&dump).unwrap() {
+            EscrowView::Current(decoded) => {
+                assert_eq!(decoded.initializer_pubkey, escrow().initializer_pubkey)
+            }
+            view => panic!("decoded {:?}", view),
+        }
+    }
+
+    #[test]
+    fn decode_v0() {
+        let mut data = [0u8; EscrowV0::LEN];
+        data[0] = 1;
+        data[1..33].copy_from_slice(&[1; 32]);
+        data[97..105].copy_from_slice(&100_000u64.to_le_bytes());
+
+        match EscrowView::from_bytes(&data).unwrap() {
+            EscrowView::V0(escrow) => {
+                assert_eq!(escrow.initializer_pubkey, Pubkey::new_from_array([1; 32]));
+                assert_eq!(escrow.expected_amount, 100_000);
+            }
+            view => panic!("decoded {:?}", view),
+        }
+    }
+
+    #[test]
+    fn decode_invalid_dumps() {
+        assert!(matches!(
+            EscrowView::from_base64("not base64!"),
+            Err(ViewError::InvalidBase64(_))
+        ));
+        assert_eq!(
+            EscrowView::from_bytes(&[0u8; Escrow::LEN]).unwrap_err(),
+            ViewError::InvalidEscrow(ProgramError::UninitializedAccount)
+        );
+        assert_eq!(
+            EscrowView::from_bytes(&[2u8; EscrowV0::LEN]).unwrap_err(),
+            ViewError::InvalidEscrow(ProgramError::InvalidAccountData)
+        );
+        assert_eq!(
+            EscrowView::from_bytes(&[1u8; 214]).unwrap_err(),
+            ViewError::InvalidEscrow(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn pretty_print() {
+        let mut data = [0u8; Escrow::LEN];
+        escrow().pack_into_slice(&mut data);
+        let view = EscrowView::from_bytes(&data).unwrap();
+
+        let printed = view.to_string();
+        assert!(printed.starts_with("Escrow (version 1)\n"), "{}", printed);
+        assert!(printed.contains(&format!(
+            "offers:                  {} (6 decimals)",
+            escrow().mint_x_pubkey
+        )));
+        assert!(printed.contains("expects:                 100000 lamports"));
+        assert!(printed.contains("expires at:              never"));
+        assert!(printed.contains("allowed taker:           anyone"));
+    }
+}
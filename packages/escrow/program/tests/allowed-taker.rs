@@ -0,0 +1,42 @@
+mod common;
+
+use common::{escrow_error, token_balance, Trade};
+use escrow::error::EscrowError;
+use solana_program::pubkey::Pubkey;
+use solana_program_test::tokio;
+use solana_sdk::signature::Signer;
+
+#[tokio::test]
+async fn exchange_by_allowed_taker() {
+    let mut trade = Trade::new(5000, 1000);
+    trade.allowed_taker = Some(trade.bob.pubkey());
+    let mut ctx = trade.program_test().start_with_context().await;
+
+    trade
+        .init_escrow(&mut ctx)
+        .await
+        .expect("Init Escrow succeeds");
+    trade
+        .exchange(&mut ctx)
+        .await
+        .expect("Designated taker can exchange");
+    assert_eq!(token_balance(&mut ctx, trade.bob_x).await, 5000);
+}
+
+#[tokio::test]
+async fn exchange_by_other_taker() {
+    let mut trade = Trade::new(5000, 1000);
+    trade.allowed_taker = Some(Pubkey::new_unique());
+    let mut ctx = trade.program_test().start_with_context().await;
+
+    trade
+        .init_escrow(&mut ctx)
+        .await
+        .expect("Init Escrow succeeds");
+    let err = trade.exchange(&mut ctx).await.unwrap_err().unwrap();
+    assert_eq!(
+        escrow_error(err),
+        Some(EscrowError::UnauthorizedTaker),
+        "Only the designated taker can exchange"
+    );
+}
@@ -0,0 +1,598 @@
+#![allow(dead_code)]
+
+use escrow::{
+    error::EscrowError,
+    instruction::{self, EscrowInstruction},
+    processor::Processor,
+    state::{Config, Escrow, EscrowV0},
+};
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+
+use solana_program::{
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_program, sysvar,
+};
+use solana_sdk::{
+    account::Account,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+    transport::TransportError,
+};
+use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+
+pub const MINT_DECIMALS: u8 = 0;
+
+// -----------------
+// Accounts
+// -----------------
+pub fn mint_account(mint_authority: &Pubkey, supply: u64) -> Account {
+    let mut data = vec![0u8; Mint::LEN];
+    Mint {
+        mint_authority: COption::Some(*mint_authority),
+        supply,
+        decimals: MINT_DECIMALS,
+        is_initialized: true,
+        freeze_authority: COption::None,
+    }
+    .pack_into_slice(&mut data);
+
+    Account {
+        lamports: Rent::default().minimum_balance(Mint::LEN),
+        data,
+        owner: spl_token::id(),
+        ..Account::default()
+    }
+}
+
+pub fn token_account(mint: &Pubkey, owner: &Pubkey, amount: u64) -> Account {
+    let mut data = vec![0u8; TokenAccount::LEN];
+    TokenAccount {
+        mint: *mint,
+        owner: *owner,
+        amount,
+        state: AccountState::Initialized,
+        ..TokenAccount::default()
+    }
+    .pack_into_slice(&mut data);
+
+    Account {
+        lamports: Rent::default().minimum_balance(TokenAccount::LEN),
+        data,
+        owner: spl_token::id(),
+        ..Account::default()
+    }
+}
+
+pub fn config_account(program_id: &Pubkey, config: Config) -> Account {
+    let mut data = vec![0u8; Config::LEN];
+    config.pack_into_slice(&mut data);
+    Account {
+        lamports: Rent::default().minimum_balance(Config::LEN),
+        data,
+        owner: *program_id,
+        ..Account::default()
+    }
+}
+
+pub fn config_pda(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[Config::PDA_SEED], program_id)
+}
+
+/// ProgramData account which the upgradeable loader keeps for `program_id`, without the program
+/// itself
+pub fn program_data_account(
+    program_id: &Pubkey,
+    upgrade_authority: Option<Pubkey>,
+) -> (Pubkey, Account) {
+    let mut data = bincode::serialize(&UpgradeableLoaderState::ProgramData {
+        slot: 0,
+        upgrade_authority_address: upgrade_authority,
+    })
+    .unwrap();
+    data.resize(
+        UpgradeableLoaderState::programdata_data_offset().unwrap(),
+        0,
+    );
+    let address =
+        Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id()).0;
+    let account = Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: bpf_loader_upgradeable::id(),
+        ..Account::default()
+    };
+    (address, account)
+}
+
+pub fn system_account(lamports: u64) -> Account {
+    Account {
+        lamports,
+        owner: system_program::id(),
+        ..Account::default()
+    }
+}
+
+pub fn escrow_account(program_id: &Pubkey) -> Account {
+    Account {
+        lamports: Rent::default().minimum_balance(Escrow::LEN),
+        data: vec![0u8; Escrow::LEN],
+        owner: *program_id,
+        ..Account::default()
+    }
+}
+
+// -----------------
+// Trade Setup
+// -----------------
+
+/// Alice offers `x_amount` of token X and expects `y_amount` of token Y in return, Bob takes the
+/// trade.
+pub struct Trade {
+    pub program_id: Pubkey,
+
+    pub alice: Keypair,
+    pub bob: Keypair,
+
+    pub mint_x: Pubkey,
+    pub mint_y: Pubkey,
+
+    /// Alice's tmp token account holding the X she offers, its authority moves to the PDA
+    pub alice_x_tmp: Pubkey,
+    /// Alice's main token X account which gets her tokens back if the trade doesn't happen
+    pub alice_x: Pubkey,
+    pub alice_y: Pubkey,
+    pub bob_x: Pubkey,
+    pub bob_y: Pubkey,
+
+    pub escrow: Pubkey,
+
+    /// Owns the treasury token accounts
+    pub treasury: Pubkey,
+    /// Treasury's token Y account which receives the fees
+    pub treasury_y: Pubkey,
+    /// Fee of the config, there is no config at all when `None`
+    pub fee_basis_points: Option<u16>,
+
+    pub x_amount: u64,
+    pub y_amount: u64,
+
+    pub expires_at: Option<i64>,
+    pub allowed_taker: Option<Pubkey>,
+}
+
+impl Trade {
+    pub fn new(x_amount: u64, y_amount: u64) -> Self {
+        Self {
+            program_id: Pubkey::new_unique(),
+            alice: Keypair::new(),
+            bob: Keypair::new(),
+            mint_x: Pubkey::new_unique(),
+            mint_y: Pubkey::new_unique(),
+            alice_x_tmp: Pubkey::new_unique(),
+            alice_x: Pubkey::new_unique(),
+            alice_y: Pubkey::new_unique(),
+            bob_x: Pubkey::new_unique(),
+            bob_y: Pubkey::new_unique(),
+            escrow: Pubkey::new_unique(),
+            treasury: Pubkey::new_unique(),
+            treasury_y: Pubkey::new_unique(),
+            fee_basis_points: Some(0),
+            x_amount,
+            y_amount,
+            expires_at: None,
+            allowed_taker: None,
+        }
+    }
+
+    /// Creates a [ProgramTest] with the escrow program and all accounts involved in the trade.
+    /// Bob starts out with exactly the amount of token Y that Alice expects.
+    pub fn program_test(&self) -> ProgramTest {
+        let mut program_test = self.program_test_without_escrow(0);
+
+        program_test.add_account(
+            self.alice_x_tmp,
+            token_account(&self.mint_x, &self.alice.pubkey(), self.x_amount),
+        );
+        program_test.add_account(self.escrow, escrow_account(&self.program_id));
+
+        program_test
+    }
+
+    /// Same as [Trade::program_test], but without the escrow account which the program creates
+    /// itself via InitEscrowPda
+    pub fn program_test_without_escrow_account(&self) -> ProgramTest {
+        let mut program_test = self.program_test_without_escrow(0);
+
+        // Alice pays for the escrow account
+        program_test.add_account(self.alice.pubkey(), system_account(1_000_000_000));
+
+        program_test.add_account(
+            self.alice_x_tmp,
+            token_account(&self.mint_x, &self.alice.pubkey(), self.x_amount),
+        );
+
+        program_test
+    }
+
+    /// Same as [Trade::program_test], but without the tmp token account which the program creates
+    /// itself via InitEscrowWithDeposit. Alice holds her token X in her main account instead.
+    pub fn program_test_without_tmp_account(&self) -> ProgramTest {
+        let mut program_test = self.program_test_without_escrow(self.x_amount);
+
+        // Alice pays for the vault
+        program_test.add_account(self.alice.pubkey(), system_account(1_000_000_000));
+        program_test.add_account(self.escrow, escrow_account(&self.program_id));
+
+        program_test
+    }
+
+    /// Same as [Trade::program_test], but with the accounts in the state that a successful
+    /// InitEscrow leaves them in, so that Exchange can be tested in isolation.
+    pub fn initialized_program_test(&self) -> ProgramTest {
+        let mut program_test = self.program_test_without_escrow(0);
+
+        let (pda, bump_seed) = self.pda_and_bump_seed();
+        program_test.add_account(
+            self.alice_x_tmp,
+            token_account(&self.mint_x, &pda, self.x_amount),
+        );
+
+        let mut escrow_account = escrow_account(&self.program_id);
+        Escrow {
+            is_initialized: true,
+            initializer_pubkey: self.alice.pubkey(),
+            tmp_token_account_pubkey: self.alice_x_tmp,
+            initializer_token_to_receive_account_pubkey: self.alice_y,
+            expected_amount: self.y_amount,
+            bump_seed,
+            expires_at: self.expires_at,
+            allowed_taker: self.allowed_taker,
+            mint_x_pubkey: self.mint_x,
+            mint_y_pubkey: self.mint_y,
+            mint_x_decimals: MINT_DECIMALS,
+            mint_y_decimals: MINT_DECIMALS,
+        }
+        .pack_into_slice(&mut escrow_account.data);
+        program_test.add_account(self.escrow, escrow_account);
+
+        program_test
+    }
+
+    /// Same as [Trade::initialized_program_test], but with the escrow stored the way the first
+    /// release of the program stored it, see [EscrowV0]
+    pub fn v0_program_test(&self) -> ProgramTest {
+        let mut program_test = self.program_test_without_escrow(0);
+
+        program_test.add_account(
+            self.alice_x_tmp,
+            token_account(&self.mint_x, &self.v0_pda(), self.x_amount),
+        );
+
+        // Built by hand rather than via EscrowV0, so that the test breaks if the layout drifts
+        let mut data = Vec::with_capacity(EscrowV0::LEN);
+        data.push(1);
+        data.extend_from_slice(self.alice.pubkey().as_ref());
+        data.extend_from_slice(self.alice_x_tmp.as_ref());
+        data.extend_from_slice(self.alice_y.as_ref());
+        data.extend_from_slice(&self.y_amount.to_le_bytes());
+        program_test.add_account(
+            self.escrow,
+            Account {
+                lamports: Rent::default().minimum_balance(data.len()),
+                data,
+                owner: self.program_id,
+                ..Account::default()
+            },
+        );
+
+        program_test
+    }
+
+    fn program_test_without_escrow(&self, alice_x_amount: u64) -> ProgramTest {
+        let mut program_test =
+            ProgramTest::new("escrow", self.program_id, processor!(Processor::process));
+
+        let alice = self.alice.pubkey();
+        let bob = self.bob.pubkey();
+        let mint_authority = Pubkey::new_unique();
+
+        program_test.add_account(self.mint_x, mint_account(&mint_authority, self.x_amount));
+        program_test.add_account(self.mint_y, mint_account(&mint_authority, self.y_amount));
+
+        program_test.add_account(
+            self.alice_x,
+            token_account(&self.mint_x, &alice, alice_x_amount),
+        );
+        program_test.add_account(self.alice_y, token_account(&self.mint_y, &alice, 0));
+        program_test.add_account(self.bob_x, token_account(&self.mint_x, &bob, 0));
+        program_test.add_account(self.bob_y, token_account(&self.mint_y, &bob, self.y_amount));
+
+        if let Some(fee_basis_points) = self.fee_basis_points {
+            let (config_pda, bump_seed) = config_pda(&self.program_id);
+            let mut config = Config {
+                is_initialized: true,
+                admin_pubkey: Pubkey::new_unique(),
+                fee_basis_points,
+                treasury_pubkey: self.treasury,
+                bump_seed,
+                ..Config::default()
+            };
+            config
+                .set_treasury_account(&self.mint_y, &self.treasury_y)
+                .unwrap();
+            program_test.add_account(config_pda, config_account(&self.program_id, config));
+        }
+        program_test.add_account(
+            self.treasury_y,
+            token_account(&self.mint_y, &self.treasury, 0),
+        );
+
+        program_test
+    }
+
+    pub fn pda(&self) -> Pubkey {
+        self.pda_and_bump_seed().0
+    }
+
+    fn pda_and_bump_seed(&self) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[Escrow::PDA_SEED, self.escrow.as_ref()], &self.program_id)
+    }
+
+    /// PDA which owns the tmp token accounts of all v0 escrows
+    pub fn v0_pda(&self) -> Pubkey {
+        Pubkey::find_program_address(&[Escrow::PDA_SEED], &self.program_id).0
+    }
+
+    /// Address of the tmp token account when the program creates it via InitEscrowWithDeposit
+    pub fn vault(&self) -> Pubkey {
+        Pubkey::find_program_address(
+            &[Escrow::VAULT_SEED, self.escrow.as_ref()],
+            &self.program_id,
+        )
+        .0
+    }
+
+    /// Address of the escrow account when the program creates it via InitEscrowPda
+    pub fn escrow_account_pda(&self) -> Pubkey {
+        Pubkey::find_program_address(
+            &[
+                Escrow::ACCOUNT_SEED,
+                self.alice.pubkey().as_ref(),
+                self.alice_x_tmp.as_ref(),
+            ],
+            &self.program_id,
+        )
+        .0
+    }
+
+    // -----------------
+    // Instructions
+    // -----------------
+    pub fn init_escrow_ix(&self) -> Instruction {
+        instruction::init_escrow(
+            &self.program_id,
+            &self.alice.pubkey(),
+            &self.alice_x_tmp,
+            &self.alice_y,
+            &self.escrow,
+            &spl_token::id(),
+            &self.mint_x,
+            &self.mint_y,
+            self.y_amount,
+            self.expires_at,
+            self.allowed_taker,
+        )
+    }
+
+    pub fn init_escrow_pda_ix(&self) -> Instruction {
+        let ix_data = EscrowInstruction::InitEscrowPda {
+            amount: self.y_amount,
+            expires_at: self.expires_at,
+            allowed_taker: self.allowed_taker,
+        }
+        .pack();
+        Instruction::new_with_bytes(
+            self.program_id,
+            &ix_data,
+            vec![
+                AccountMeta::new(self.alice.pubkey(), true),
+                AccountMeta::new(self.alice_x_tmp, false),
+                AccountMeta::new_readonly(self.alice_y, false),
+                AccountMeta::new(self.escrow, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(self.mint_x, false),
+                AccountMeta::new_readonly(self.mint_y, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ],
+        )
+    }
+
+    pub fn init_escrow_with_deposit_ix(&self) -> Instruction {
+        let ix_data = EscrowInstruction::InitEscrowWithDeposit {
+            deposit: self.x_amount,
+            amount: self.y_amount,
+            expires_at: self.expires_at,
+            allowed_taker: self.allowed_taker,
+        }
+        .pack();
+        Instruction::new_with_bytes(
+            self.program_id,
+            &ix_data,
+            vec![
+                AccountMeta::new(self.alice.pubkey(), true),
+                AccountMeta::new(self.alice_x_tmp, false),
+                AccountMeta::new_readonly(self.alice_y, false),
+                AccountMeta::new(self.escrow, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new(self.alice_x, false),
+                AccountMeta::new_readonly(self.mint_x, false),
+                AccountMeta::new_readonly(self.pda(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+                AccountMeta::new_readonly(self.mint_y, false),
+            ],
+        )
+    }
+
+    pub fn exchange_ix(&self) -> Instruction {
+        instruction::exchange(
+            &self.program_id,
+            &self.bob.pubkey(),
+            &self.bob_y,
+            &self.bob_x,
+            &self.alice_x_tmp,
+            &self.alice.pubkey(),
+            &self.alice_y,
+            &self.escrow,
+            &spl_token::id(),
+            &self.treasury_y,
+            &self.mint_x,
+            &self.mint_y,
+            self.x_amount,
+        )
+    }
+
+    /// Exchange of the escrow as created by [Trade::v0_program_test]
+    pub fn exchange_v0_ix(&self) -> Instruction {
+        instruction::exchange_v0(
+            &self.program_id,
+            &self.bob.pubkey(),
+            &self.bob_y,
+            &self.bob_x,
+            &self.alice_x_tmp,
+            &self.alice.pubkey(),
+            &self.alice_y,
+            &self.escrow,
+            &spl_token::id(),
+            &self.treasury_y,
+            &self.mint_x,
+            &self.mint_y,
+            self.x_amount,
+        )
+    }
+
+    pub fn partial_exchange_ix(&self, amount: u64, expected_amount: u64) -> Instruction {
+        let ix_data = EscrowInstruction::PartialExchange {
+            amount,
+            expected_amount,
+        }
+        .pack();
+        let mut ix = self.exchange_ix();
+        ix.data = ix_data;
+        ix
+    }
+
+    pub fn migrate_escrow_ix(&self, new_escrow: &Pubkey) -> Instruction {
+        instruction::migrate_escrow(
+            &self.program_id,
+            &self.alice.pubkey(),
+            &self.escrow,
+            new_escrow,
+            &self.alice_x_tmp,
+            &self.alice_y,
+            &spl_token::id(),
+            &self.mint_x,
+            &self.mint_y,
+        )
+    }
+
+    pub fn cancel_escrow_ix(&self) -> Instruction {
+        let ix_data = EscrowInstruction::CancelEscrow.pack();
+        self.return_deposit_ix(ix_data, true)
+    }
+
+    pub fn reclaim_expired_ix(&self) -> Instruction {
+        let ix_data = EscrowInstruction::ReclaimExpired.pack();
+        self.return_deposit_ix(ix_data, false)
+    }
+
+    fn return_deposit_ix(&self, ix_data: Vec<u8>, alice_signs: bool) -> Instruction {
+        Instruction::new_with_bytes(
+            self.program_id,
+            &ix_data,
+            vec![
+                AccountMeta::new(self.alice.pubkey(), alice_signs),
+                AccountMeta::new(self.alice_x_tmp, false),
+                AccountMeta::new(self.alice_x, false),
+                AccountMeta::new(self.escrow, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(self.pda(), false),
+                AccountMeta::new_readonly(self.mint_x, false),
+            ],
+        )
+    }
+
+    // -----------------
+    // Transactions
+    // -----------------
+    pub async fn init_escrow(&self, ctx: &mut ProgramTestContext) -> Result<(), TransportError> {
+        let ix = self.init_escrow_ix();
+        process_ix(ctx, ix, &self.alice).await
+    }
+
+    pub async fn exchange(&self, ctx: &mut ProgramTestContext) -> Result<(), TransportError> {
+        let ix = self.exchange_ix();
+        process_ix(ctx, ix, &self.bob).await
+    }
+}
+
+pub async fn escrow_state(ctx: &mut ProgramTestContext, pubkey: Pubkey) -> Option<Escrow> {
+    ctx.banks_client
+        .get_account(pubkey)
+        .await
+        .unwrap()
+        .map(|account| Escrow::unpack(&account.data).unwrap())
+}
+
+pub async fn account_exists(ctx: &mut ProgramTestContext, pubkey: Pubkey) -> bool {
+    ctx.banks_client
+        .get_account(pubkey)
+        .await
+        .unwrap()
+        .is_some()
+}
+
+pub async fn process_ix(
+    ctx: &mut ProgramTestContext,
+    ix: Instruction,
+    signer: &Keypair,
+) -> Result<(), TransportError> {
+    let mut transaction = Transaction::new_with_payer(&[ix], Some(&ctx.payer.pubkey()));
+    transaction.sign(&[&ctx.payer, signer], ctx.last_blockhash);
+    ctx.banks_client.process_transaction(transaction).await
+}
+
+/// Processes an instruction that only needs to be signed by the payer
+pub async fn process_unsigned_ix(
+    ctx: &mut ProgramTestContext,
+    ix: Instruction,
+) -> Result<(), TransportError> {
+    let mut transaction = Transaction::new_with_payer(&[ix], Some(&ctx.payer.pubkey()));
+    transaction.sign(&[&ctx.payer], ctx.last_blockhash);
+    ctx.banks_client.process_transaction(transaction).await
+}
+
+/// The escrow error a transaction failed with, if it failed with one
+pub fn escrow_error(err: TransactionError) -> Option<EscrowError> {
+    match err {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => {
+            EscrowError::from_code(code)
+        }
+        _ => None,
+    }
+}
+
+pub async fn token_balance(ctx: &mut ProgramTestContext, pubkey: Pubkey) -> u64 {
+    let account = ctx
+        .banks_client
+        .get_account(pubkey)
+        .await
+        .unwrap()
+        .expect("token account exists");
+    TokenAccount::unpack(&account.data).unwrap().amount
+}
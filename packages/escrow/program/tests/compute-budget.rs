@@ -0,0 +1,50 @@
+// Compute units are only metered when the program runs as BPF, run via `make test`
+#![cfg(feature = "test-bpf")]
+
+mod common;
+
+use common::Trade;
+use solana_program::instruction::InstructionError;
+use solana_program_test::tokio;
+use solana_sdk::transaction::TransactionError;
+
+// Exchange covers the three token program CPIs plus a single create_program_address, it rebuilds
+// the PDA from the bump seed stored during InitEscrow.
+// How much individual steps cost, like finding the PDA compared to rebuilding it, is compared in
+// compute-trace.rs, run via `make test-trace`.
+#[cfg(feature = "trace-compute")]
+const EXCHANGE_COMPUTE_UNITS: u64 = 24_000;
+#[cfg(not(feature = "trace-compute"))]
+const EXCHANGE_COMPUTE_UNITS: u64 = 22_000;
+
+const FAILED_TO_COMPLETE: TransactionError =
+    TransactionError::InstructionError(0, InstructionError::ProgramFailedToComplete);
+const COMPUTATIONAL_BUDGET_EXCEEDED: TransactionError =
+    TransactionError::InstructionError(0, InstructionError::ComputationalBudgetExceeded);
+
+#[tokio::test]
+async fn exchange_within_compute_budget() {
+    let trade = Trade::new(5000, 1000);
+    let mut program_test = trade.initialized_program_test();
+    program_test.set_bpf_compute_max_units(EXCHANGE_COMPUTE_UNITS);
+    let mut ctx = program_test.start_with_context().await;
+
+    trade
+        .exchange(&mut ctx)
+        .await
+        .expect("Exchange fits into compute budget");
+}
+
+#[tokio::test]
+async fn exchange_not_enough_units() {
+    let trade = Trade::new(5000, 1000);
+    let mut program_test = trade.initialized_program_test();
+    program_test.set_bpf_compute_max_units(EXCHANGE_COMPUTE_UNITS / 2);
+    let mut ctx = program_test.start_with_context().await;
+
+    let err = trade.exchange(&mut ctx).await.unwrap_err().unwrap();
+    assert!(
+        err == FAILED_TO_COMPLETE || err == COMPUTATIONAL_BUDGET_EXCEEDED,
+        "exceeded computations"
+    );
+}
@@ -0,0 +1,139 @@
+// Compares the compute units spent in the `compute!` blocks of the program, which only log them
+// when the program runs as BPF built with `trace-compute`, run via `make test-trace`
+#![cfg(all(feature = "test-bpf", feature = "trace-compute"))]
+
+mod common;
+
+use common::{process_ix, Trade};
+use lazy_static::lazy_static;
+use log::{LevelFilter, Log, Metadata, Record};
+use solana_program::pubkey::Pubkey;
+use solana_program_test::{tokio, ProgramTest};
+use std::sync::{Mutex, Once};
+
+lazy_static! {
+    /// Every line the runtime logged for the programs of this test binary
+    static ref LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    /// Serializes the tests, their logs would interleave otherwise
+    static ref SERIAL: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+static RECORD_LOGS: Once = Once::new();
+
+/// The banks client of this runtime doesn't return the logs of a transaction, but the runtime
+/// passes them on to the `log` crate. Installed before the program test installs its own logger,
+/// which then fails to replace this one.
+struct RecordingLogger;
+
+impl Log for RecordingLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        if record.target() == "solana_runtime::message_processor" {
+            LOGS.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Creates a program test via `program_test`, recording the program logs from then on
+fn recorded(program_test: impl FnOnce() -> ProgramTest) -> ProgramTest {
+    RECORD_LOGS.call_once(|| {
+        log::set_boxed_logger(Box::new(RecordingLogger)).expect("no logger installed yet");
+        log::set_max_level(LevelFilter::Debug);
+    });
+    LOGS.lock().unwrap().clear();
+    program_test()
+}
+
+/// Compute units spent in the last run of the `compute!` block with the given name
+fn block_units(name: &str) -> u64 {
+    let logs = LOGS.lock().unwrap().clone();
+    let start = format!("Program log: {} {{", name);
+    let start = logs
+        .iter()
+        .rposition(|line| *line == start)
+        .unwrap_or_else(|| panic!("\"{}\" ran", name));
+    let mut remaining = logs[start..].iter().filter_map(|line| {
+        line.strip_prefix("Program consumption: ")?
+            .strip_suffix(" units remaining")?
+            .parse::<u64>()
+            .ok()
+    });
+    let before = remaining
+        .next()
+        .expect("units logged at the start of the block");
+    let after = remaining
+        .next()
+        .expect("units logged at the end of the block");
+    before - after
+}
+
+#[tokio::test]
+async fn loading_escrow_is_cheaper_than_unpacking() {
+    let _serial = SERIAL.lock().await;
+
+    // Exchange reads the escrow in place
+    let trade = Trade::new(5000, 1000);
+    let mut ctx = recorded(|| trade.initialized_program_test())
+        .start_with_context()
+        .await;
+    trade.exchange(&mut ctx).await.unwrap();
+    let load_units = block_units("load escrow");
+
+    // CancelEscrow still unpacks the same escrow
+    let trade = Trade::new(5000, 1000);
+    let mut ctx = recorded(|| trade.initialized_program_test())
+        .start_with_context()
+        .await;
+    process_ix(&mut ctx, trade.cancel_escrow_ix(), &trade.alice)
+        .await
+        .unwrap();
+    let unpack_units = block_units("unpack escrow");
+
+    assert!(
+        load_units < unpack_units,
+        "loading took {} units, unpacking {} units",
+        load_units,
+        unpack_units
+    );
+}
+
+#[tokio::test]
+async fn creating_pda_is_cheaper_than_finding_it() {
+    let _serial = SERIAL.lock().await;
+
+    // find_program_address tries one bump seed after the other, starting at 255, thus how much
+    // it costs depends on the escrow. Averaged over a fixed set of escrow accounts, whatever
+    // their bump seeds turn out to be.
+    const ESCROWS: u8 = 16;
+    let program_id = Pubkey::new_from_array([0xe5; 32]);
+    let (mut find_units, mut create_units) = (0, 0);
+    for i in 1..=ESCROWS {
+        let mut trade = Trade::new(5000, 1000);
+        trade.program_id = program_id;
+        trade.escrow = Pubkey::new_from_array([i; 32]);
+        let mut ctx = recorded(|| trade.program_test()).start_with_context().await;
+
+        // InitEscrow searches for the PDA, Exchange rebuilds it from the stored bump seed
+        trade.init_escrow(&mut ctx).await.unwrap();
+        find_units += block_units("find pda");
+        trade.exchange(&mut ctx).await.unwrap();
+        create_units += block_units("create pda");
+    }
+    let (find_units, create_units) = (find_units / ESCROWS as u64, create_units / ESCROWS as u64);
+
+    println!(
+        "creating took {} units, finding {} units on average",
+        create_units, find_units
+    );
+    assert!(
+        create_units < find_units,
+        "creating took {} units, finding {} units on average",
+        create_units,
+        find_units
+    );
+}
@@ -0,0 +1,292 @@
+mod common;
+
+use common::{
+    config_account, config_pda, escrow_error, process_ix, program_data_account, system_account,
+    token_balance, Trade,
+};
+use escrow::{
+    error::EscrowError, instruction::EscrowInstruction, processor::Processor, state::Config,
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_program,
+};
+use solana_program_test::{processor, tokio, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    signature::{Keypair, Signer},
+    transport::TransportError,
+};
+
+/// Starts the program as deployed by `upgrade_authority`, who can pay for the config account
+async fn start_program(program_id: Pubkey, upgrade_authority: Pubkey) -> ProgramTestContext {
+    let mut program_test = ProgramTest::new("escrow", program_id, processor!(Processor::process));
+    let (program_data, program_data_account) =
+        program_data_account(&program_id, Some(upgrade_authority));
+    program_test.add_account(program_data, program_data_account);
+    program_test.add_account(upgrade_authority, system_account(1_000_000_000));
+    program_test.start_with_context().await
+}
+
+/// Starts the program with a config account as InitConfig leaves it, since natively the program
+/// test cannot create accounts via a system program CPI
+async fn start_program_with_config(program_id: Pubkey, admin: Pubkey) -> ProgramTestContext {
+    let mut program_test = ProgramTest::new("escrow", program_id, processor!(Processor::process));
+    let (config_pda, bump_seed) = config_pda(&program_id);
+    let config = Config {
+        is_initialized: true,
+        admin_pubkey: admin,
+        fee_basis_points: 250,
+        treasury_pubkey: Pubkey::new_unique(),
+        bump_seed,
+        ..Config::default()
+    };
+    program_test.add_account(config_pda, config_account(&program_id, config));
+    program_test.start_with_context().await
+}
+
+async fn process_init_config(
+    ctx: &mut ProgramTestContext,
+    program_id: Pubkey,
+    admin: &Keypair,
+    treasury: Pubkey,
+    fee_basis_points: u16,
+) -> Result<(), TransportError> {
+    let ix = Instruction::new_with_bytes(
+        program_id,
+        &EscrowInstruction::InitConfig { fee_basis_points }.pack(),
+        vec![
+            AccountMeta::new(admin.pubkey(), true),
+            AccountMeta::new(config_pda(&program_id).0, false),
+            AccountMeta::new_readonly(treasury, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(program_data_account(&program_id, None).0, false),
+        ],
+    );
+    process_ix(ctx, ix, admin).await
+}
+
+fn update_config_ix(
+    program_id: Pubkey,
+    admin: Pubkey,
+    new_admin: Pubkey,
+    treasury: Pubkey,
+    fee_basis_points: u16,
+) -> Instruction {
+    Instruction::new_with_bytes(
+        program_id,
+        &EscrowInstruction::UpdateConfig { fee_basis_points }.pack(),
+        vec![
+            AccountMeta::new_readonly(admin, true),
+            AccountMeta::new(config_pda(&program_id).0, false),
+            AccountMeta::new_readonly(new_admin, false),
+            AccountMeta::new_readonly(treasury, false),
+        ],
+    )
+}
+
+async fn config_state(ctx: &mut ProgramTestContext, program_id: Pubkey) -> Config {
+    let account = ctx
+        .banks_client
+        .get_account(config_pda(&program_id).0)
+        .await
+        .unwrap()
+        .expect("config account exists");
+    Config::unpack(&account.data).unwrap()
+}
+
+// -----------------
+// Tests
+// -----------------
+// Creating the config account via the system program is only supported when running as BPF
+#[cfg(feature = "test-bpf")]
+#[tokio::test]
+async fn init_config() {
+    let program_id = Pubkey::new_unique();
+    let admin = Keypair::new();
+    let treasury = Pubkey::new_unique();
+    let mut ctx = start_program(program_id, admin.pubkey()).await;
+
+    process_init_config(&mut ctx, program_id, &admin, treasury, 250)
+        .await
+        .expect("Init Config succeeds");
+
+    let config = config_state(&mut ctx, program_id).await;
+    assert_eq!(config.admin_pubkey, admin.pubkey());
+    assert_eq!(config.fee_basis_points, 250);
+    assert_eq!(config.treasury_pubkey, treasury);
+}
+
+#[tokio::test]
+async fn update_config() {
+    let program_id = Pubkey::new_unique();
+    let admin = Keypair::new();
+    let mut ctx = start_program_with_config(program_id, admin.pubkey()).await;
+
+    let new_admin = Keypair::new();
+    let new_treasury = Pubkey::new_unique();
+    let ix = update_config_ix(
+        program_id,
+        admin.pubkey(),
+        new_admin.pubkey(),
+        new_treasury,
+        100,
+    );
+    process_ix(&mut ctx, ix, &admin)
+        .await
+        .expect("Update Config succeeds");
+
+    let config = config_state(&mut ctx, program_id).await;
+    assert_eq!(config.admin_pubkey, new_admin.pubkey());
+    assert_eq!(config.fee_basis_points, 100);
+    assert_eq!(config.treasury_pubkey, new_treasury);
+}
+
+#[tokio::test]
+async fn init_config_invalid_fee() {
+    let program_id = Pubkey::new_unique();
+    let admin = Keypair::new();
+    let mut ctx = start_program(program_id, admin.pubkey()).await;
+
+    let err = process_init_config(&mut ctx, program_id, &admin, Pubkey::new_unique(), 10_001)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(escrow_error(err), Some(EscrowError::InvalidFee));
+}
+
+#[tokio::test]
+async fn init_config_not_upgrade_authority() {
+    let program_id = Pubkey::new_unique();
+    let mut ctx = start_program(program_id, Pubkey::new_unique()).await;
+
+    // Mallory tries to claim the config before the deployer does
+    let mallory = Keypair::new();
+    let err = process_init_config(&mut ctx, program_id, &mallory, mallory.pubkey(), 10_000)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(escrow_error(err), Some(EscrowError::UnauthorizedAdmin));
+}
+
+#[tokio::test]
+async fn update_config_not_admin() {
+    let program_id = Pubkey::new_unique();
+    let mut ctx = start_program_with_config(program_id, Pubkey::new_unique()).await;
+
+    let mallory = Keypair::new();
+    let ix = update_config_ix(
+        program_id,
+        mallory.pubkey(),
+        mallory.pubkey(),
+        mallory.pubkey(),
+        10_000,
+    );
+    let err = process_ix(&mut ctx, ix, &mallory)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(escrow_error(err), Some(EscrowError::UnauthorizedAdmin));
+}
+
+#[tokio::test]
+async fn exchange_with_fee() {
+    let mut trade = Trade::new(5000, 1000);
+    trade.fee_basis_points = Some(250);
+    let mut ctx = trade.initialized_program_test().start_with_context().await;
+
+    trade.exchange(&mut ctx).await.expect("Exchange succeeds");
+
+    assert_eq!(
+        token_balance(&mut ctx, trade.alice_y).await,
+        975,
+        "Alice receives Bob's tokens minus the fee"
+    );
+    assert_eq!(
+        token_balance(&mut ctx, trade.treasury_y).await,
+        25,
+        "Treasury receives the fee"
+    );
+    assert_eq!(token_balance(&mut ctx, trade.bob_x).await, 5000);
+}
+
+#[tokio::test]
+async fn exchange_with_foreign_treasury_account() {
+    let mut trade = Trade::new(5000, 1000);
+    trade.fee_basis_points = Some(250);
+    let mut ctx = trade.initialized_program_test().start_with_context().await;
+
+    // Bob tries to pay the fee to himself
+    let mut ix = trade.exchange_ix();
+    ix.accounts[10] = AccountMeta::new(trade.bob_y, false);
+    let err = process_ix(&mut ctx, ix, &trade.bob)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(escrow_error(err), Some(EscrowError::InvalidTreasuryAccount));
+}
+
+#[tokio::test]
+async fn exchange_without_config() {
+    let mut trade = Trade::new(5000, 1000);
+    trade.fee_basis_points = None;
+    let mut ctx = trade.initialized_program_test().start_with_context().await;
+
+    trade
+        .exchange(&mut ctx)
+        .await
+        .expect("Exchange succeeds before the config was initialized");
+
+    assert_eq!(
+        token_balance(&mut ctx, trade.alice_y).await,
+        1000,
+        "Alice receives all of Bob's tokens"
+    );
+    assert_eq!(token_balance(&mut ctx, trade.bob_x).await, 5000);
+}
+
+#[tokio::test]
+async fn exchange_without_config_account() {
+    let mut trade = Trade::new(5000, 1000);
+    trade.fee_basis_points = Some(250);
+    let mut ctx = trade.initialized_program_test().start_with_context().await;
+
+    // Bob tries to dodge the fee by pretending there is no config
+    let mut ix = trade.exchange_ix();
+    ix.accounts[9] = AccountMeta::new_readonly(Pubkey::new_unique(), false);
+    let err = process_ix(&mut ctx, ix, &trade.bob)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(escrow_error(err), Some(EscrowError::InvalidConfigAccount));
+}
+
+#[tokio::test]
+async fn exchange_without_treasury_account() {
+    let mut trade = Trade::new(5000, 1000);
+    trade.fee_basis_points = Some(250);
+    let mut program_test = trade.initialized_program_test();
+
+    // The treasury has no token account for token Y
+    let (config_pda, bump_seed) = config_pda(&trade.program_id);
+    let config = Config {
+        is_initialized: true,
+        admin_pubkey: Pubkey::new_unique(),
+        fee_basis_points: 250,
+        treasury_pubkey: trade.treasury,
+        bump_seed,
+        ..Config::default()
+    };
+    program_test.add_account(config_pda, config_account(&trade.program_id, config));
+    let mut ctx = program_test.start_with_context().await;
+
+    trade.exchange(&mut ctx).await.expect("Exchange succeeds");
+
+    assert_eq!(
+        token_balance(&mut ctx, trade.alice_y).await,
+        1000,
+        "No fee is charged in tokens the treasury can't receive"
+    );
+    assert_eq!(token_balance(&mut ctx, trade.treasury_y).await, 0);
+}
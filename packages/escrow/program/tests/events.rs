@@ -0,0 +1,189 @@
+mod common;
+
+use common::{process_ix, Trade};
+use escrow::event::EscrowEvent;
+use lazy_static::lazy_static;
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::Instruction,
+    program_stubs::{self, SyscallStubs},
+    pubkey::Pubkey,
+};
+use solana_program_test::{tokio, ProgramTest, ProgramTestContext};
+use solana_sdk::signature::Signer;
+use std::sync::{Mutex, RwLock};
+
+lazy_static! {
+    /// Every line logged by programs of this test binary
+    static ref LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    /// Stubs of the program test which do the actual work
+    static ref INNER_STUBS: RwLock<Option<Box<dyn SyscallStubs>>> = RwLock::new(None);
+    /// Serializes starting program tests until the program logs are recorded
+    static ref STARTED: tokio::sync::Mutex<bool> = tokio::sync::Mutex::new(false);
+}
+
+/// The banks client of this runtime doesn't return the logs of a transaction, thus the logs are
+/// recorded on their way to the program test's syscall stubs
+struct RecordingStubs;
+
+fn inner_stubs<T>(f: impl FnOnce(&dyn SyscallStubs) -> T) -> T {
+    f(INNER_STUBS.read().unwrap().as_deref().unwrap())
+}
+
+impl SyscallStubs for RecordingStubs {
+    fn sol_log(&self, message: &str) {
+        LOGS.lock().unwrap().push(message.to_string());
+        inner_stubs(|stubs| stubs.sol_log(message))
+    }
+    fn sol_log_compute_units(&self) {
+        inner_stubs(|stubs| stubs.sol_log_compute_units())
+    }
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        inner_stubs(|stubs| stubs.sol_invoke_signed(instruction, account_infos, signers_seeds))
+    }
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        inner_stubs(|stubs| stubs.sol_get_clock_sysvar(var_addr))
+    }
+    fn sol_get_epoch_schedule_sysvar(&self, var_addr: *mut u8) -> u64 {
+        inner_stubs(|stubs| stubs.sol_get_epoch_schedule_sysvar(var_addr))
+    }
+    fn sol_get_fees_sysvar(&self, var_addr: *mut u8) -> u64 {
+        inner_stubs(|stubs| stubs.sol_get_fees_sysvar(var_addr))
+    }
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        inner_stubs(|stubs| stubs.sol_get_rent_sysvar(var_addr))
+    }
+}
+
+/// Starts the program test, recording the program logs from then on
+async fn start(program_test: ProgramTest) -> ProgramTestContext {
+    let mut started = STARTED.lock().await;
+    let ctx = program_test.start_with_context().await;
+    // The program test installs its stubs when it starts for the first time, no program can run
+    // before that
+    if !*started {
+        let stubs = program_stubs::set_syscall_stubs(Box::new(RecordingStubs));
+        *INNER_STUBS.write().unwrap() = Some(stubs);
+        *started = true;
+    }
+    ctx
+}
+
+/// Events of the given escrow logged so far, tests run in parallel and log into the same
+/// [LOGS]
+fn events_of(escrow: Pubkey) -> Vec<EscrowEvent> {
+    let logs = LOGS.lock().unwrap();
+    EscrowEvent::from_logs(logs.iter().map(String::as_str))
+        .expect("events decode")
+        .into_iter()
+        .filter(|event| match event {
+            EscrowEvent::Created { escrow: e, .. }
+            | EscrowEvent::Exchanged { escrow: e, .. }
+            | EscrowEvent::Cancelled { escrow: e, .. } => *e == escrow,
+        })
+        .collect()
+}
+
+fn created(trade: &Trade) -> EscrowEvent {
+    EscrowEvent::Created {
+        escrow: trade.escrow,
+        initializer: trade.alice.pubkey(),
+        mint_x: trade.mint_x,
+        mint_y: trade.mint_y,
+        amount_x: trade.x_amount,
+        expected_amount: trade.y_amount,
+    }
+}
+
+#[tokio::test]
+async fn init_escrow_and_exchange_events() {
+    let mut trade = Trade::new(5000, 1000);
+    trade.fee_basis_points = Some(100);
+    let mut ctx = start(trade.program_test()).await;
+
+    trade
+        .init_escrow(&mut ctx)
+        .await
+        .expect("Init Escrow succeeds");
+    assert_eq!(events_of(trade.escrow), vec![created(&trade)]);
+
+    trade.exchange(&mut ctx).await.expect("Exchange succeeds");
+    assert_eq!(
+        events_of(trade.escrow),
+        vec![
+            created(&trade),
+            EscrowEvent::Exchanged {
+                escrow: trade.escrow,
+                initializer: trade.alice.pubkey(),
+                taker: trade.bob.pubkey(),
+                mint_x: trade.mint_x,
+                mint_y: trade.mint_y,
+                amount_x: 5000,
+                amount_y: 1000,
+                fee: 10,
+                remaining_amount: 0,
+            }
+        ]
+    );
+}
+
+#[tokio::test]
+async fn partial_exchange_event() {
+    let trade = Trade::new(5000, 1000);
+    let mut ctx = start(trade.initialized_program_test()).await;
+
+    process_ix(&mut ctx, trade.partial_exchange_ix(400, 2000), &trade.bob)
+        .await
+        .expect("Partial Exchange succeeds");
+    assert_eq!(
+        events_of(trade.escrow),
+        vec![EscrowEvent::Exchanged {
+            escrow: trade.escrow,
+            initializer: trade.alice.pubkey(),
+            taker: trade.bob.pubkey(),
+            mint_x: trade.mint_x,
+            mint_y: trade.mint_y,
+            amount_x: 2000,
+            amount_y: 400,
+            fee: 0,
+            remaining_amount: 600,
+        }]
+    );
+}
+
+#[tokio::test]
+async fn cancel_escrow_event() {
+    let trade = Trade::new(5000, 1000);
+    let mut ctx = start(trade.initialized_program_test()).await;
+
+    process_ix(&mut ctx, trade.cancel_escrow_ix(), &trade.alice)
+        .await
+        .expect("Cancel Escrow succeeds");
+    assert_eq!(
+        events_of(trade.escrow),
+        vec![EscrowEvent::Cancelled {
+            escrow: trade.escrow,
+            initializer: trade.alice.pubkey(),
+            mint_x: trade.mint_x,
+            amount_x: 5000,
+            reclaimed: false,
+        }]
+    );
+}
+
+#[tokio::test]
+async fn failed_exchange_logs_no_event() {
+    let trade = Trade::new(5000, 1000);
+    let mut ctx = start(trade.initialized_program_test()).await;
+
+    process_ix(&mut ctx, trade.partial_exchange_ix(400, 1), &trade.bob)
+        .await
+        .unwrap_err();
+    assert_eq!(events_of(trade.escrow), vec![]);
+}
@@ -0,0 +1,385 @@
+//! Runs the whole trade the way the JS client does against a live cluster: setup creates the
+//! mints and token accounts via the token program, Alice inits the escrow and Bob takes it.
+
+mod common;
+
+use common::{account_exists, config_account, config_pda, escrow_state, token_balance};
+use escrow::{
+    instruction,
+    processor::Processor,
+    state::{Config, Escrow},
+};
+use solana_program::{
+    instruction::Instruction, program_pack::Pack, pubkey::Pubkey, rent::Rent, system_instruction,
+};
+use solana_program_test::{processor, tokio, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+    transport::TransportError,
+};
+use spl_token::state::{Account as TokenAccount, Mint};
+
+/// Tokens minted to Alice's X and Bob's Y account during setup
+const MINTED: u64 = 50;
+
+/// Accounts created during setup, see `setup.ts`
+struct Market {
+    program_id: Pubkey,
+
+    alice: Keypair,
+    bob: Keypair,
+
+    mint_x: Pubkey,
+    mint_y: Pubkey,
+    alice_x: Pubkey,
+    alice_y: Pubkey,
+    bob_x: Pubkey,
+    bob_y: Pubkey,
+
+    /// Treasury's token Y account which receives the fees
+    treasury_y: Pubkey,
+}
+
+/// Accounts created by Alice when she inits the escrow, see `alice.ts`
+struct Offer {
+    tmp_x: Pubkey,
+    escrow: Pubkey,
+}
+
+async fn process(
+    ctx: &mut ProgramTestContext,
+    ixs: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<(), TransportError> {
+    let mut transaction = Transaction::new_with_payer(ixs, Some(&ctx.payer.pubkey()));
+    let mut all_signers = vec![&ctx.payer];
+    all_signers.extend_from_slice(signers);
+    transaction.sign(&all_signers, ctx.last_blockhash);
+    ctx.banks_client.process_transaction(transaction).await
+}
+
+async fn lamports(ctx: &mut ProgramTestContext, pubkey: Pubkey) -> u64 {
+    ctx.banks_client.get_balance(pubkey).await.unwrap()
+}
+
+/// Instruction creating the account of `keypair` with `space` bytes, owned by `owner`
+fn create_account_ix(
+    payer: &Pubkey,
+    keypair: &Keypair,
+    space: usize,
+    owner: &Pubkey,
+) -> Instruction {
+    system_instruction::create_account(
+        payer,
+        &keypair.pubkey(),
+        Rent::default().minimum_balance(space),
+        space as u64,
+        owner,
+    )
+}
+
+fn create_token_account_ixs(
+    payer: &Pubkey,
+    keypair: &Keypair,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) -> [Instruction; 2] {
+    [
+        create_account_ix(payer, keypair, TokenAccount::LEN, &spl_token::id()),
+        spl_token::instruction::initialize_account(
+            &spl_token::id(),
+            &keypair.pubkey(),
+            mint,
+            owner,
+        )
+        .unwrap(),
+    ]
+}
+
+impl Market {
+    /// Creates mint X and Y, the token accounts of Alice, Bob and the treasury and mints X to
+    /// Alice and Y to Bob. The escrow config charges `fee_basis_points` of what Bob pays.
+    async fn setup(fee_basis_points: u16) -> (Self, ProgramTestContext) {
+        let program_id = Pubkey::new_unique();
+        let treasury = Keypair::new();
+        let alice = Keypair::new();
+        let bob = Keypair::new();
+        let client = Keypair::new();
+        let [mint_x, mint_y, alice_x, alice_y, bob_x, bob_y, treasury_y] =
+            [(); 7].map(|_| Keypair::new());
+
+        // Natively run programs can't create accounts, thus the config is there from the start
+        // instead of created via InitConfig
+        let mut program_test =
+            ProgramTest::new("escrow", program_id, processor!(Processor::process));
+        let (config_pda, bump_seed) = config_pda(&program_id);
+        let mut config = Config {
+            is_initialized: true,
+            admin_pubkey: Pubkey::new_unique(),
+            fee_basis_points,
+            treasury_pubkey: treasury.pubkey(),
+            bump_seed,
+            ..Config::default()
+        };
+        config
+            .set_treasury_account(&mint_y.pubkey(), &treasury_y.pubkey())
+            .unwrap();
+        program_test.add_account(config_pda, config_account(&program_id, config));
+        let mut ctx = program_test.start_with_context().await;
+        let payer = ctx.payer.pubkey();
+
+        // Alice pays for the tmp token account and the escrow account
+        process(
+            &mut ctx,
+            &[system_instruction::transfer(
+                &payer,
+                &alice.pubkey(),
+                1_000_000_000,
+            )],
+            &[],
+        )
+        .await
+        .expect("Airdrop to Alice succeeds");
+
+        // The client is the mint authority of both mints
+        for (mint, accounts) in [
+            (&mint_x, vec![(&alice_x, &alice), (&bob_x, &bob)]),
+            (
+                &mint_y,
+                vec![(&alice_y, &alice), (&bob_y, &bob), (&treasury_y, &treasury)],
+            ),
+        ] {
+            let mut ixs = vec![
+                create_account_ix(&payer, mint, Mint::LEN, &spl_token::id()),
+                spl_token::instruction::initialize_mint(
+                    &spl_token::id(),
+                    &mint.pubkey(),
+                    &client.pubkey(),
+                    None,
+                    0,
+                )
+                .unwrap(),
+            ];
+            let mut signers = vec![mint];
+            for (account, owner) in accounts {
+                ixs.extend(create_token_account_ixs(
+                    &payer,
+                    account,
+                    &mint.pubkey(),
+                    &owner.pubkey(),
+                ));
+                signers.push(account);
+            }
+            process(&mut ctx, &ixs, &signers)
+                .await
+                .expect("Creating the mint and token accounts succeeds");
+        }
+
+        process(
+            &mut ctx,
+            &[
+                spl_token::instruction::mint_to(
+                    &spl_token::id(),
+                    &mint_x.pubkey(),
+                    &alice_x.pubkey(),
+                    &client.pubkey(),
+                    &[],
+                    MINTED,
+                )
+                .unwrap(),
+                spl_token::instruction::mint_to(
+                    &spl_token::id(),
+                    &mint_y.pubkey(),
+                    &bob_y.pubkey(),
+                    &client.pubkey(),
+                    &[],
+                    MINTED,
+                )
+                .unwrap(),
+            ],
+            &[&client],
+        )
+        .await
+        .expect("Minting succeeds");
+
+        let market = Market {
+            program_id,
+            alice,
+            bob,
+            mint_x: mint_x.pubkey(),
+            mint_y: mint_y.pubkey(),
+            alice_x: alice_x.pubkey(),
+            alice_y: alice_y.pubkey(),
+            bob_x: bob_x.pubkey(),
+            bob_y: bob_y.pubkey(),
+            treasury_y: treasury_y.pubkey(),
+        };
+        (market, ctx)
+    }
+
+    /// Alice moves `x_amount` into a new tmp token account and offers it for `y_amount`
+    async fn alice(&self, ctx: &mut ProgramTestContext, x_amount: u64, y_amount: u64) -> Offer {
+        let alice = self.alice.pubkey();
+        let tmp_x = Keypair::new();
+        let escrow = Keypair::new();
+
+        let [create_tmp_x_ix, init_tmp_x_ix] =
+            create_token_account_ixs(&alice, &tmp_x, &self.mint_x, &alice);
+        let transfer_to_tmp_x_ix = spl_token::instruction::transfer(
+            &spl_token::id(),
+            &self.alice_x,
+            &tmp_x.pubkey(),
+            &alice,
+            &[],
+            x_amount,
+        )
+        .unwrap();
+        let create_escrow_ix = create_account_ix(&alice, &escrow, Escrow::LEN, &self.program_id);
+        let init_escrow_ix = instruction::init_escrow(
+            &self.program_id,
+            &alice,
+            &tmp_x.pubkey(),
+            &self.alice_y,
+            &escrow.pubkey(),
+            &spl_token::id(),
+            &self.mint_x,
+            &self.mint_y,
+            y_amount,
+            None,
+            None,
+        );
+        process(
+            ctx,
+            &[
+                create_tmp_x_ix,
+                init_tmp_x_ix,
+                transfer_to_tmp_x_ix,
+                create_escrow_ix,
+                init_escrow_ix,
+            ],
+            &[&self.alice, &tmp_x, &escrow],
+        )
+        .await
+        .expect("Alice's transaction succeeds");
+
+        Offer {
+            tmp_x: tmp_x.pubkey(),
+            escrow: escrow.pubkey(),
+        }
+    }
+
+    /// Bob takes the offer, expecting to receive `x_amount`
+    async fn bob(
+        &self,
+        ctx: &mut ProgramTestContext,
+        offer: &Offer,
+        x_amount: u64,
+    ) -> Result<(), TransportError> {
+        let escrow = escrow_state(ctx, offer.escrow)
+            .await
+            .expect("Bob finds the escrow");
+        let exchange_ix = instruction::exchange(
+            &self.program_id,
+            &self.bob.pubkey(),
+            &self.bob_y,
+            &self.bob_x,
+            &escrow.tmp_token_account_pubkey,
+            &escrow.initializer_pubkey,
+            &escrow.initializer_token_to_receive_account_pubkey,
+            &offer.escrow,
+            &spl_token::id(),
+            &self.treasury_y,
+            &escrow.mint_x_pubkey,
+            &escrow.mint_y_pubkey,
+            x_amount,
+        );
+        process(ctx, &[exchange_ix], &[&self.bob]).await
+    }
+}
+
+#[tokio::test]
+async fn exchange_end_to_end() {
+    let (market, mut ctx) = Market::setup(0).await;
+    assert_eq!(token_balance(&mut ctx, market.alice_x).await, MINTED);
+    assert_eq!(token_balance(&mut ctx, market.bob_y).await, MINTED);
+
+    let offer = market.alice(&mut ctx, 5, 3).await;
+
+    // verifyInitializedEscrow
+    let escrow = escrow_state(&mut ctx, offer.escrow).await.unwrap();
+    assert!(escrow.is_initialized);
+    assert_eq!(escrow.initializer_pubkey, market.alice.pubkey());
+    assert_eq!(escrow.tmp_token_account_pubkey, offer.tmp_x);
+    assert_eq!(
+        escrow.initializer_token_to_receive_account_pubkey,
+        market.alice_y
+    );
+    assert_eq!(escrow.expected_amount, 3);
+    assert_eq!(token_balance(&mut ctx, market.alice_x).await, MINTED - 5);
+    assert_eq!(token_balance(&mut ctx, offer.tmp_x).await, 5);
+
+    let rent = lamports(&mut ctx, offer.escrow).await + lamports(&mut ctx, offer.tmp_x).await;
+    let alice_lamports = lamports(&mut ctx, market.alice.pubkey()).await;
+
+    market
+        .bob(&mut ctx, &offer, 5)
+        .await
+        .expect("Bob's transaction succeeds");
+
+    // verifyExchangedEscrow
+    assert!(
+        !account_exists(&mut ctx, offer.escrow).await,
+        "Escrow account closed"
+    );
+    assert!(
+        !account_exists(&mut ctx, offer.tmp_x).await,
+        "Tmp token account closed"
+    );
+    assert_eq!(token_balance(&mut ctx, market.alice_x).await, MINTED - 5);
+    assert_eq!(token_balance(&mut ctx, market.alice_y).await, 3);
+    assert_eq!(token_balance(&mut ctx, market.bob_x).await, 5);
+    assert_eq!(token_balance(&mut ctx, market.bob_y).await, MINTED - 3);
+    assert_eq!(token_balance(&mut ctx, market.treasury_y).await, 0);
+    assert_eq!(
+        lamports(&mut ctx, market.alice.pubkey()).await,
+        alice_lamports + rent,
+        "Alice got the rent of the closed accounts back"
+    );
+}
+
+#[tokio::test]
+async fn exchange_end_to_end_with_fee() {
+    // 10% of what Bob pays goes to the treasury
+    let (market, mut ctx) = Market::setup(1000).await;
+
+    let offer = market.alice(&mut ctx, 40, 30).await;
+    market
+        .bob(&mut ctx, &offer, 40)
+        .await
+        .expect("Bob's transaction succeeds");
+
+    assert!(!account_exists(&mut ctx, offer.escrow).await);
+    assert!(!account_exists(&mut ctx, offer.tmp_x).await);
+    assert_eq!(token_balance(&mut ctx, market.alice_y).await, 27);
+    assert_eq!(token_balance(&mut ctx, market.treasury_y).await, 3);
+    assert_eq!(token_balance(&mut ctx, market.bob_x).await, 40);
+    assert_eq!(token_balance(&mut ctx, market.bob_y).await, MINTED - 30);
+}
+
+#[tokio::test]
+async fn exchange_expecting_more_than_offered() {
+    let (market, mut ctx) = Market::setup(0).await;
+
+    let offer = market.alice(&mut ctx, 5, 3).await;
+    market
+        .bob(&mut ctx, &offer, 6)
+        .await
+        .expect_err("Bob can't receive more than Alice offered");
+
+    assert!(escrow_state(&mut ctx, offer.escrow).await.is_some());
+    assert_eq!(token_balance(&mut ctx, offer.tmp_x).await, 5);
+    assert_eq!(token_balance(&mut ctx, market.bob_x).await, 0);
+    assert_eq!(token_balance(&mut ctx, market.bob_y).await, MINTED);
+    assert_eq!(token_balance(&mut ctx, market.alice_y).await, 0);
+}
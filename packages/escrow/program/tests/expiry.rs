@@ -0,0 +1,129 @@
+mod common;
+
+use common::{account_exists, escrow_error, process_ix, process_unsigned_ix, token_balance, Trade};
+use escrow::error::EscrowError;
+use solana_program::{clock::Clock, instruction::AccountMeta};
+use solana_program_test::{tokio, ProgramTestContext};
+
+// The bank only moves the clock forward within the allowed drift from the epoch start, so we warp
+// across multiple epochs to move it well beyond the escrow's lifetime
+const SLOTS_UNTIL_EXPIRED: u64 = 100_000;
+const ESCROW_LIFETIME_SECS: i64 = 60;
+
+async fn clock(ctx: &mut ProgramTestContext) -> Clock {
+    ctx.banks_client.get_sysvar::<Clock>().await.unwrap()
+}
+
+/// Inits an escrow which expires [ESCROW_LIFETIME_SECS] from now
+async fn init_expiring_escrow(trade: &mut Trade) -> ProgramTestContext {
+    let mut ctx = trade.program_test().start_with_context().await;
+    trade.expires_at = Some(clock(&mut ctx).await.unix_timestamp + ESCROW_LIFETIME_SECS);
+    trade
+        .init_escrow(&mut ctx)
+        .await
+        .expect("Init Escrow succeeds");
+    ctx
+}
+
+async fn warp_past_expiry(ctx: &mut ProgramTestContext, trade: &Trade) {
+    let slot = clock(ctx).await.slot;
+    ctx.warp_to_slot(slot + SLOTS_UNTIL_EXPIRED).unwrap();
+    assert!(
+        clock(ctx).await.unix_timestamp >= trade.expires_at.unwrap(),
+        "warped past expiry"
+    );
+}
+
+#[tokio::test]
+async fn exchange_before_expiry() {
+    let mut trade = Trade::new(5000, 1000);
+    let mut ctx = init_expiring_escrow(&mut trade).await;
+
+    trade
+        .exchange(&mut ctx)
+        .await
+        .expect("Exchange succeeds before expiry");
+    assert_eq!(token_balance(&mut ctx, trade.bob_x).await, 5000);
+}
+
+#[tokio::test]
+async fn exchange_after_expiry() {
+    let mut trade = Trade::new(5000, 1000);
+    let mut ctx = init_expiring_escrow(&mut trade).await;
+    warp_past_expiry(&mut ctx, &trade).await;
+
+    let err = trade.exchange(&mut ctx).await.unwrap_err().unwrap();
+    assert_eq!(escrow_error(err), Some(EscrowError::EscrowExpired));
+}
+
+#[tokio::test]
+async fn reclaim_before_expiry() {
+    let mut trade = Trade::new(5000, 1000);
+    let mut ctx = init_expiring_escrow(&mut trade).await;
+
+    let ix = trade.reclaim_expired_ix();
+    let err = process_unsigned_ix(&mut ctx, ix)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(escrow_error(err), Some(EscrowError::EscrowNotExpired));
+}
+
+#[tokio::test]
+async fn reclaim_after_expiry() {
+    let mut trade = Trade::new(5000, 1000);
+    let mut ctx = init_expiring_escrow(&mut trade).await;
+    warp_past_expiry(&mut ctx, &trade).await;
+
+    // Submitted and paid for by someone other than Alice
+    let ix = trade.reclaim_expired_ix();
+    process_unsigned_ix(&mut ctx, ix)
+        .await
+        .expect("Anyone can reclaim an expired escrow");
+
+    assert_eq!(
+        token_balance(&mut ctx, trade.alice_x).await,
+        5000,
+        "Alice got her tokens back"
+    );
+    assert!(
+        !account_exists(&mut ctx, trade.alice_x_tmp).await,
+        "Tmp token account closed"
+    );
+    assert!(
+        !account_exists(&mut ctx, trade.escrow).await,
+        "Escrow account closed"
+    );
+}
+
+#[tokio::test]
+async fn reclaim_to_other_account() {
+    let mut trade = Trade::new(5000, 1000);
+    let mut ctx = init_expiring_escrow(&mut trade).await;
+    warp_past_expiry(&mut ctx, &trade).await;
+
+    let mut ix = trade.reclaim_expired_ix();
+    ix.accounts[2] = AccountMeta::new(trade.bob_x, false);
+    let err = process_unsigned_ix(&mut ctx, ix)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        escrow_error(err),
+        Some(EscrowError::InvalidTokenAccountOwner),
+        "Reclaimed tokens only go back to Alice"
+    );
+}
+
+#[tokio::test]
+async fn cancel_after_expiry() {
+    let mut trade = Trade::new(5000, 1000);
+    let mut ctx = init_expiring_escrow(&mut trade).await;
+    warp_past_expiry(&mut ctx, &trade).await;
+
+    let ix = trade.cancel_escrow_ix();
+    process_ix(&mut ctx, ix, &trade.alice)
+        .await
+        .expect("Alice can still cancel an expired escrow");
+    assert_eq!(token_balance(&mut ctx, trade.alice_x).await, 5000);
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use common::{escrow_error, escrow_state, process_ix, Trade};
+use escrow::error::EscrowError;
+use solana_program::pubkey::Pubkey;
+use solana_program_test::tokio;
+
+// Creating the escrow account via the system program is only supported when running as BPF
+#[cfg(feature = "test-bpf")]
+#[tokio::test]
+async fn init_escrow_pda_and_exchange() {
+    use common::{account_exists, token_balance};
+    use solana_sdk::signature::Signer;
+
+    let mut trade = Trade::new(5000, 1000);
+    trade.escrow = trade.escrow_account_pda();
+    let mut ctx = trade
+        .program_test_without_escrow_account()
+        .start_with_context()
+        .await;
+
+    process_ix(&mut ctx, trade.init_escrow_pda_ix(), &trade.alice)
+        .await
+        .expect("Init Escrow PDA succeeds");
+
+    let escrow = escrow_state(&mut ctx, trade.escrow)
+        .await
+        .expect("program created the escrow account");
+    assert!(escrow.is_initialized);
+    assert_eq!(escrow.initializer_pubkey, trade.alice.pubkey());
+    assert_eq!(escrow.expected_amount, 1000);
+
+    trade.exchange(&mut ctx).await.expect("Exchange succeeds");
+    assert_eq!(token_balance(&mut ctx, trade.bob_x).await, 5000);
+    assert_eq!(token_balance(&mut ctx, trade.alice_y).await, 1000);
+    assert!(!account_exists(&mut ctx, trade.escrow).await);
+}
+
+#[tokio::test]
+async fn init_escrow_pda_wrong_address() {
+    let mut trade = Trade::new(5000, 1000);
+    trade.escrow = Pubkey::new_unique();
+    let mut ctx = trade
+        .program_test_without_escrow_account()
+        .start_with_context()
+        .await;
+
+    let err = process_ix(&mut ctx, trade.init_escrow_pda_ix(), &trade.alice)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        escrow_error(err),
+        Some(EscrowError::InvalidEscrowAddress),
+        "Escrow account has to be the PDA of Alice's tmp token account"
+    );
+    assert!(escrow_state(&mut ctx, trade.escrow).await.is_none());
+}
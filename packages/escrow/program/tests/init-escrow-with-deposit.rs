@@ -0,0 +1,65 @@
+mod common;
+
+use common::{escrow_error, process_ix, Trade};
+use escrow::error::EscrowError;
+use solana_program::pubkey::Pubkey;
+use solana_program_test::tokio;
+
+// Creating the vault via the system program is only supported when running as BPF
+#[cfg(feature = "test-bpf")]
+#[tokio::test]
+async fn init_escrow_with_deposit_and_exchange() {
+    use common::{account_exists, escrow_state, token_balance};
+    use solana_program::program_pack::Pack;
+    use spl_token::state::Account as TokenAccount;
+
+    let mut trade = Trade::new(5000, 1000);
+    trade.alice_x_tmp = trade.vault();
+    let mut ctx = trade
+        .program_test_without_tmp_account()
+        .start_with_context()
+        .await;
+
+    process_ix(&mut ctx, trade.init_escrow_with_deposit_ix(), &trade.alice)
+        .await
+        .expect("Init Escrow With Deposit succeeds");
+
+    let vault = ctx
+        .banks_client
+        .get_account(trade.vault())
+        .await
+        .unwrap()
+        .expect("program created the vault");
+    let vault = TokenAccount::unpack(&vault.data).unwrap();
+    assert_eq!(vault.owner, trade.pda(), "PDA owns the vault");
+    assert_eq!(vault.amount, 5000, "vault holds the deposit");
+    assert_eq!(token_balance(&mut ctx, trade.alice_x).await, 0);
+
+    let escrow = escrow_state(&mut ctx, trade.escrow).await.unwrap();
+    assert_eq!(escrow.tmp_token_account_pubkey, trade.vault());
+
+    trade.exchange(&mut ctx).await.expect("Exchange succeeds");
+    assert_eq!(token_balance(&mut ctx, trade.bob_x).await, 5000);
+    assert_eq!(token_balance(&mut ctx, trade.alice_y).await, 1000);
+    assert!(!account_exists(&mut ctx, trade.vault()).await);
+}
+
+#[tokio::test]
+async fn init_escrow_with_deposit_wrong_vault_address() {
+    let mut trade = Trade::new(5000, 1000);
+    trade.alice_x_tmp = Pubkey::new_unique();
+    let mut ctx = trade
+        .program_test_without_tmp_account()
+        .start_with_context()
+        .await;
+
+    let err = process_ix(&mut ctx, trade.init_escrow_with_deposit_ix(), &trade.alice)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        escrow_error(err),
+        Some(EscrowError::InvalidVaultAddress),
+        "Vault has to be the PDA of the escrow account"
+    );
+}
@@ -1,103 +1,54 @@
-use escrow::{instruction::EscrowInstruction, processor::Processor};
-use solana_program_test::{processor, tokio, ProgramTest, ProgramTestContext};
+mod common;
 
-use solana_program::{
-    instruction::{AccountMeta, Instruction},
-    pubkey::Pubkey,
-};
-use solana_sdk::{account::Account, signer::Signer, transaction::Transaction};
-
-// -----------------
-// Utils
-// -----------------
-async fn start_program(
-    // keys
-    program_id: Pubkey,
-    alice_pubkey: Pubkey,
-    alice_x_pubkey: Pubkey,
-    alice_y_pubkey: Pubkey,
-    escrow_pubkey: Pubkey,
-    // accounts
-    alice_account: Account,
-    alice_x_account: Account,
-    alice_y_account: Account,
-    escrow_account: Account,
-    // payload
-    amount: u64,
-) -> (ProgramTestContext, Instruction) {
-    let mut program_test = ProgramTest::new("escrow", program_id, processor!(Processor::process));
-
-    // -----------------
-    // Add Accounts
-    // -----------------
-    program_test.add_account(alice_pubkey, alice_account);
-    program_test.add_account(alice_x_pubkey, alice_x_account);
-    program_test.add_account(alice_y_pubkey, alice_y_account);
-    program_test.add_account(escrow_pubkey, escrow_account);
-
-    // -----------------
-    // Setup and pack Instruction
-    // -----------------
-    let escrow_init_ix = EscrowInstruction::InitEscrow { amount };
-
-    let ix_data = &escrow_init_ix.pack();
-
-    let instruction = Instruction::new_with_bytes(
-        program_id,
-        ix_data,
-        vec![
-            AccountMeta::new(alice_pubkey, true),
-            AccountMeta::new(alice_x_pubkey, false),
-            AccountMeta::new(alice_y_pubkey, false),
-            AccountMeta::new(escrow_pubkey, false),
-            AccountMeta::new(program_id, false),
-        ],
-    );
-
-    let ctx = program_test.start_with_context().await;
-    (ctx, instruction)
-}
+use common::{escrow_state, process_ix, Trade};
+use escrow::instruction;
+use solana_program::program_pack::Pack;
+use solana_program_test::tokio;
+use solana_sdk::signature::Signer;
+use spl_token::state::Account as TokenAccount;
 
 #[tokio::test]
 async fn init_escrow_success() {
-    let program_id = Pubkey::new_unique();
-    let alice_pubkey = Pubkey::new_unique();
-    let alice_x_pubkey = Pubkey::new_unique();
-    let alice_y_pubkey = Pubkey::new_unique();
-    let escrow_pubkey = Pubkey::new_unique();
-
-    let alice_account = Account::default();
-    let alice_x_account = Account {
-        owner: spl_token::id(),
-        ..Account::default()
-    };
-    let alice_y_account = Account::default();
-    let escrow_account = Account::default();
-
-    let amount = 5000;
-
-    let (mut ctx, instruction) = start_program(
-        // keys
-        program_id,
-        alice_pubkey,
-        alice_x_pubkey,
-        alice_y_pubkey,
-        escrow_pubkey,
-        // accounts
-        alice_account,
-        alice_x_account,
-        alice_y_account,
-        escrow_account,
-        // payload
-        amount,
-    )
-    .await;
+    let trade = Trade::new(5000, 1000);
+    let mut ctx = trade.program_test().start_with_context().await;
+
+    let ix = instruction::init_escrow(
+        &trade.program_id,
+        &trade.alice.pubkey(),
+        &trade.alice_x_tmp,
+        &trade.alice_y,
+        &trade.escrow,
+        &spl_token::id(),
+        &trade.mint_x,
+        &trade.mint_y,
+        trade.y_amount,
+        None,
+        None,
+    );
+    process_ix(&mut ctx, ix, &trade.alice)
+        .await
+        .expect("Init Escrow succeeds");
 
-    let mut transaction = Transaction::new_with_payer(&[instruction], Some(&ctx.payer.pubkey()));
-    transaction.sign(&[&ctx.payer], ctx.last_blockhash);
+    let escrow = escrow_state(&mut ctx, trade.escrow).await.unwrap();
+    assert!(escrow.is_initialized);
+    assert_eq!(escrow.initializer_pubkey, trade.alice.pubkey());
+    assert_eq!(escrow.tmp_token_account_pubkey, trade.alice_x_tmp);
+    assert_eq!(
+        escrow.initializer_token_to_receive_account_pubkey,
+        trade.alice_y
+    );
+    assert_eq!(escrow.expected_amount, trade.y_amount);
 
-    ctx.banks_client
-        .process_transaction(transaction)
+    let tmp_account = ctx
+        .banks_client
+        .get_account(trade.alice_x_tmp)
         .await
-        .expect("Init Escrow succeeds");
+        .unwrap()
+        .unwrap();
+    let tmp_account = TokenAccount::unpack(&tmp_account.data).unwrap();
+    assert_eq!(
+        tmp_account.owner,
+        trade.pda(),
+        "PDA owns the tmp token account"
+    );
 }
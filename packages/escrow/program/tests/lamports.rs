@@ -0,0 +1,225 @@
+mod common;
+
+use common::{account_exists, escrow_state, process_ix, system_account, token_balance, Trade};
+use escrow::{instruction::EscrowInstruction, state::Escrow};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_program,
+};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::signature::Signer;
+
+const LAMPORTS: u64 = 1_000_000_000;
+
+async fn lamports(ctx: &mut ProgramTestContext, pubkey: Pubkey) -> u64 {
+    ctx.banks_client
+        .get_account(pubkey)
+        .await
+        .unwrap()
+        .map(|account| account.lamports)
+        .unwrap_or_default()
+}
+
+// -----------------
+// Alice offers lamports
+// -----------------
+
+/// Alice offers `x_amount` lamports instead of token X
+fn init_escrow_offering_lamports_ix(trade: &Trade) -> Instruction {
+    Instruction::new_with_bytes(
+        trade.program_id,
+        &EscrowInstruction::InitEscrowOfferingLamports {
+            deposit: trade.x_amount,
+            amount: trade.y_amount,
+            expires_at: None,
+            allowed_taker: None,
+        }
+        .pack(),
+        vec![
+            AccountMeta::new(trade.alice.pubkey(), true),
+            AccountMeta::new(trade.escrow, false),
+            AccountMeta::new_readonly(trade.alice_y, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(trade.mint_y, false),
+        ],
+    )
+}
+
+/// Bob receives the lamports in his main account which the escrow account holds
+fn exchange_for_lamports_ix(trade: &Trade, ix: Instruction) -> Instruction {
+    let mut ix = ix;
+    ix.accounts[2] = AccountMeta::new(trade.bob.pubkey(), false);
+    ix.accounts[3] = AccountMeta::new(trade.escrow, false);
+    ix
+}
+
+async fn start_offering_lamports(trade: &Trade) -> ProgramTestContext {
+    let mut program_test = trade.program_test();
+    program_test.add_account(trade.alice.pubkey(), system_account(2 * LAMPORTS));
+    let mut ctx = program_test.start_with_context().await;
+
+    process_ix(
+        &mut ctx,
+        init_escrow_offering_lamports_ix(trade),
+        &trade.alice,
+    )
+    .await
+    .expect("Init Escrow succeeds");
+    ctx
+}
+
+#[tokio::test]
+async fn exchange_tokens_for_offered_lamports() {
+    let trade = Trade::new(LAMPORTS, 1000);
+    let mut ctx = start_offering_lamports(&trade).await;
+
+    let escrow = escrow_state(&mut ctx, trade.escrow).await.unwrap();
+    assert!(escrow.offers_lamports());
+    assert_eq!(
+        lamports(&mut ctx, trade.escrow).await,
+        Rent::default().minimum_balance(Escrow::LEN) + LAMPORTS,
+        "escrow account holds the deposit"
+    );
+
+    let alice_lamports = lamports(&mut ctx, trade.alice.pubkey()).await;
+    let ix = exchange_for_lamports_ix(&trade, trade.exchange_ix());
+    process_ix(&mut ctx, ix, &trade.bob)
+        .await
+        .expect("Exchange succeeds");
+
+    assert_eq!(lamports(&mut ctx, trade.bob.pubkey()).await, LAMPORTS);
+    assert_eq!(token_balance(&mut ctx, trade.alice_y).await, 1000);
+    assert!(!account_exists(&mut ctx, trade.escrow).await);
+    assert_eq!(
+        lamports(&mut ctx, trade.alice.pubkey()).await,
+        alice_lamports + Rent::default().minimum_balance(Escrow::LEN),
+        "Alice gets the escrow's rent back"
+    );
+}
+
+#[tokio::test]
+async fn partial_exchange_for_offered_lamports() {
+    let trade = Trade::new(LAMPORTS, 1000);
+    let mut ctx = start_offering_lamports(&trade).await;
+
+    let ix = exchange_for_lamports_ix(&trade, trade.partial_exchange_ix(250, LAMPORTS / 4));
+    process_ix(&mut ctx, ix, &trade.bob)
+        .await
+        .expect("Partial exchange succeeds");
+
+    assert_eq!(lamports(&mut ctx, trade.bob.pubkey()).await, LAMPORTS / 4);
+    assert_eq!(
+        lamports(&mut ctx, trade.escrow).await,
+        Rent::default().minimum_balance(Escrow::LEN) + LAMPORTS / 4 * 3,
+        "escrow account holds the remainder"
+    );
+    let escrow = escrow_state(&mut ctx, trade.escrow).await.unwrap();
+    assert_eq!(escrow.expected_amount, 750);
+}
+
+#[tokio::test]
+async fn cancel_escrow_offering_lamports() {
+    let trade = Trade::new(LAMPORTS, 1000);
+    let mut ctx = start_offering_lamports(&trade).await;
+    let alice_lamports = lamports(&mut ctx, trade.alice.pubkey()).await;
+
+    let mut ix = trade.cancel_escrow_ix();
+    ix.accounts[1] = AccountMeta::new(trade.escrow, false);
+    process_ix(&mut ctx, ix, &trade.alice)
+        .await
+        .expect("Cancel succeeds");
+
+    assert!(!account_exists(&mut ctx, trade.escrow).await);
+    assert_eq!(
+        lamports(&mut ctx, trade.alice.pubkey()).await,
+        alice_lamports + Rent::default().minimum_balance(Escrow::LEN) + LAMPORTS,
+        "Alice gets her deposit and the escrow's rent back"
+    );
+}
+
+// -----------------
+// Alice expects lamports
+// -----------------
+
+/// Alice expects `y_amount` lamports in her main account instead of token Y
+fn init_escrow_expecting_lamports_ix(trade: &Trade) -> Instruction {
+    let mut ix = trade.init_escrow_ix();
+    ix.data = EscrowInstruction::InitEscrowExpectingLamports {
+        amount: trade.y_amount,
+        expires_at: None,
+        allowed_taker: None,
+    }
+    .pack();
+    ix.accounts[2] = AccountMeta::new_readonly(trade.alice.pubkey(), false);
+    // There's no mint of token Y
+    ix.accounts.pop();
+    ix
+}
+
+/// Bob pays with lamports from his main account
+fn exchange_with_lamports_ix(trade: &Trade) -> Instruction {
+    let mut ix = trade.exchange_ix();
+    ix.accounts[1] = AccountMeta::new(trade.bob.pubkey(), true);
+    ix.accounts[5] = AccountMeta::new(trade.alice.pubkey(), false);
+    ix.accounts[10] = AccountMeta::new(trade.treasury, false);
+    ix.accounts
+        .push(AccountMeta::new_readonly(system_program::id(), false));
+    ix
+}
+
+async fn start_expecting_lamports(trade: &Trade) -> ProgramTestContext {
+    let mut program_test = trade.program_test();
+    program_test.add_account(trade.bob.pubkey(), system_account(2 * LAMPORTS));
+    let mut ctx = program_test.start_with_context().await;
+
+    process_ix(
+        &mut ctx,
+        init_escrow_expecting_lamports_ix(trade),
+        &trade.alice,
+    )
+    .await
+    .expect("Init Escrow succeeds");
+    ctx
+}
+
+#[tokio::test]
+async fn exchange_lamports_for_tokens() {
+    let trade = Trade::new(5000, LAMPORTS);
+    let mut ctx = start_expecting_lamports(&trade).await;
+
+    let escrow = escrow_state(&mut ctx, trade.escrow).await.unwrap();
+    assert!(escrow.expects_lamports());
+
+    process_ix(&mut ctx, exchange_with_lamports_ix(&trade), &trade.bob)
+        .await
+        .expect("Exchange succeeds");
+
+    assert_eq!(token_balance(&mut ctx, trade.bob_x).await, 5000);
+    assert_eq!(lamports(&mut ctx, trade.bob.pubkey()).await, LAMPORTS);
+    assert!(!account_exists(&mut ctx, trade.escrow).await);
+    assert!(
+        lamports(&mut ctx, trade.alice.pubkey()).await > LAMPORTS,
+        "Alice receives the lamports and the rent"
+    );
+}
+
+#[tokio::test]
+async fn exchange_lamports_with_fee() {
+    let mut trade = Trade::new(5000, LAMPORTS);
+    trade.fee_basis_points = Some(250);
+    let mut ctx = start_expecting_lamports(&trade).await;
+
+    process_ix(&mut ctx, exchange_with_lamports_ix(&trade), &trade.bob)
+        .await
+        .expect("Exchange succeeds");
+
+    assert_eq!(
+        lamports(&mut ctx, trade.treasury).await,
+        LAMPORTS / 40,
+        "Treasury receives the fee"
+    );
+    assert_eq!(lamports(&mut ctx, trade.bob.pubkey()).await, LAMPORTS);
+}
@@ -0,0 +1,173 @@
+mod common;
+
+use common::{
+    account_exists, escrow_account, escrow_error, escrow_state, process_ix, token_balance, Trade,
+    MINT_DECIMALS,
+};
+use escrow::{error::EscrowError, instruction::EscrowInstruction, state::EscrowV0};
+use solana_program::{program_pack::Pack, pubkey::Pubkey};
+use solana_program_test::tokio;
+use solana_sdk::signature::Signer;
+use spl_token::state::Account as TokenAccount;
+
+#[tokio::test]
+async fn migrate_v0_escrow() {
+    let mut trade = Trade::new(5000, 1000);
+    let new_escrow = Pubkey::new_unique();
+    let mut program_test = trade.v0_program_test();
+    program_test.add_account(new_escrow, escrow_account(&trade.program_id));
+    let mut ctx = program_test.start_with_context().await;
+
+    process_ix(&mut ctx, trade.migrate_escrow_ix(&new_escrow), &trade.alice)
+        .await
+        .unwrap();
+
+    assert!(
+        !account_exists(&mut ctx, trade.escrow).await,
+        "The v0 escrow account is closed"
+    );
+
+    trade.escrow = new_escrow;
+    let (_, bump_seed) = Pubkey::find_program_address(
+        &[escrow::state::Escrow::PDA_SEED, new_escrow.as_ref()],
+        &trade.program_id,
+    );
+    let escrow = escrow_state(&mut ctx, new_escrow).await.unwrap();
+    assert!(escrow.is_initialized);
+    assert_eq!(escrow.initializer_pubkey, trade.alice.pubkey());
+    assert_eq!(escrow.tmp_token_account_pubkey, trade.alice_x_tmp);
+    assert_eq!(
+        escrow.initializer_token_to_receive_account_pubkey,
+        trade.alice_y
+    );
+    assert_eq!(escrow.expected_amount, 1000);
+    assert_eq!(escrow.mint_x_pubkey, trade.mint_x);
+    assert_eq!(escrow.mint_y_pubkey, trade.mint_y);
+    assert_eq!(escrow.mint_x_decimals, MINT_DECIMALS);
+    assert_eq!(escrow.mint_y_decimals, MINT_DECIMALS);
+    assert_eq!(escrow.bump_seed, bump_seed);
+
+    let tmp_account = ctx
+        .banks_client
+        .get_account(trade.alice_x_tmp)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        TokenAccount::unpack(&tmp_account.data).unwrap().owner,
+        trade.pda(),
+        "The new escrow's PDA owns the tmp token account"
+    );
+
+    trade
+        .exchange(&mut ctx)
+        .await
+        .expect("The migrated escrow can be taken");
+    assert_eq!(token_balance(&mut ctx, trade.bob_x).await, 5000);
+    assert_eq!(token_balance(&mut ctx, trade.alice_y).await, 1000);
+}
+
+#[tokio::test]
+async fn migrate_someone_elses_escrow() {
+    let trade = Trade::new(5000, 1000);
+    let new_escrow = Pubkey::new_unique();
+    let mut program_test = trade.v0_program_test();
+    program_test.add_account(new_escrow, escrow_account(&trade.program_id));
+    let mut ctx = program_test.start_with_context().await;
+
+    let mut ix = trade.migrate_escrow_ix(&new_escrow);
+    ix.accounts[0].pubkey = trade.bob.pubkey();
+    let err = process_ix(&mut ctx, ix, &trade.bob)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(escrow_error(err), Some(EscrowError::EscrowAccountMismatch));
+    assert!(account_exists(&mut ctx, trade.escrow).await);
+}
+
+#[tokio::test]
+async fn migrate_current_escrow() {
+    let trade = Trade::new(5000, 1000);
+    let new_escrow = Pubkey::new_unique();
+    let mut program_test = trade.initialized_program_test();
+    program_test.add_account(new_escrow, escrow_account(&trade.program_id));
+    let mut ctx = program_test.start_with_context().await;
+
+    let err = process_ix(&mut ctx, trade.migrate_escrow_ix(&new_escrow), &trade.alice)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        escrow_error(err),
+        Some(EscrowError::InvalidEscrowSize),
+        "Only v0 escrows need to be migrated"
+    );
+}
+
+#[tokio::test]
+async fn exchange_v0_escrow() {
+    let trade = Trade::new(5000, 1000);
+    let mut ctx = trade.v0_program_test().start_with_context().await;
+
+    // v0 escrows share a single PDA instead of one derived from the escrow
+    process_ix(&mut ctx, trade.exchange_v0_ix(), &trade.bob)
+        .await
+        .expect("v0 escrows can be taken without migrating them first");
+
+    assert_eq!(token_balance(&mut ctx, trade.bob_x).await, 5000);
+    assert_eq!(token_balance(&mut ctx, trade.alice_y).await, 1000);
+    assert!(!account_exists(&mut ctx, trade.alice_x_tmp).await);
+    assert!(!account_exists(&mut ctx, trade.escrow).await);
+}
+
+#[tokio::test]
+async fn partially_exchange_v0_escrow() {
+    let trade = Trade::new(5000, 1000);
+    let mut ctx = trade.v0_program_test().start_with_context().await;
+
+    let mut ix = trade.exchange_v0_ix();
+    ix.data = EscrowInstruction::PartialExchange {
+        amount: 400,
+        expected_amount: 2000,
+    }
+    .pack();
+    process_ix(&mut ctx, ix, &trade.bob).await.unwrap();
+
+    assert_eq!(token_balance(&mut ctx, trade.bob_x).await, 2000);
+    assert_eq!(token_balance(&mut ctx, trade.alice_x_tmp).await, 3000);
+    let escrow = ctx
+        .banks_client
+        .get_account(trade.escrow)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(escrow.data.len(), EscrowV0::LEN, "Stays in the v0 layout");
+    assert_eq!(EscrowV0::unpack(&escrow.data).unwrap().expected_amount, 600);
+}
+
+#[tokio::test]
+async fn cancel_v0_escrow() {
+    let trade = Trade::new(5000, 1000);
+    let mut ctx = trade.v0_program_test().start_with_context().await;
+
+    let mut ix = trade.cancel_escrow_ix();
+    ix.accounts[5].pubkey = trade.v0_pda();
+    process_ix(&mut ctx, ix, &trade.alice).await.unwrap();
+
+    assert_eq!(token_balance(&mut ctx, trade.alice_x).await, 5000);
+    assert!(!account_exists(&mut ctx, trade.alice_x_tmp).await);
+    assert!(!account_exists(&mut ctx, trade.escrow).await);
+}
+
+#[tokio::test]
+async fn exchange_v0_escrow_with_escrow_pda() {
+    let trade = Trade::new(5000, 1000);
+    let mut ctx = trade.v0_program_test().start_with_context().await;
+
+    let err = trade.exchange(&mut ctx).await.unwrap_err().unwrap();
+    assert_eq!(
+        escrow_error(err),
+        Some(EscrowError::InvalidPdaAccount),
+        "The tmp account of a v0 escrow is owned by the shared PDA"
+    );
+}
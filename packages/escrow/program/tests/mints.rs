@@ -0,0 +1,92 @@
+mod common;
+
+use common::{escrow_error, escrow_state, process_ix, token_account, Trade, MINT_DECIMALS};
+use escrow::error::EscrowError;
+use solana_program::{instruction::AccountMeta, pubkey::Pubkey};
+use solana_program_test::tokio;
+use solana_sdk::signature::Signer;
+
+#[tokio::test]
+async fn init_escrow_records_mints() {
+    let trade = Trade::new(5000, 1000);
+    let mut ctx = trade.program_test().start_with_context().await;
+
+    trade
+        .init_escrow(&mut ctx)
+        .await
+        .expect("Init Escrow succeeds");
+
+    let escrow = escrow_state(&mut ctx, trade.escrow).await.unwrap();
+    assert_eq!(escrow.mint_x_pubkey, trade.mint_x, "offered mint");
+    assert_eq!(escrow.mint_y_pubkey, trade.mint_y, "expected mint");
+    assert_eq!(
+        escrow.mint_x_decimals, MINT_DECIMALS,
+        "offered mint decimals"
+    );
+    assert_eq!(
+        escrow.mint_y_decimals, MINT_DECIMALS,
+        "expected mint decimals"
+    );
+}
+
+#[tokio::test]
+async fn init_escrow_with_swapped_mints() {
+    let trade = Trade::new(5000, 1000);
+    let mut ctx = trade.program_test().start_with_context().await;
+
+    let mut ix = trade.init_escrow_ix();
+    ix.accounts.swap(5, 6);
+    let err = process_ix(&mut ctx, ix, &trade.alice)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        escrow_error(err),
+        Some(EscrowError::MintMismatch),
+        "Mint accounts have to match Alice's token accounts"
+    );
+}
+
+#[tokio::test]
+async fn exchange_sending_other_mint() {
+    let trade = Trade::new(5000, 1000);
+    let mut program_test = trade.initialized_program_test();
+
+    // Bob's account of a worthless token that Alice's receiving account would reject anyways
+    let bob_z = Pubkey::new_unique();
+    program_test.add_account(
+        bob_z,
+        token_account(&Pubkey::new_unique(), &trade.bob.pubkey(), 1000),
+    );
+    let mut ctx = program_test.start_with_context().await;
+
+    let mut ix = trade.exchange_ix();
+    ix.accounts[1] = AccountMeta::new(bob_z, false);
+    let err = process_ix(&mut ctx, ix, &trade.bob)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        escrow_error(err),
+        Some(EscrowError::MintMismatch),
+        "Bob has to send token Y"
+    );
+}
+
+#[tokio::test]
+async fn exchange_receiving_other_mint() {
+    let trade = Trade::new(5000, 1000);
+    let mut ctx = trade.initialized_program_test().start_with_context().await;
+
+    let mut ix = trade.exchange_ix();
+    ix.accounts[2] = AccountMeta::new(trade.bob_y, false);
+    let err = process_ix(&mut ctx, ix, &trade.bob)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        escrow_error(err),
+        Some(EscrowError::MintMismatch),
+        "Bob has to receive token X"
+    );
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::{account_exists, escrow_error, escrow_state, process_ix, token_balance, Trade};
+use escrow::error::EscrowError;
+use solana_program_test::tokio;
+
+#[tokio::test]
+async fn partial_exchanges_until_filled() {
+    let trade = Trade::new(5000, 1000);
+    let mut ctx = trade.initialized_program_test().start_with_context().await;
+
+    // -----------------
+    // Bob fills a fifth of the offer
+    // -----------------
+    let ix = trade.partial_exchange_ix(200, 1000);
+    process_ix(&mut ctx, ix, &trade.bob)
+        .await
+        .expect("First partial exchange succeeds");
+
+    assert_eq!(token_balance(&mut ctx, trade.alice_y).await, 200);
+    assert_eq!(token_balance(&mut ctx, trade.bob_x).await, 1000);
+    assert_eq!(token_balance(&mut ctx, trade.alice_x_tmp).await, 4000);
+
+    let escrow = escrow_state(&mut ctx, trade.escrow)
+        .await
+        .expect("Escrow stays open");
+    assert_eq!(escrow.expected_amount, 800, "Escrow expects the remainder");
+
+    // -----------------
+    // Bob fills the remainder
+    // -----------------
+    let ix = trade.partial_exchange_ix(800, 4000);
+    process_ix(&mut ctx, ix, &trade.bob)
+        .await
+        .expect("Second partial exchange succeeds");
+
+    assert_eq!(token_balance(&mut ctx, trade.alice_y).await, 1000);
+    assert_eq!(token_balance(&mut ctx, trade.bob_x).await, 5000);
+
+    assert!(
+        !account_exists(&mut ctx, trade.alice_x_tmp).await,
+        "Tmp token account closed once filled"
+    );
+    assert!(
+        !account_exists(&mut ctx, trade.escrow).await,
+        "Escrow account closed once filled"
+    );
+}
+
+#[tokio::test]
+async fn partial_exchange_rejects_rounding_in_favor_of_taker() {
+    // 1 Y is worth 3.33 X
+    let trade = Trade::new(10, 3);
+    let mut ctx = trade.initialized_program_test().start_with_context().await;
+
+    let ix = trade.partial_exchange_ix(1, 4);
+    let err = process_ix(&mut ctx, ix, &trade.bob)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        escrow_error(err),
+        Some(EscrowError::ExpectedAmountMismatch),
+        "Bob cannot receive more than his share rounded down"
+    );
+
+    let ix = trade.partial_exchange_ix(1, 3);
+    process_ix(&mut ctx, ix, &trade.bob)
+        .await
+        .expect("Bob receives his share rounded down");
+    assert_eq!(token_balance(&mut ctx, trade.bob_x).await, 3);
+}